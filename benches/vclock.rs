@@ -0,0 +1,237 @@
+//! Benchmarks of `VClock` against the `BTreeMap` clock it replaced, on bare
+//! clocks and on `Orswot` and `Map` merges, where most clocks are the member
+//! clocks of one or two actors.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+
+use crdts::{Causal, CmRDT, CvRDT, Dot, Map, Orswot, VClock};
+
+/// The `BTreeMap` clock `VClock` used to be, kept as a baseline
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TreeClock {
+    dots: BTreeMap<u64, u64>,
+}
+
+impl TreeClock {
+    fn get(&self, actor: &u64) -> u64 {
+        self.dots.get(actor).cloned().unwrap_or(0)
+    }
+
+    fn apply_dot(&mut self, actor: u64, counter: u64) {
+        if self.get(&actor) < counter {
+            self.dots.insert(actor, counter);
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        for (actor, counter) in other.dots {
+            self.apply_dot(actor, counter);
+        }
+    }
+
+    fn forget(&mut self, other: &Self) {
+        for (actor, counter) in other.dots.iter() {
+            if *counter >= self.get(actor) {
+                self.dots.remove(actor);
+            }
+        }
+    }
+
+    fn intersection(left: &Self, right: &Self) -> Self {
+        let mut dots = BTreeMap::new();
+        for (actor, counter) in left.dots.iter() {
+            if right.get(actor) == *counter {
+                dots.insert(*actor, *counter);
+            }
+        }
+        TreeClock { dots }
+    }
+
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self == other {
+            Some(Ordering::Equal)
+        } else if other.dots.iter().all(|(w, c)| self.get(w) >= *c) {
+            Some(Ordering::Greater)
+        } else if self.dots.iter().all(|(w, c)| other.get(w) >= *c) {
+            Some(Ordering::Less)
+        } else {
+            None
+        }
+    }
+}
+
+// two clocks of `actors` actors, each ahead of the other on some actors
+fn clocks(actors: u64) -> ((VClock<u64>, VClock<u64>), (TreeClock, TreeClock)) {
+    let mut a = VClock::new();
+    let mut b = VClock::new();
+    for actor in 0..actors {
+        a.apply(Dot::new(actor, 10 + actor % 3));
+        b.apply(Dot::new(actor, 10 + actor % 2));
+    }
+    let tree = |clock: &VClock<u64>| TreeClock {
+        dots: clock.iter().map(|dot| (*dot.actor, dot.counter)).collect(),
+    };
+    let trees = (tree(&a), tree(&b));
+    ((a, b), trees)
+}
+
+fn bench_clocks(c: &mut Criterion) {
+    for &actors in &[2, 8, 64] {
+        let ((a, b), (ta, tb)) = clocks(actors);
+        let mut group = c.benchmark_group(format!("clock/{}-actors", actors));
+
+        group.bench_function("merge/vclock", |bench| {
+            bench.iter_batched(
+                || (a.clone(), b.clone()),
+                |(mut a, b)| {
+                    a.merge(b);
+                    a
+                },
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function("merge/btreemap", |bench| {
+            bench.iter_batched(
+                || (ta.clone(), tb.clone()),
+                |(mut a, b)| {
+                    a.merge(b);
+                    a
+                },
+                BatchSize::SmallInput,
+            )
+        });
+
+        group.bench_function("forget/vclock", |bench| {
+            bench.iter_batched(
+                || a.clone(),
+                |mut a| {
+                    a.forget(&b);
+                    a
+                },
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function("forget/btreemap", |bench| {
+            bench.iter_batched(
+                || ta.clone(),
+                |mut a| {
+                    a.forget(&tb);
+                    a
+                },
+                BatchSize::SmallInput,
+            )
+        });
+
+        group.bench_function("intersection/vclock", |bench| {
+            bench.iter(|| VClock::intersection(black_box(&a), black_box(&b)))
+        });
+        group.bench_function("intersection/btreemap", |bench| {
+            bench.iter(|| TreeClock::intersection(black_box(&ta), black_box(&tb)))
+        });
+
+        group.bench_function("partial_cmp/vclock", |bench| {
+            bench.iter(|| black_box(&a).partial_cmp(black_box(&b)))
+        });
+        group.bench_function("partial_cmp/btreemap", |bench| {
+            bench.iter(|| black_box(&ta).partial_cmp(black_box(&tb)))
+        });
+
+        group.bench_function("clone/vclock", |bench| bench.iter(|| black_box(&a).clone()));
+        group.bench_function("clone/btreemap", |bench| {
+            bench.iter(|| black_box(&ta).clone())
+        });
+
+        group.finish();
+    }
+}
+
+// a replica's set after `members` adds spread over `replicas` actors, with
+// every tenth member removed
+fn replica_set(actor: u64, replicas: u64, members: u64) -> Orswot<u64, u64> {
+    let mut set = Orswot::new();
+    for member in 0..members {
+        let ctx = set
+            .read()
+            .derive_add_ctx(actor * replicas + member % replicas);
+        set.apply(set.add(member, ctx));
+    }
+    for member in (0..members).step_by(10) {
+        let ctx = set.contains(&member).derive_rm_ctx();
+        set.apply(set.rm(member, ctx));
+    }
+    set
+}
+
+fn replica_map(actor: u64, keys: u64, members: u64) -> Map<u64, Orswot<u64, u64>, u64> {
+    let mut map = Map::new();
+    for i in 0..keys * members {
+        let ctx = map.len().derive_add_ctx(actor);
+        map.apply(map.update(i % keys, ctx, |set: &Orswot<u64, u64>, ctx| set.add(i, ctx)));
+    }
+    map
+}
+
+fn bench_workloads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("workload");
+
+    let a = replica_set(0, 2, 1000);
+    let b = replica_set(1, 2, 1000);
+    group.bench_function("orswot-merge", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(mut a, b)| {
+                a.merge(b);
+                a
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("orswot-clone", |bench| bench.iter(|| black_box(&a).clone()));
+    group.bench_function("orswot-add", |bench| {
+        bench.iter_batched(
+            || a.clone(),
+            |mut a| {
+                for member in 1000..1100 {
+                    let ctx = a.read().derive_add_ctx(member % 2);
+                    a.apply(a.add(member, ctx));
+                }
+                a
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    let a = replica_map(0, 50, 20);
+    let b = replica_map(1, 50, 20);
+    group.bench_function("map-merge", |bench| {
+        bench.iter_batched(
+            || (a.clone(), b.clone()),
+            |(mut a, b)| {
+                a.merge(b);
+                a
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("map-rm", |bench| {
+        bench.iter_batched(
+            || a.clone(),
+            |mut a| {
+                for key in 0..10 {
+                    let ctx = a.get(&key).derive_rm_ctx();
+                    a.apply(a.rm(key, ctx));
+                }
+                a
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_clocks, bench_workloads);
+criterion_main!(benches);
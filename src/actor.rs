@@ -0,0 +1,319 @@
+//! Random actor ids, and an allocator handing a replica a fresh one each time
+//! its process starts.
+//!
+//! Reusing an actor's dots corrupts every CRDT that sees both uses, and the
+//! usual way dots get reused is a replica restored from a stale backup
+//! picking up its old actor and counters. Instead of saving a counter next to
+//! the state, a process takes a fresh `ActorId` from its `ActorAllocator`
+//! when it starts and uses it until it exits. The state restored from a
+//! backup, however old, only ever holds dots of earlier incarnations, so the
+//! new actor's dots can't collide with them.
+//!
+//! The allocator records every id it hands out, `history` is what to retire
+//! once the replica's old incarnations are no longer needed, see
+//! `crate::retire`.
+//!
+//! ``` rust
+//! use crdts::actor::ActorAllocator;
+//! use crdts::{CmRDT, Orswot};
+//!
+//! let path = std::env::temp_dir().join("crdts-actor-doc");
+//! # let _ = std::fs::remove_file(&path);
+//! let actor = ActorAllocator::open(&path).unwrap().allocate().unwrap();
+//!
+//! let mut set = Orswot::new();
+//! set.apply(set.add("apple", set.read().derive_add_ctx(actor)));
+//!
+//! // after a restart the process writes as a new actor
+//! let restarted = ActorAllocator::open(&path).unwrap().allocate().unwrap();
+//! assert_ne!(restarted, actor);
+//! # std::fs::remove_file(&path).unwrap();
+//! ```
+
+use std::fmt::{self, Debug, Display};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+use crate::error::{Error, Result};
+
+// Every record is an id followed by a crc32 of it.
+const RECORD_LEN: usize = 20;
+
+/// A random 128 bit actor id.
+///
+/// Displayed as hex digits grouped like a UUID, the first group alone is
+/// usually enough to tell actors apart in logs and is what `Debug` shows.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ActorId([u8; 16]);
+
+impl ActorId {
+    /// A new random id, drawn from a generator seeded by the OS
+    pub fn random() -> Self {
+        ActorId(rand::random())
+    }
+
+    /// The id with the given bytes
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        ActorId(bytes)
+    }
+
+    /// The bytes of the id
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+impl Display for ActorId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i == 4 || i == 6 || i == 8 || i == 10 {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for ActorId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, ..] = self.0;
+        write!(f, "ActorId({:02x}{:02x}{:02x}{:02x})", a, b, c, d)
+    }
+}
+
+impl FromStr for ActorId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let digits: Vec<char> = s.chars().filter(|c| *c != '-').collect();
+        if digits.len() != 32 {
+            return Err(Error::ParseActor(s.to_string()));
+        }
+        let mut bytes = [0u8; 16];
+        for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+            let (hi, lo) = match (pair[0].to_digit(16), pair[1].to_digit(16)) {
+                (Some(hi), Some(lo)) => (hi, lo),
+                _ => return Err(Error::ParseActor(s.to_string())),
+            };
+            *byte = (hi * 16 + lo) as u8;
+        }
+        Ok(ActorId(bytes))
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl Arbitrary for ActorId {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut bytes = [0u8; 16];
+        g.fill_bytes(&mut bytes);
+        ActorId(bytes)
+    }
+}
+
+/// A file backed allocator of fresh `ActorId`'s.
+///
+/// Allocate one id per process start and use it for the life of the
+/// process. An id is on disk before `allocate` returns it. If we crash
+/// mid-write the torn record is dropped when the allocator is opened, the id
+/// in it was never handed out. A damaged record with intact records after it
+/// fails the open with `CorruptRecord` instead.
+#[derive(Debug)]
+pub struct ActorAllocator {
+    path: PathBuf,
+    file: File,
+    history: Vec<ActorId>,
+}
+
+impl ActorAllocator {
+    /// Open the allocator stored at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let records: Vec<Option<ActorId>> =
+            bytes.chunks_exact(RECORD_LEN).map(decode_record).collect();
+        let history: Vec<ActorId> = records.iter().map_while(|id| *id).collect();
+
+        // a torn write only ever damages the last record, if an intact record
+        // follows the damaged one it's corruption
+        if records[history.len()..].iter().any(Option::is_some) {
+            return Err(Error::CorruptRecord((history.len() * RECORD_LEN) as u64));
+        }
+
+        let valid_len = (history.len() * RECORD_LEN) as u64;
+        if valid_len < bytes.len() as u64 {
+            file.set_len(valid_len)?;
+            file.sync_all()?;
+        }
+
+        Ok(ActorAllocator {
+            path,
+            file,
+            history,
+        })
+    }
+
+    /// The file the allocator is stored in
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Allocate an id this allocator has never handed out. With 128 random
+    /// bits, no other allocator will hand it out either.
+    pub fn allocate(&mut self) -> Result<ActorId> {
+        let mut id = ActorId::random();
+        while self.history.contains(&id) {
+            id = ActorId::random();
+        }
+
+        let mut record = [0u8; RECORD_LEN];
+        record[..16].copy_from_slice(id.as_bytes());
+        record[16..].copy_from_slice(&crc32fast::hash(id.as_bytes()).to_le_bytes());
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+
+        self.history.push(id);
+        Ok(id)
+    }
+
+    /// Every id this allocator has handed out, oldest first
+    pub fn history(&self) -> &[ActorId] {
+        &self.history
+    }
+}
+
+fn decode_record(record: &[u8]) -> Option<ActorId> {
+    let (id, crc) = record.split_at(16);
+    if crc32fast::hash(id).to_le_bytes() != crc {
+        return None;
+    }
+    let mut id_bytes = [0u8; 16];
+    id_bytes.copy_from_slice(id);
+    Some(ActorId(id_bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fs;
+
+    use quickcheck::quickcheck;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("crdts-actor-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_display() {
+        let id = ActorId::from_bytes([
+            0x3f, 0x2a, 0x9c, 0x1e, 0x00, 0x01, 0x02, 0x03, 0xa0, 0xb0, 0xc0, 0xd0, 0xe0, 0xf0,
+            0x0a, 0x0b,
+        ]);
+        assert_eq!(id.to_string(), "3f2a9c1e-0001-0203-a0b0-c0d0e0f00a0b");
+        assert_eq!(format!("{:?}", id), "ActorId(3f2a9c1e)");
+        assert_eq!(
+            "3f2a9c1e00010203a0b0c0d0e0f00a0b"
+                .parse::<ActorId>()
+                .unwrap(),
+            id
+        );
+        assert!("3f2a9c1e".parse::<ActorId>().is_err());
+        assert!("3f2a9c1e-0001-0203-a0b0-c0d0e0f00a0g"
+            .parse::<ActorId>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_allocations_survive_reopen() {
+        let path = temp_path("reopen");
+        let mut allocator = ActorAllocator::open(&path).unwrap();
+        let first = allocator.allocate().unwrap();
+        let second = allocator.allocate().unwrap();
+        assert_ne!(first, second);
+        drop(allocator);
+
+        let mut allocator = ActorAllocator::open(&path).unwrap();
+        assert_eq!(allocator.history(), &[first, second]);
+        let third = allocator.allocate().unwrap();
+        assert_eq!(
+            ActorAllocator::open(&path).unwrap().history(),
+            &[first, second, third]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_record_is_dropped() {
+        let path = temp_path("torn");
+        let mut allocator = ActorAllocator::open(&path).unwrap();
+        let first = allocator.allocate().unwrap();
+        allocator.allocate().unwrap();
+        drop(allocator);
+
+        // chop the last byte off, as if we crashed mid-write
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(2 * RECORD_LEN as u64 - 1).unwrap();
+
+        let mut allocator = ActorAllocator::open(&path).unwrap();
+        assert_eq!(allocator.history(), &[first]);
+        let second = allocator.allocate().unwrap();
+        assert_eq!(
+            ActorAllocator::open(&path).unwrap().history(),
+            &[first, second]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_record_is_an_error() {
+        let path = temp_path("corrupt");
+        let mut allocator = ActorAllocator::open(&path).unwrap();
+        for _ in 0..3 {
+            allocator.allocate().unwrap();
+        }
+        drop(allocator);
+
+        // flip a bit in the second id
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[RECORD_LEN] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+
+        match ActorAllocator::open(&path) {
+            Err(Error::CorruptRecord(offset)) => assert_eq!(offset, RECORD_LEN as u64),
+            other => panic!("expected a corrupt record, got {:?}", other),
+        }
+        // nothing was truncated
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+
+        // damage to the last record alone is a torn write
+        bytes[RECORD_LEN] ^= 1;
+        bytes[2 * RECORD_LEN] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(ActorAllocator::open(&path).unwrap().history().len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    quickcheck! {
+        fn prop_display_parses_back(id: ActorId) -> bool {
+            id.to_string().parse::<ActorId>().unwrap() == id
+        }
+    }
+}
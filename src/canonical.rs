@@ -0,0 +1,27 @@
+//! Helpers for building canonical encodings, see `Canonical`.
+//!
+//! bincode writes a sequence (or a map) as its length followed by its
+//! elements, so we can build the encoding of a collection piece by piece
+//! and choose the order its elements are written in.
+
+use serde::Serialize;
+
+/// The bincode encoding of a value
+pub(crate) fn encode<T: Serialize + ?Sized>(val: &T) -> Vec<u8> {
+    bincode::serialize(val).expect("CRDT state always serializes")
+}
+
+/// Write a sequence of encoded elements in the order given
+pub(crate) fn write_seq(out: &mut Vec<u8>, elems: Vec<Vec<u8>>) {
+    out.extend(encode(&(elems.len() as u64)));
+    for elem in elems {
+        out.extend(elem);
+    }
+}
+
+/// Write a sequence of encoded elements in sorted order, for collections
+/// whose iteration order depends on how they were built
+pub(crate) fn write_sorted(out: &mut Vec<u8>, mut elems: Vec<Vec<u8>>) {
+    elems.sort();
+    write_seq(out, elems);
+}
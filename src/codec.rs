@@ -0,0 +1,505 @@
+//! A compact binary encoding for CRDT states and Op's.
+//!
+//! The serde encodings repeat every actor in full in every clock. Here each
+//! message carries a dictionary of the actors it mentions and refers to them
+//! by their position in it, counters are varints and the clocks nested in
+//! a CRDT are written as deltas from the CRDT's own clock.
+//!
+//! A message is laid out as:
+//!
+//! ```text
+//! [version: u8] [actor count: varint] [actor: bincode]* [body]
+//! ```
+//!
+//! Members, keys and values are written with bincode, prefixed by their length.
+//!
+//! Each actor is decoded once per message and every clock shares that copy.
+//! `from_bytes_interned` goes further and looks the actors up in an
+//! `ActorTable`, so the states and Op's it decodes share one copy of each
+//! actor across messages. It reads messages written from plain actors, an
+//! `Interned<A>` is encoded exactly as `A` is.
+//!
+//! ``` rust
+//! use crdts::{codec, CmRDT, Orswot};
+//!
+//! let mut set: Orswot<String, String> = Orswot::new();
+//! set.apply(set.add("apple".to_string(), set.read().derive_add_ctx("a-long-actor-id".to_string())));
+//!
+//! let bytes = codec::to_bytes(&set);
+//! assert!(bytes.len() < bincode::serialize(&set).unwrap().len());
+//! assert_eq!(codec::from_bytes::<String, Orswot<String, String>>(&bytes).unwrap(), set);
+//! ```
+
+use std::collections::BTreeMap;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::intern::{ActorTable, Interned};
+use crate::vclock::{Actor, Dot, VClock};
+
+/// The version of the format written by `to_bytes`
+pub const VERSION: u8 = 1;
+
+/// CRDT's and Op's with a compact encoding
+pub trait Compact<A: Actor>: Sized {
+    /// Write self to the encoder
+    fn encode(&self, enc: &mut Encoder<A>);
+
+    /// Read a value written by `encode`
+    fn decode(dec: &mut Decoder<A>) -> Result<Self>;
+}
+
+/// Encode a CRDT or Op as a complete message, header included
+pub fn to_bytes<A, T>(val: &T) -> Vec<u8>
+where
+    A: Actor + Serialize,
+    T: Compact<A>,
+{
+    let mut enc = Encoder {
+        actors: BTreeMap::new(),
+        dict: Vec::new(),
+        buf: Vec::new(),
+    };
+    val.encode(&mut enc);
+
+    let mut header: Encoder<A> = Encoder {
+        actors: BTreeMap::new(),
+        dict: Vec::new(),
+        buf: vec![VERSION],
+    };
+    header.u64(enc.dict.len() as u64);
+    for actor in enc.dict.iter() {
+        header.value(actor);
+    }
+    header.buf.extend(enc.buf);
+    header.buf
+}
+
+/// Decode a message written by `to_bytes`
+pub fn from_bytes<A, T>(bytes: &[u8]) -> Result<T>
+where
+    A: Actor + DeserializeOwned,
+    T: Compact<A>,
+{
+    decode_message(bytes, |actor| actor)
+}
+
+/// Decode a message written by `to_bytes`, taking its actors from `table`
+/// (adding those it hasn't seen) so they're shared with everything else
+/// decoded with the same table.
+pub fn from_bytes_interned<A, T>(bytes: &[u8], table: &mut ActorTable<A>) -> Result<T>
+where
+    A: Actor + DeserializeOwned,
+    T: Compact<Interned<A>>,
+{
+    decode_message(bytes, |actor| table.actor(&actor))
+}
+
+fn decode_message<A, T, F>(bytes: &[u8], mut intern: F) -> Result<T>
+where
+    A: Actor + DeserializeOwned,
+    T: Compact<A>,
+    F: FnMut(A) -> A,
+{
+    let mut dec = Decoder {
+        dict: Vec::new(),
+        bytes,
+    };
+    let version = dec.byte()?;
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let n = dec.seq_len()?;
+    for _ in 0..n {
+        let actor = dec.value()?;
+        dec.dict.push(intern(actor));
+    }
+
+    let val = T::decode(&mut dec)?;
+    if !dec.bytes.is_empty() {
+        return Err(Error::TrailingBytes);
+    }
+    Ok(val)
+}
+
+/// Writes the body of a message, collecting the actors it mentions
+#[derive(Debug)]
+pub struct Encoder<A: Actor> {
+    actors: BTreeMap<A, u64>,
+    dict: Vec<A>,
+    buf: Vec<u8>,
+}
+
+impl<A: Actor> Encoder<A> {
+    /// Write a single byte, e.g. the tag of an enum variant
+    pub fn byte(&mut self, byte: u8) {
+        self.buf.push(byte);
+    }
+
+    /// Write an unsigned varint
+    pub fn u64(&mut self, mut n: u64) {
+        while n >= 0x80 {
+            self.buf.push((n as u8) | 0x80);
+            n >>= 7;
+        }
+        self.buf.push(n as u8);
+    }
+
+    /// Write a signed varint, zigzag encoded so small magnitudes stay small
+    pub fn i64(&mut self, n: i64) {
+        self.u64(((n << 1) ^ (n >> 63)) as u64);
+    }
+
+    /// Write a member, key or value with bincode
+    pub fn value<T: Serialize + ?Sized>(&mut self, val: &T) {
+        let bytes = bincode::serialize(val).expect("values always serialize");
+        self.u64(bytes.len() as u64);
+        self.buf.extend(bytes);
+    }
+
+    /// Write an actor as its index in the dictionary
+    pub fn actor(&mut self, actor: &A) {
+        let index = self.intern(actor);
+        self.u64(index);
+    }
+
+    fn intern(&mut self, actor: &A) -> u64 {
+        if let Some(index) = self.actors.get(actor) {
+            return *index;
+        }
+        let index = self.dict.len() as u64;
+        self.actors.insert(actor.clone(), index);
+        self.dict.push(actor.clone());
+        index
+    }
+
+    /// Write a dot
+    pub fn dot(&mut self, dot: &Dot<A>) {
+        self.actor(&dot.actor);
+        self.u64(dot.counter);
+    }
+
+    /// Write a clock
+    pub fn clock(&mut self, clock: &VClock<A>) {
+        self.dots(clock, None);
+    }
+
+    /// Write a clock nested under `parent`, each counter is written as its
+    /// distance from the parent's counter, which is small when the nested
+    /// clock is close to the parent's.
+    pub fn clock_under(&mut self, clock: &VClock<A>, parent: &VClock<A>) {
+        self.dots(clock, Some(parent));
+    }
+
+    // Dots are written by increasing actor index, each index as the gap
+    // from the previous one.
+    fn dots(&mut self, clock: &VClock<A>, parent: Option<&VClock<A>>) {
+        let mut dots: Vec<(u64, u64)> = clock
+            .dots
+            .iter()
+            .map(|(actor, counter)| (self.intern(actor), *counter))
+            .collect();
+        dots.sort();
+
+        self.u64(dots.len() as u64);
+        let mut prev = 0;
+        for (index, counter) in dots {
+            self.u64(index - prev);
+            match parent {
+                Some(parent) => {
+                    let actor = self.dict[index as usize].clone();
+                    self.i64(parent.get(&actor).wrapping_sub(counter) as i64);
+                }
+                None => self.u64(counter),
+            }
+            prev = index;
+        }
+    }
+}
+
+/// Reads the body of a message
+#[derive(Debug)]
+pub struct Decoder<'a, A: Actor> {
+    dict: Vec<A>,
+    bytes: &'a [u8],
+}
+
+impl<'a, A: Actor> Decoder<'a, A> {
+    /// Read a single byte
+    pub fn byte(&mut self) -> Result<u8> {
+        let (byte, rest) = self.bytes.split_first().ok_or(Error::UnexpectedEnd)?;
+        self.bytes = rest;
+        Ok(*byte)
+    }
+
+    /// Read an unsigned varint
+    pub fn u64(&mut self) -> Result<u64> {
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u64::from(byte & 0x7f);
+            if shift == 63 && bits > 1 {
+                return Err(Error::VarintOverflow);
+            }
+            n |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(Error::VarintOverflow)
+    }
+
+    /// Read a signed varint
+    pub fn i64(&mut self) -> Result<i64> {
+        let n = self.u64()?;
+        Ok(((n >> 1) as i64) ^ -((n & 1) as i64))
+    }
+
+    /// Read the length of a sequence. Every element takes at least a byte,
+    /// so a length longer than the rest of the message is rejected before
+    /// we allocate for it.
+    pub fn seq_len(&mut self) -> Result<usize> {
+        let n = self.u64()?;
+        if n > self.bytes.len() as u64 {
+            return Err(Error::UnexpectedEnd);
+        }
+        Ok(n as usize)
+    }
+
+    /// Read a member, key or value
+    pub fn value<T: DeserializeOwned>(&mut self) -> Result<T> {
+        let n = self.seq_len()?;
+        let (bytes, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    /// Read an actor
+    pub fn actor(&mut self) -> Result<A> {
+        let index = self.u64()?;
+        self.dict
+            .get(index as usize)
+            .cloned()
+            .ok_or(Error::UnknownActor(index))
+    }
+
+    /// Read a dot
+    pub fn dot(&mut self) -> Result<Dot<A>> {
+        Ok(Dot::new(self.actor()?, self.u64()?))
+    }
+
+    /// Read a clock
+    pub fn clock(&mut self) -> Result<VClock<A>> {
+        self.dots(None)
+    }
+
+    /// Read a clock written with `Encoder::clock_under` the same parent
+    pub fn clock_under(&mut self, parent: &VClock<A>) -> Result<VClock<A>> {
+        self.dots(Some(parent))
+    }
+
+    fn dots(&mut self, parent: Option<&VClock<A>>) -> Result<VClock<A>> {
+        let n = self.seq_len()?;
+        let mut clock = VClock::new();
+        let mut index = 0u64;
+        for _ in 0..n {
+            index = index
+                .checked_add(self.u64()?)
+                .ok_or(Error::VarintOverflow)?;
+            let actor = self
+                .dict
+                .get(index as usize)
+                .cloned()
+                .ok_or(Error::UnknownActor(index))?;
+            let counter = match parent {
+                Some(parent) => parent.get(&actor).wrapping_sub(self.i64()? as u64),
+                None => self.u64()?,
+            };
+            if counter == 0 {
+                return Err(Error::InvalidCounter);
+            }
+            clock.dots.insert(actor, counter);
+        }
+        Ok(clock)
+    }
+}
+
+impl<A: Actor> Compact<A> for VClock<A> {
+    fn encode(&self, enc: &mut Encoder<A>) {
+        enc.clock(self)
+    }
+
+    fn decode(dec: &mut Decoder<A>) -> Result<Self> {
+        dec.clock()
+    }
+}
+
+impl<A: Actor> Compact<A> for Dot<A> {
+    fn encode(&self, enc: &mut Encoder<A>) {
+        enc.dot(self)
+    }
+
+    fn decode(dec: &mut Decoder<A>) -> Result<Self> {
+        dec.dot()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use quickcheck::quickcheck;
+
+    use crate::{map, mvreg, orswot, CmRDT, MVReg, Map, Orswot};
+
+    type TReg = MVReg<u8, u8>;
+    type TMap = Map<u8, TReg, u8>;
+
+    fn round_trips<T: Compact<u8> + PartialEq>(val: T) -> bool {
+        let bytes = to_bytes(&val);
+        // a truncated message is always rejected, never misread
+        (0..bytes.len()).all(|n| from_bytes::<u8, T>(&bytes[..n]).is_err())
+            && from_bytes::<u8, T>(&bytes)
+                .map(|decoded| decoded == val)
+                .unwrap_or(false)
+    }
+
+    #[test]
+    fn test_varints() {
+        let mut enc: Encoder<u8> = Encoder {
+            actors: BTreeMap::new(),
+            dict: Vec::new(),
+            buf: Vec::new(),
+        };
+        let unsigned = vec![0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX];
+        let signed = vec![0, -1, 1, -64, 64, i64::MIN, i64::MAX];
+        for n in unsigned.iter() {
+            enc.u64(*n);
+        }
+        for n in signed.iter() {
+            enc.i64(*n);
+        }
+        assert_eq!(enc.buf[..5], [0, 1, 127, 128, 1]);
+
+        let mut dec: Decoder<u8> = Decoder {
+            dict: Vec::new(),
+            bytes: &enc.buf,
+        };
+        for n in unsigned {
+            assert_eq!(dec.u64().unwrap(), n);
+        }
+        for n in signed {
+            assert_eq!(dec.i64().unwrap(), n);
+        }
+        assert!(dec.bytes.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_unknown_version_and_trailing_bytes() {
+        let clock: VClock<u8> = vec![Dot::new(1, 3), Dot::new(2, 1)].into_iter().collect();
+        let mut bytes = to_bytes(&clock);
+
+        bytes.push(0);
+        match from_bytes::<u8, VClock<u8>>(&bytes) {
+            Err(Error::TrailingBytes) => (),
+            other => panic!("expected trailing bytes, got {:?}", other),
+        }
+
+        bytes.pop();
+        bytes[0] = VERSION + 1;
+        match from_bytes::<u8, VClock<u8>>(&bytes) {
+            Err(Error::UnsupportedVersion(v)) => assert_eq!(v, VERSION + 1),
+            other => panic!("expected an unsupported version, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_actors_are_written_once() {
+        let actor = "an actor with a rather long id".to_string();
+        let mut map: Map<u8, MVReg<u8, String>, String> = Map::new();
+        for key in 0..20 {
+            let op = map.update(
+                key,
+                map.get(&key).derive_add_ctx(actor.clone()),
+                |reg, ctx| reg.write(key, ctx),
+            );
+            map.apply(op);
+        }
+
+        let bytes = to_bytes(&map);
+        let occurrences = bytes
+            .windows(actor.len())
+            .filter(|window| *window == actor.as_bytes())
+            .count();
+        assert_eq!(occurrences, 1);
+        assert!(bytes.len() * 5 < bincode::serialize(&map).unwrap().len());
+        assert_eq!(
+            from_bytes::<String, Map<u8, MVReg<u8, String>, String>>(&bytes).unwrap(),
+            map
+        );
+    }
+
+    #[test]
+    fn test_interned_decoding_shares_actors_across_messages() {
+        let actor = "an actor with a rather long id".to_string();
+        let mut set: Orswot<u8, String> = Orswot::new();
+        let mut ops = Vec::new();
+        for member in 0..3 {
+            let op = set.add(member, set.read().derive_add_ctx(actor.clone()));
+            set.apply(op.clone());
+            ops.push(to_bytes(&op));
+        }
+
+        // the sender's actors are plain Strings
+        let mut table = ActorTable::new();
+        let mut interned: Orswot<u8, Interned<String>> =
+            from_bytes_interned(&to_bytes(&set), &mut table).unwrap();
+        for bytes in ops {
+            let op: orswot::Op<u8, Interned<String>> =
+                from_bytes_interned(&bytes, &mut table).unwrap();
+            interned.apply(op);
+        }
+
+        assert_eq!(table.len(), 1);
+        let shared = table.get(&actor).unwrap();
+        for clock in interned.entries.values() {
+            for actor in clock.dots.keys() {
+                assert!(Interned::ptr_eq(actor, &shared));
+            }
+        }
+        let plain: Orswot<u8, String> = from_bytes(&to_bytes(&interned)).unwrap();
+        assert_eq!(plain, set);
+    }
+
+    quickcheck! {
+        fn prop_vclock_round_trips(clock: VClock<u8>) -> bool {
+            round_trips(clock)
+        }
+
+        fn prop_orswot_round_trips(set: Orswot<u8, u8>) -> bool {
+            round_trips(set)
+        }
+
+        fn prop_orswot_op_round_trips(op: orswot::Op<u8, u8>) -> bool {
+            round_trips(op)
+        }
+
+        fn prop_mvreg_round_trips(reg: TReg) -> bool {
+            round_trips(reg)
+        }
+
+        fn prop_mvreg_op_round_trips(op: mvreg::Op<u8, u8>) -> bool {
+            round_trips(op)
+        }
+
+        fn prop_map_round_trips(map: TMap) -> bool {
+            round_trips(map)
+        }
+
+        fn prop_map_op_round_trips(op: map::Op<u8, TReg, u8>) -> bool {
+            round_trips(op)
+        }
+    }
+}
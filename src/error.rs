@@ -1,10 +1,10 @@
-use std::{error, fmt, result};
+use std::{error, fmt, io, mem, result};
 
 /// CRDT Result alias to reduce redundency in function return types
 pub(crate) type Result<T> = result::Result<T, Error>;
 
 /// Possible CRDT error codes
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub enum Error {
     /// A conflicting change to a CRDT is witnessed by a dot that already exists.
     ///
@@ -12,17 +12,178 @@ pub enum Error {
     /// Instead, users must design their system in a way that will make these
     /// dot collisions unlikely / impossible.
     ConflictingMarker,
+
+    /// An Op depends on Op's that have not been applied yet, e.g. the dot
+    /// of the Op skips ahead of the actors last seen counter.
+    CausalGap,
+
+    /// An Op carries an empty clock, it would have no effect when applied.
+    EmptyClock,
+
+    /// The clock of a remove has seen more than the CRDT it's applied to.
+    RmClockAhead,
+
+    /// The state of a CRDT breaks one of its invariants, the message says which.
+    InvalidState(&'static str),
+
+    /// The actor making an Op is not permitted to by the policy it was
+    /// checked against.
+    PermissionDenied,
+
+    /// An actor was remapped twice, two actors were remapped to one id, or an
+    /// actor was remapped onto an actor the remapped value already holds.
+    DuplicateActor,
+
+    /// The state has counts of the retired actor the retire Op doesn't fold
+    /// into its successor.
+    RetiredActorAhead,
+
+    /// Some replicas haven't acknowledged the retirement, holds their number
+    Unacknowledged(usize),
+
+    /// The string is not 32 hex digits, optionally grouped by dashes
+    ParseActor(String),
+
+    /// The record at this offset is damaged but intact records follow it,
+    /// so it isn't a torn write and dropping the rest would lose data
+    CorruptRecord(u64),
+
+    /// The signature doesn't match the Op and the key it came with
+    BadSignature,
+
+    /// The signing key isn't bound to any actor
+    UnknownSigner,
+
+    /// The Op carries dots of an actor the signing key isn't bound to
+    WrongSigner,
+
+    /// The actor is already bound to another key
+    KeyConflict,
+
+    /// The snapshot failed its checksum, the log can't be recovered from it
+    CorruptSnapshot,
+
+    /// The remote peer sent a message out of order, e.g. Op's before its clock
+    UnexpectedMessage,
+
+    /// The remote peer is missing Op's that were trimmed from our log, it
+    /// has to catch up by merging our state instead
+    OpsTrimmed,
+
+    /// The message or riak_dt binary was written in a version of the format
+    /// or layout we can't read
+    UnsupportedVersion(u8),
+
+    /// The message or binary ended in the middle of a value
+    UnexpectedEnd,
+
+    /// A varint ran past 64 bits
+    VarintOverflow,
+
+    /// An actor index points past the end of the actor dictionary
+    UnknownActor(u64),
+
+    /// A tag does not name a variant of the type being decoded
+    InvalidTag(u8),
+
+    /// A clock nested in a CRDT has a counter that can't be recovered from
+    /// the CRDT's clock
+    InvalidCounter,
+
+    /// Bytes were left over after the message body or term
+    TrailingBytes,
+
+    /// There is no migration registered from this schema
+    NoMigration {
+        /// The schema the payload is in
+        from: String,
+        /// The schema we are loading
+        to: String,
+    },
+
+    /// The migrations led back to a schema they had already passed through
+    MigrationCycle(String),
+
+    /// The binary doesn't start with the ETF version byte
+    NotEtf(u8),
+
+    /// A kind of term riak_dt states are not built from, e.g. a float or a pid
+    UnsupportedTerm(u8),
+
+    /// An integer too large to read
+    IntegerOverflow,
+
+    /// A compressed term failed to inflate
+    Compression(io::Error),
+
+    /// The binary holds another riak_dt type
+    WrongTag {
+        /// The tag of the type being read
+        expected: u8,
+        /// The tag of the binary
+        found: u8,
+    },
+
+    /// The term doesn't have the shape we expected, describes what we expected
+    UnexpectedTerm(&'static str),
+
+    /// Tuples and lists are nested deeper than any riak_dt state
+    TooDeep,
+
+    /// The underlying file system failed
+    Io(io::Error),
+
+    /// Encoding or decoding failed
+    Encoding(bincode::Error),
 }
 
 impl error::Error for Error {
     fn description(&self) -> &str {
         match self {
             Error::ConflictingMarker => "Dot's are used exactly once for the lifetime of a CRDT",
+            Error::CausalGap => "The Op depends on Op's that have not been applied",
+            Error::EmptyClock => "The Op has an empty clock",
+            Error::RmClockAhead => "The remove clock is ahead of the CRDT's clock",
+            Error::InvalidState(msg) => msg,
+            Error::PermissionDenied => "The actor is not permitted to make this Op",
+            Error::DuplicateActor => "An actor is remapped twice or two actors share an id",
+            Error::RetiredActorAhead => "The retired actor has counts the retire Op doesn't fold",
+            Error::Unacknowledged(_) => "Some replicas have not acknowledged",
+            Error::ParseActor(_) => "Not an actor id",
+            Error::CorruptRecord(_) => "A record is corrupt",
+            Error::BadSignature => "The Op's signature is invalid",
+            Error::UnknownSigner => "The Op was signed by a key bound to no actor",
+            Error::WrongSigner => "The Op was not signed by the key of its actor",
+            Error::KeyConflict => "The actor is already bound to another key",
+            Error::CorruptSnapshot => "The snapshot failed its checksum",
+            Error::UnexpectedMessage => "The peer sent a message out of order",
+            Error::OpsTrimmed => "The Op's the peer is missing were trimmed from the log",
+            Error::UnsupportedVersion(_) => "Unsupported format version",
+            Error::UnexpectedEnd => "The data ended in the middle of a value",
+            Error::VarintOverflow => "A varint overflowed 64 bits",
+            Error::UnknownActor(_) => "An actor is not in the dictionary",
+            Error::InvalidTag(_) => "Invalid tag",
+            Error::InvalidCounter => "A nested clock has an invalid counter",
+            Error::TrailingBytes => "Bytes were left over after the value",
+            Error::NoMigration { .. } => "There is no migration from the schema",
+            Error::MigrationCycle(_) => "The migrations cycle",
+            Error::NotEtf(_) => "Not an ETF binary",
+            Error::UnsupportedTerm(_) => "Unsupported term",
+            Error::IntegerOverflow => "An integer is too large",
+            Error::Compression(_) => "Failed to inflate a term",
+            Error::WrongTag { .. } => "The binary holds another riak_dt type",
+            Error::UnexpectedTerm(_) => "The term has an unexpected shape",
+            Error::TooDeep => "Terms are nested deeper than any riak_dt state",
+            Error::Io(_) => "io error",
+            Error::Encoding(_) => "encoding error",
         }
     }
-    fn cause(&self) -> Option<&dyn error::Error> {
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
-            Error::ConflictingMarker => None,
+            Error::Compression(err) | Error::Io(err) => Some(err),
+            Error::Encoding(err) => Some(err),
+            _ => None,
         }
     }
 }
@@ -30,10 +191,77 @@ impl error::Error for Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Error::ConflictingMarker => {
+            Error::Unacknowledged(n) => write!(f, "{} replicas have not acknowledged", n),
+            Error::ParseActor(s) => write!(f, "Not an actor id: {:?}", s),
+            Error::CorruptRecord(offset) => write!(f, "The record at offset {} is corrupt", offset),
+            Error::UnsupportedVersion(v) => write!(f, "Unsupported format version {}", v),
+            Error::UnknownActor(i) => write!(f, "Actor {} is not in the dictionary", i),
+            Error::InvalidTag(tag) => write!(f, "Invalid tag {}", tag),
+            Error::NoMigration { from, to } => write!(f, "No migration from {} to {}", from, to),
+            Error::MigrationCycle(schema) => write!(f, "The migrations cycle through {}", schema),
+            Error::NotEtf(byte) => write!(f, "Not an ETF binary, starts with {}", byte),
+            Error::UnsupportedTerm(tag) => write!(f, "Unsupported term with ETF tag {}", tag),
+            Error::Compression(err) => write!(f, "Failed to inflate a term: {}", err),
+            Error::WrongTag { expected, found } => {
+                write!(f, "Expected riak_dt tag {}, found {}", expected, found)
+            }
+            Error::UnexpectedTerm(expected) => write!(f, "Expected {}", expected),
+            Error::Io(err) => write!(f, "io error: {}", err),
+            Error::Encoding(err) => write!(f, "encoding error: {}", err),
+            _ => {
                 use std::error::Error;
                 write!(f, "{}", self.description())
             }
         }
     }
 }
+
+// io and bincode errors can't be compared, they are equal when their kinds
+// and messages are.
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Error::InvalidState(a), Error::InvalidState(b)) => a == b,
+            (Error::Unacknowledged(a), Error::Unacknowledged(b)) => a == b,
+            (Error::ParseActor(a), Error::ParseActor(b)) => a == b,
+            (Error::CorruptRecord(a), Error::CorruptRecord(b)) => a == b,
+            (Error::UnsupportedVersion(a), Error::UnsupportedVersion(b)) => a == b,
+            (Error::UnknownActor(a), Error::UnknownActor(b)) => a == b,
+            (Error::InvalidTag(a), Error::InvalidTag(b)) => a == b,
+            (Error::NoMigration { from: a, to: b }, Error::NoMigration { from: c, to: d }) => {
+                (a, b) == (c, d)
+            }
+            (Error::MigrationCycle(a), Error::MigrationCycle(b)) => a == b,
+            (Error::NotEtf(a), Error::NotEtf(b)) => a == b,
+            (Error::UnsupportedTerm(a), Error::UnsupportedTerm(b)) => a == b,
+            (
+                Error::WrongTag {
+                    expected: a,
+                    found: b,
+                },
+                Error::WrongTag {
+                    expected: c,
+                    found: d,
+                },
+            ) => (a, b) == (c, d),
+            (Error::UnexpectedTerm(a), Error::UnexpectedTerm(b)) => a == b,
+            (Error::Compression(a), Error::Compression(b)) | (Error::Io(a), Error::Io(b)) => {
+                a.kind() == b.kind() && a.to_string() == b.to_string()
+            }
+            (Error::Encoding(a), Error::Encoding(b)) => a.to_string() == b.to_string(),
+            (a, b) => mem::discriminant(a) == mem::discriminant(b),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Self {
+        Error::Encoding(err)
+    }
+}
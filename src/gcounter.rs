@@ -1,7 +1,21 @@
 use num_bigint::BigUint;
 use serde::{Deserialize, Serialize};
 
-use crate::traits::{Causal, CmRDT, CvRDT};
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+use crate::canonical;
+#[cfg(any(test, feature = "quickcheck"))]
+use crate::ctx::AddCtx;
+use crate::error::Result;
+use crate::intern::{ActorTable, Intern, Interned};
+use crate::retire::{ActorRemap, Fold, Remap, RetireActor};
+use crate::riak::{Etf, RiakDt, Term};
+#[cfg(any(test, feature = "quickcheck"))]
+use crate::traits::ArbitraryOp;
+use crate::traits::{
+    Canonical, Causal, CmRDT, CvRDT, Lattice, Meet, TrackChanges, Validate, ValidateUnder, Witness,
+};
 use crate::vclock::{Actor, Dot, VClock};
 
 /// `GCounter` is a grow-only witnessed counter.
@@ -24,7 +38,7 @@ use crate::vclock::{Actor, Dot, VClock};
 /// ```
 #[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
 pub struct GCounter<A: Actor> {
-    inner: VClock<A>,
+    pub(crate) inner: VClock<A>,
 }
 
 impl<A: Actor> Default for GCounter<A> {
@@ -39,6 +53,16 @@ impl<A: Actor> CmRDT for GCounter<A> {
     fn apply(&mut self, op: Self::Op) {
         self.inner.apply(op)
     }
+
+    fn validate_op(&self, op: &Self::Op) -> Result<()> {
+        self.inner.validate_op(op)
+    }
+}
+
+impl<A: Actor> ValidateUnder<A> for GCounter<A> {
+    fn validate_op_under(&self, op: &Self::Op, clock: &VClock<A>) -> Result<()> {
+        clock.validate_dot(op)
+    }
 }
 
 impl<A: Actor> CvRDT for GCounter<A> {
@@ -47,10 +71,125 @@ impl<A: Actor> CvRDT for GCounter<A> {
     }
 }
 
+impl<A: Actor> Lattice for GCounter<A> {
+    fn leq(&self, other: &Self) -> bool {
+        self.inner.leq(&other.inner)
+    }
+
+    fn is_bottom(&self) -> bool {
+        self.inner.is_bottom()
+    }
+}
+
+impl<A: Actor> Meet for GCounter<A> {
+    fn meet(&mut self, other: &Self) {
+        self.inner.meet(&other.inner);
+    }
+}
+
 impl<A: Actor> Causal<A> for GCounter<A> {
     fn forget(&mut self, clock: &VClock<A>) {
         self.inner.forget(&clock);
     }
+
+    /// The counts in a `GCounter` are not dots, there are none to forget.
+    fn forget_dots(&mut self, _clock: &VClock<A>) {}
+}
+
+impl<A: Actor> Witness<A> for GCounter<A> {
+    /// The counts in a `GCounter` are not dots, so the whole count is
+    /// re-tagged as counted by the actor of the dot, the total is kept.
+    fn witness(&mut self, dot: &Dot<A>) {
+        let total = self
+            .inner
+            .iter()
+            .fold(0u64, |total, dot| total.saturating_add(dot.counter));
+        self.inner = VClock::new();
+        if total > 0 {
+            self.inner.apply(Dot::new(dot.actor.clone(), total));
+        }
+    }
+}
+
+impl<A: Actor + Serialize> Canonical for GCounter<A> {
+    fn canonical_bytes(&self) -> Vec<u8> {
+        canonical::encode(self)
+    }
+}
+
+impl<A: Actor> Intern<A> for GCounter<Interned<A>> {
+    fn intern(&mut self, table: &mut ActorTable<A>) {
+        self.inner.intern(table);
+    }
+}
+
+impl<A: Actor> RetireActor<A> for GCounter<A> {
+    type Op = Fold<A>;
+
+    fn retire(&self, retired: A, successor: A) -> Fold<A> {
+        Fold::new(&self.inner, retired, successor)
+    }
+
+    fn apply_retire(&mut self, op: Fold<A>) -> Result<()> {
+        op.apply(&mut self.inner)
+    }
+}
+
+impl<A: Actor> Remap<A> for GCounter<A> {
+    fn remap(&mut self, remap: &ActorRemap<A>) -> Result<()> {
+        self.inner.remap(remap)
+    }
+}
+
+/// riak_dt_gcounter's state is an orddict of actors to their counts
+impl<A: Actor + Etf> Etf for GCounter<A> {
+    fn to_term(&self) -> Term {
+        self.inner.to_term()
+    }
+
+    fn from_term(term: Term) -> Result<Self> {
+        Ok(GCounter {
+            inner: VClock::from_term(term)?,
+        })
+    }
+}
+
+impl<A: Actor + Etf> RiakDt for GCounter<A> {
+    const MODULE: &'static str = "riak_dt_gcounter";
+    const TAG: u8 = 70;
+    const VERSION: u8 = 1;
+}
+
+impl<A: Actor> Validate for GCounter<A> {
+    fn validate(&self) -> Result<()> {
+        self.inner.validate()
+    }
+}
+
+impl<A: Actor> TrackChanges for GCounter<A> {
+    /// The value of the counter before and after, if it changed
+    type Changes = Option<(BigUint, BigUint)>;
+
+    fn changes_since(&self, before: &Self) -> Self::Changes {
+        let (old, new) = (before.read(), self.read());
+        if old != new {
+            Some((old, new))
+        } else {
+            None
+        }
+    }
+
+    fn apply_with_changes(&mut self, op: Self::Op) -> Self::Changes {
+        let gain = op.counter.saturating_sub(self.inner.get(&op.actor));
+        self.apply(op);
+        self.gained(gain.into())
+    }
+
+    fn merge_with_changes(&mut self, other: Self) -> Self::Changes {
+        let gain = self.gain(&other.inner);
+        self.merge(other);
+        self.gained(gain)
+    }
 }
 
 impl<A: Actor> GCounter<A> {
@@ -70,6 +209,44 @@ impl<A: Actor> GCounter<A> {
     pub fn read(&self) -> BigUint {
         self.inner.iter().map(|dot| dot.counter).sum()
     }
+
+    /// How much merging in the given counts would add to this counter
+    pub(crate) fn gain(&self, counts: &VClock<A>) -> BigUint {
+        counts
+            .iter()
+            .map(|dot| BigUint::from(dot.counter.saturating_sub(self.inner.get(dot.actor))))
+            .sum()
+    }
+
+    /// The value before and after we gained `gain`, if we gained anything
+    fn gained(&self, gain: BigUint) -> Option<(BigUint, BigUint)> {
+        if gain == BigUint::default() {
+            None
+        } else {
+            let new = self.read();
+            Some((&new - gain, new))
+        }
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Actor> ArbitraryOp<A> for GCounter<A> {
+    /// Increments the counter by the actor of the given context.
+    fn arbitrary_op<G: Gen>(&self, ctx: AddCtx<A>, _g: &mut G) -> Dot<A> {
+        self.inc(ctx.dot.actor)
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Actor + Arbitrary> Arbitrary for GCounter<A> {
+    /// Builds a GCounter from a sequence of increments by arbitrary actors.
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut counter = GCounter::new();
+        for actor in Vec::<A>::arbitrary(g) {
+            counter.apply(counter.inc(actor));
+        }
+        counter
+    }
 }
 
 #[cfg(test)]
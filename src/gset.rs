@@ -3,7 +3,8 @@ use std::fmt::Debug;
 
 use serde::{Deserialize, Serialize};
 
-use crate::traits::{CmRDT, CvRDT};
+use crate::canonical;
+use crate::traits::{Canonical, CmRDT, CvRDT, Lattice, Meet};
 
 /// A `GSet` is a grow-only set.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -36,6 +37,36 @@ impl<T: Ord + Clone> CvRDT for GSet<T> {
     }
 }
 
+impl<T: Ord + Clone> Lattice for GSet<T> {
+    fn leq(&self, other: &Self) -> bool {
+        self.value.is_subset(&other.value)
+    }
+
+    fn is_bottom(&self) -> bool {
+        self.value.is_empty()
+    }
+}
+
+impl<T: Ord + Clone> Meet for GSet<T> {
+    /// Keeps the elements in both sets.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use crdts::{GSet, Meet};
+    /// let (mut a, mut b) = (GSet::new(), GSet::new());
+    /// a.insert(1);
+    /// a.insert(2);
+    /// b.insert(2);
+    /// a.meet(&b);
+    /// assert!(!a.contains(&1));
+    /// assert!(a.contains(&2));
+    /// ```
+    fn meet(&mut self, other: &Self) {
+        self.value.retain(|e| other.value.contains(e));
+    }
+}
+
 impl<T: Ord + Debug> CmRDT for GSet<T> {
     type Op = T;
 
@@ -44,6 +75,12 @@ impl<T: Ord + Debug> CmRDT for GSet<T> {
     }
 }
 
+impl<T: Ord + Serialize> Canonical for GSet<T> {
+    fn canonical_bytes(&self) -> Vec<u8> {
+        canonical::encode(self)
+    }
+}
+
 impl<T: Ord> GSet<T> {
     /// Instantiates an empty `GSet`.
     pub fn new() -> Self {
@@ -0,0 +1,329 @@
+//! Interned actors, so large states keep a single copy of each actor id.
+//!
+//! Every clock in a state holds its own clone of the actors it mentions, with
+//! `String` or UUID actors a large `Orswot` or `Map` spends most of its memory
+//! on copies of the same few ids. An `Interned` actor is a shared pointer to
+//! its id, cloning it doesn't copy the id, and an `ActorTable` hands out one
+//! pointer per distinct actor. `Intern::intern` points every actor of a state
+//! or Op at the table's copy, e.g. after deserializing a state or before
+//! applying an Op received from another replica. States and Op's read with
+//! `codec::from_bytes_interned` are interned as they are decoded.
+//!
+//! `Interned<A>` orders, hashes and serializes exactly as `A` does, so a
+//! state's API and serialized form are the same with either actor type.
+//!
+//! ``` rust
+//! use crdts::intern::{ActorTable, Intern, Interned};
+//! use crdts::{CmRDT, Orswot};
+//!
+//! let mut table = ActorTable::new();
+//! let mut set: Orswot<u8, Interned<String>> = Orswot::new();
+//! for member in 0..10 {
+//!     let actor = Interned::new("laptop-f1d7".to_string());
+//!     set.apply(set.add(member, set.read().derive_add_ctx(actor)));
+//! }
+//! set.intern(&mut table);
+//!
+//! // the table, the set clock, the ten member clocks and `actor` share one
+//! // copy of the actor id
+//! assert_eq!(table.len(), 1);
+//! let actor = table.get(&"laptop-f1d7".to_string()).unwrap();
+//! assert_eq!(Interned::copies(&actor), 1 + 1 + 10 + 1);
+//! ```
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt::{self, Debug, Display};
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+use crate::vclock::{Actor, Dot, VClock};
+
+/// An actor id behind a shared pointer, see `ActorTable`
+pub struct Interned<A>(Arc<A>);
+
+impl<A> Interned<A> {
+    /// Wrap an actor id, it's not shared with other copies of the id until it
+    /// is interned.
+    pub fn new(actor: A) -> Self {
+        Interned(Arc::new(actor))
+    }
+
+    /// The number of `Interned` values sharing this copy of the id,
+    /// including the one held by an `ActorTable`.
+    pub fn copies(this: &Self) -> usize {
+        Arc::strong_count(&this.0)
+    }
+
+    /// Whether both point to the same copy of the id
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(&this.0, &other.0)
+    }
+}
+
+impl<A> Clone for Interned<A> {
+    fn clone(&self) -> Self {
+        Interned(Arc::clone(&self.0))
+    }
+}
+
+impl<A> Deref for Interned<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.0
+    }
+}
+
+impl<A> Borrow<A> for Interned<A> {
+    fn borrow(&self) -> &A {
+        &self.0
+    }
+}
+
+impl<A> From<A> for Interned<A> {
+    fn from(actor: A) -> Self {
+        Interned::new(actor)
+    }
+}
+
+impl<A: PartialEq> PartialEq for Interned<A> {
+    fn eq(&self, other: &Self) -> bool {
+        Interned::ptr_eq(self, other) || self.0 == other.0
+    }
+}
+
+impl<A: Eq> Eq for Interned<A> {}
+
+impl<A: PartialOrd> PartialOrd for Interned<A> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.0.as_ref().partial_cmp(other.0.as_ref())
+    }
+}
+
+impl<A: Ord> Ord for Interned<A> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.as_ref().cmp(other.0.as_ref())
+    }
+}
+
+impl<A: Hash> Hash for Interned<A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_ref().hash(state)
+    }
+}
+
+impl<A: Debug> Debug for Interned<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.as_ref().fmt(f)
+    }
+}
+
+impl<A: Display> Display for Interned<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.as_ref().fmt(f)
+    }
+}
+
+impl<A: Serialize> Serialize for Interned<A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.as_ref().serialize(serializer)
+    }
+}
+
+impl<'de, A: Deserialize<'de>> Deserialize<'de> for Interned<A> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        A::deserialize(deserializer).map(Interned::new)
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Arbitrary + Sync> Arbitrary for Interned<A> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Interned::new(A::arbitrary(g))
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        Box::new(self.0.as_ref().shrink().map(Interned::new))
+    }
+}
+
+/// A table of interned actors, holding one copy of each actor id
+#[derive(Debug, Clone)]
+pub struct ActorTable<A: Actor> {
+    actors: HashSet<Interned<A>>,
+}
+
+impl<A: Actor> Default for ActorTable<A> {
+    fn default() -> Self {
+        ActorTable::new()
+    }
+}
+
+impl<A: Actor> ActorTable<A> {
+    /// An empty table
+    pub fn new() -> Self {
+        ActorTable {
+            actors: HashSet::new(),
+        }
+    }
+
+    /// The number of actors in the table
+    pub fn len(&self) -> usize {
+        self.actors.len()
+    }
+
+    /// Whether the table is empty
+    pub fn is_empty(&self) -> bool {
+        self.actors.is_empty()
+    }
+
+    /// The table's copy of an actor, if it has one
+    pub fn get(&self, actor: &A) -> Option<Interned<A>> {
+        self.actors.get(actor).cloned()
+    }
+
+    /// The table's copy of an actor, adding the actor if the table hasn't
+    /// seen it yet.
+    pub fn actor(&mut self, actor: &Interned<A>) -> Interned<A> {
+        match self.actors.get(actor) {
+            Some(interned) => interned.clone(),
+            None => {
+                self.actors.insert(actor.clone());
+                actor.clone()
+            }
+        }
+    }
+
+    /// Drop the actors no longer used outside the table
+    pub fn purge(&mut self) {
+        self.actors.retain(|actor| Interned::copies(actor) > 1);
+    }
+}
+
+/// States and Op's whose actors can be interned
+pub trait Intern<A: Actor> {
+    /// Point every actor at its copy in `table`
+    fn intern(&mut self, table: &mut ActorTable<A>);
+}
+
+impl<A: Actor> Intern<A> for VClock<Interned<A>> {
+    fn intern(&mut self, table: &mut ActorTable<A>) {
+        self.dots = mem::take(&mut self.dots)
+            .into_iter()
+            .map(|(actor, counter)| (table.actor(&actor), counter))
+            .collect();
+    }
+}
+
+impl<A: Actor> Intern<A> for Dot<Interned<A>> {
+    fn intern(&mut self, table: &mut ActorTable<A>) {
+        self.actor = table.actor(&self.actor);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use quickcheck::quickcheck;
+
+    use crate::{map, Canonical, CmRDT, Map, Orswot};
+
+    type TActor = Interned<String>;
+    type TSet = Orswot<u8, TActor>;
+    type TMap = Map<u8, TSet, TActor>;
+
+    // an op as it arrives from another replica, with its own copies of actors
+    fn received<T: Serialize + serde::de::DeserializeOwned>(op: T) -> T {
+        bincode::deserialize(&bincode::serialize(&op).unwrap()).unwrap()
+    }
+
+    fn build_map(table: &mut ActorTable<String>) -> TMap {
+        let mut map = TMap::new();
+        for i in 0..30u8 {
+            let actor = Interned::new(format!("replica-{}", i % 3));
+            let ctx = map.len().derive_add_ctx(actor);
+            let mut op = received(map.update(i % 5, ctx, |set, ctx| set.add(i, ctx)));
+            op.intern(table);
+            map.apply(op);
+        }
+        map
+    }
+
+    #[test]
+    fn test_states_share_one_copy_of_each_actor() {
+        let mut table = ActorTable::new();
+        let mut map = build_map(&mut table);
+        assert_eq!(table.len(), 3);
+
+        // a deserialized state has its own copies until interned
+        map = received(map);
+        let set = map.get(&0).val.unwrap();
+        let actor = set.clock.dots.keys().next().unwrap();
+        // held by the map's clock and by the clock of the clone `get` returned
+        assert_eq!(Interned::copies(actor), 2);
+
+        map.intern(&mut table);
+        let set = map.get(&0).val.unwrap();
+        for dots in set.entries.values() {
+            for actor in dots.dots.keys() {
+                assert!(Interned::ptr_eq(actor, &table.get(actor).unwrap()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_purge_drops_unused_actors() {
+        let mut table = ActorTable::new();
+        let map = build_map(&mut table);
+        table.actor(&Interned::new("retired".to_string()));
+        assert_eq!(table.len(), 4);
+
+        table.purge();
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.get(&"retired".to_string()), None);
+
+        drop(map);
+        table.purge();
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_serialized_like_the_plain_actor() {
+        let mut plain: Orswot<u8, String> = Orswot::new();
+        let mut interned: TSet = Orswot::new();
+        for i in 0..4 {
+            let actor = format!("replica-{}", i % 2);
+            plain.apply(plain.add(i, plain.read().derive_add_ctx(actor.clone())));
+            interned.apply(interned.add(i, interned.read().derive_add_ctx(actor.into())));
+        }
+
+        assert_eq!(interned.canonical_bytes(), plain.canonical_bytes());
+        let bytes = bincode::serialize(&plain).unwrap();
+        assert_eq!(bincode::deserialize::<TSet>(&bytes).unwrap(), interned);
+    }
+
+    quickcheck! {
+        fn prop_intern_preserves_state(map: Map<u8, Orswot<u8, Interned<u8>>, Interned<u8>>) -> bool {
+            let mut interned = map.clone();
+            interned.intern(&mut ActorTable::new());
+            interned == map
+        }
+
+        fn prop_intern_preserves_op(op: map::Op<u8, Orswot<u8, Interned<u8>>, Interned<u8>>) -> bool {
+            let mut interned = op.clone();
+            interned.intern(&mut ActorTable::new());
+            interned == op
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use crate::traits::{CmRDT, CvRDT, Lattice, Meet};
+
+/// Merge must be associative: `a ⊔ (b ⊔ c) == (a ⊔ b) ⊔ c`
+pub fn merge_is_associative<T: CvRDT + Clone + PartialEq>(a: T, b: T, c: T) -> bool {
+    let mut a_bc = a.clone();
+    let mut bc = b.clone();
+    bc.merge(c.clone());
+    a_bc.merge(bc);
+
+    let mut ab_c = a;
+    ab_c.merge(b);
+    ab_c.merge(c);
+
+    a_bc == ab_c
+}
+
+/// Merge must be commutative: `a ⊔ b == b ⊔ a`
+pub fn merge_is_commutative<T: CvRDT + Clone + PartialEq>(a: T, b: T) -> bool {
+    let mut ab = a.clone();
+    ab.merge(b.clone());
+
+    let mut ba = b;
+    ba.merge(a);
+
+    ab == ba
+}
+
+/// Merge must be idempotent: `a ⊔ a == a`
+pub fn merge_is_idempotent<T: CvRDT + Clone + PartialEq>(a: T) -> bool {
+    let mut aa = a.clone();
+    aa.merge(a.clone());
+
+    aa == a
+}
+
+/// Concurrent Op's must commute, applying them in either order gives the same state.
+///
+/// Op's from the same actor need only be applied in the order they were
+/// produced, so only pass in Op's from different actors.
+pub fn ops_commute<T>(state: T, op1: T::Op, op2: T::Op) -> bool
+where
+    T: CmRDT + Clone + PartialEq,
+    T::Op: Clone,
+{
+    let mut state_12 = state.clone();
+    state_12.apply(op1.clone());
+    state_12.apply(op2.clone());
+
+    let mut state_21 = state;
+    state_21.apply(op2);
+    state_21.apply(op1);
+
+    state_12 == state_21
+}
+
+/// Replicating an Op must have the same effect as replicating the state it produced.
+///
+/// `op` is produced at `origin` and shipped to `replica`, which has seen
+/// everything `origin` has seen (we merge `origin` into it first). Applying
+/// the Op at the replica must be the same as merging the origin after it
+/// applied the Op.
+pub fn op_is_same_as_merge<T>(origin: T, replica: T, op: T::Op) -> bool
+where
+    T: CvRDT + CmRDT + Clone + PartialEq,
+    T::Op: Clone,
+{
+    let mut replica = replica;
+    replica.merge(origin.clone());
+
+    let mut by_op = replica.clone();
+    by_op.apply(op.clone());
+
+    let mut origin = origin;
+    origin.apply(op);
+    let mut by_merge = replica;
+    by_merge.merge(origin);
+
+    by_op == by_merge
+}
+
+/// `leq` must agree with merge: `a <= b` exactly when `b ⊔ a == b`
+pub fn leq_is_merge_noop<T: Lattice + Clone + PartialEq>(a: T, b: T) -> bool {
+    let mut ba = b.clone();
+    ba.merge(a.clone());
+
+    a.leq(&b) == (ba == b)
+}
+
+/// A join must be above both sides: `a <= a ⊔ b` and `b <= a ⊔ b`
+pub fn join_is_upper_bound<T: Lattice + Clone>(a: T, b: T) -> bool {
+    let ab = a.clone().join(b.clone());
+
+    a.leq(&a) && a.leq(&ab) && b.leq(&ab)
+}
+
+/// A meet must be below both sides: `a ⊓ b <= a` and `a ⊓ b <= b`
+pub fn meet_is_lower_bound<T: Meet + Clone>(a: T, b: T) -> bool {
+    let mut ab = a.clone();
+    ab.meet(&b);
+
+    ab.leq(&a) && ab.leq(&b)
+}
+
+/// The default state must be the bottom, below every other state
+pub fn bottom_is_least<T: Lattice + Default>(a: T) -> bool {
+    let bottom = T::default();
+
+    bottom.is_bottom() && bottom.leq(&a)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use quickcheck::quickcheck;
+
+    use crate::tvclock::TVClock;
+    use crate::{Dot, GCounter, GSet, MVReg, Map, Orswot, PNCounter};
+
+    type TOrswot = Orswot<u8, u8>;
+    type TReg = MVReg<u8, u8>;
+    type TMap = Map<u8, TReg, u8>;
+    type Prims = Vec<(u8, u8, u8, u8)>;
+
+    const REPLICAS: u8 = 3;
+
+    /// The states of replicas `0..REPLICAS` after replaying the primitives
+    /// `(replica, choice, x, y)`, like test/reference.rs does. Choices 0 and 1
+    /// are edits the replica makes as its own actor, 2 merges the state of
+    /// replica `x` and 3 delivers the ops replica `x` has seen. The replicas
+    /// share actors, but no two edits share a dot.
+    fn replay<T, F>(prims: Prims, edit: F) -> Vec<T>
+    where
+        T: CmRDT + CvRDT + Default + Clone,
+        T::Op: Clone,
+        F: Fn(&T, u8, bool, u8, u8) -> T::Op,
+    {
+        let mut ops: Vec<T::Op> = Vec::new();
+        let mut replicas = vec![(T::default(), BTreeSet::new()); REPLICAS as usize];
+        for (replica, choice, x, y) in prims {
+            let actor = replica % REPLICAS;
+            let to = actor as usize;
+            let from = (x % REPLICAS) as usize;
+            match choice % 4 {
+                0 | 1 => {
+                    let (state, seen) = &mut replicas[to];
+                    let op = edit(state, actor, choice % 4 == 0, x, y);
+                    state.apply(op.clone());
+                    seen.insert(ops.len());
+                    ops.push(op);
+                }
+                2 => {
+                    let (other, other_seen) = replicas[from].clone();
+                    let (state, seen) = &mut replicas[to];
+                    state.merge(other);
+                    seen.extend(other_seen);
+                }
+                _ => {
+                    // ops only depend on ops made before them, so applying
+                    // in the order they were made respects happens-before
+                    let other_seen = replicas[from].1.clone();
+                    let (state, seen) = &mut replicas[to];
+                    for id in other_seen.difference(&seen.clone()) {
+                        state.apply(ops[*id].clone());
+                        seen.insert(*id);
+                    }
+                }
+            }
+        }
+        replicas.into_iter().map(|(state, _)| state).collect()
+    }
+
+    // An entry remembers the dots removed from it by actors still editing
+    // it. How far back it remembers depends on the order edits arrived in,
+    // the dots past that were never in the entry, so they're left out when
+    // comparing. Everything else of the Map is compared.
+    #[derive(Debug, Clone, Default)]
+    struct Settled(TMap);
+
+    impl PartialEq for Settled {
+        fn eq(&self, other: &Self) -> bool {
+            self.0.without_removed() == other.0.without_removed()
+        }
+    }
+
+    impl CvRDT for Settled {
+        fn merge(&mut self, other: Self) {
+            self.0.merge(other.0)
+        }
+    }
+
+    impl CmRDT for Settled {
+        type Op = crate::map::Op<u8, TReg, u8>;
+
+        fn apply(&mut self, op: Self::Op) {
+            self.0.apply(op)
+        }
+    }
+
+    impl Lattice for Settled {
+        fn leq(&self, other: &Self) -> bool {
+            self.0.leq(&other.0)
+        }
+
+        fn is_bottom(&self) -> bool {
+            self.0.is_bottom()
+        }
+    }
+
+    fn orswot_op(set: &TOrswot, actor: u8, member: u8, is_add: bool) -> crate::orswot::Op<u8, u8> {
+        if is_add {
+            set.add(member, set.read().derive_add_ctx(actor))
+        } else {
+            set.rm(member, set.contains(&member).derive_rm_ctx())
+        }
+    }
+
+    fn orswot_edit(
+        set: &TOrswot,
+        actor: u8,
+        is_add: bool,
+        member: u8,
+        _: u8,
+    ) -> crate::orswot::Op<u8, u8> {
+        orswot_op(set, actor, member % 8, is_add)
+    }
+
+    fn map_op(map: &TMap, actor: u8, key: u8, val: Option<u8>) -> crate::map::Op<u8, TReg, u8> {
+        match val {
+            Some(val) => map.update(key, map.get(&key).derive_add_ctx(actor), |reg, ctx| {
+                reg.write(val, ctx)
+            }),
+            None => map.rm(key, map.get(&key).derive_rm_ctx()),
+        }
+    }
+
+    fn map_edit(
+        map: &Settled,
+        actor: u8,
+        is_write: bool,
+        key: u8,
+        val: u8,
+    ) -> crate::map::Op<u8, TReg, u8> {
+        map_op(&map.0, actor, key % 4, Some(val).filter(|_| is_write))
+    }
+
+    fn mvreg_edit(reg: &TReg, actor: u8, _: bool, val: u8, _: u8) -> crate::mvreg::Op<u8, u8> {
+        reg.write(val, reg.read().derive_add_ctx(actor))
+    }
+
+    fn pncounter_edit(
+        counter: &PNCounter<u8>,
+        actor: u8,
+        is_inc: bool,
+        _: u8,
+        _: u8,
+    ) -> crate::pncounter::Op<u8> {
+        if is_inc {
+            counter.inc(actor)
+        } else {
+            counter.dec(actor)
+        }
+    }
+
+    fn gcounter_edit(counter: &GCounter<u8>, actor: u8, _: bool, _: u8, _: u8) -> Dot<u8> {
+        counter.inc(actor)
+    }
+
+    /// Three states of one history and the merge laws between them
+    fn merge_laws<T, F>(prims: Prims, edit: F) -> bool
+    where
+        T: CmRDT + CvRDT + Default + Clone + PartialEq,
+        T::Op: Clone,
+        F: Fn(&T, u8, bool, u8, u8) -> T::Op,
+    {
+        let states = replay(prims, edit);
+        let (a, b, c) = (states[0].clone(), states[1].clone(), states[2].clone());
+        merge_is_associative(a.clone(), b.clone(), c)
+            && merge_is_commutative(a.clone(), b)
+            && merge_is_idempotent(a)
+    }
+
+    /// Replicas 1 and 2 each make an edit, the ops are applied in either
+    /// order to a state that has seen both replicas.
+    fn ops_commute_in<T, F>(
+        prims: Prims,
+        edit1: (bool, u8, u8),
+        edit2: (bool, u8, u8),
+        edit: F,
+    ) -> bool
+    where
+        T: CmRDT + CvRDT + Default + Clone + PartialEq,
+        T::Op: Clone,
+        F: Fn(&T, u8, bool, u8, u8) -> T::Op,
+    {
+        let states = replay(prims, &edit);
+        let op1 = edit(&states[1], 1, edit1.0, edit1.1, edit1.2);
+        let op2 = edit(&states[2], 2, edit2.0, edit2.1, edit2.2);
+        let mut state = states[1].clone();
+        state.merge(states[2].clone());
+        ops_commute(state, op1, op2)
+    }
+
+    /// Replica 0 makes an edit, which is shipped to replica 1.
+    fn op_is_same_as_merge_in<T, F>(prims: Prims, op: (bool, u8, u8), edit: F) -> bool
+    where
+        T: CmRDT + CvRDT + Default + Clone + PartialEq,
+        T::Op: Clone,
+        F: Fn(&T, u8, bool, u8, u8) -> T::Op,
+    {
+        let states = replay(prims, &edit);
+        let op = edit(&states[0], 0, op.0, op.1, op.2);
+        op_is_same_as_merge(states[0].clone(), states[1].clone(), op)
+    }
+
+    /// Two states of one history, one often ahead of the other, and the
+    /// laws `leq` and `join` must keep between them
+    fn lattice_laws<T, F>(prims: Prims, edit: F) -> bool
+    where
+        T: CmRDT + Lattice + Default + Clone + PartialEq,
+        T::Op: Clone,
+        F: Fn(&T, u8, bool, u8, u8) -> T::Op,
+    {
+        let states = replay(prims, edit);
+        let (a, b) = (states[0].clone(), states[1].clone());
+        leq_is_merge_noop(a.clone(), b.clone())
+            && leq_is_merge_noop(b.clone(), a.clone())
+            && leq_is_merge_noop(a.clone(), a.clone().join(b.clone()))
+            && join_is_upper_bound(a.clone(), b)
+            && bottom_is_least(a)
+    }
+
+    #[test]
+    fn test_map_lattice_laws_over_a_remove() {
+        // 1 writes a key, 0 removes it while 1 and 2 overwrite it
+        let prims = vec![
+            (88, 0, 90, 0),
+            (0, 2, 4, 0),
+            (47, 2, 0, 0),
+            (34, 0, 26, 0),
+            (65, 0, 14, 0),
+            (22, 2, 5, 0),
+            (0, 5, 90, 0),
+        ];
+        assert!(lattice_laws(prims, map_edit));
+    }
+
+    quickcheck! {
+        fn prop_orswot_merge_laws(prims: Prims) -> bool {
+            merge_laws(prims, orswot_edit)
+        }
+
+        fn prop_map_merge_laws(prims: Prims) -> bool {
+            merge_laws(prims, map_edit)
+        }
+
+        fn prop_mvreg_merge_laws(prims: Prims) -> bool {
+            merge_laws(prims, mvreg_edit)
+        }
+
+        fn prop_counter_merge_laws(pn_prims: Prims, g_prims: Prims) -> bool {
+            merge_laws(pn_prims, pncounter_edit) && merge_laws(g_prims, gcounter_edit)
+        }
+
+        fn prop_orswot_ops_commute(prims: Prims, op1: (bool, u8, u8), op2: (bool, u8, u8)) -> bool {
+            ops_commute_in(prims, op1, op2, orswot_edit)
+        }
+
+        fn prop_map_ops_commute(prims: Prims, op1: (bool, u8, u8), op2: (bool, u8, u8)) -> bool {
+            ops_commute_in(prims, op1, op2, map_edit)
+        }
+
+        fn prop_mvreg_ops_commute(prims: Prims, op1: (bool, u8, u8), op2: (bool, u8, u8)) -> bool {
+            ops_commute_in(prims, op1, op2, mvreg_edit)
+        }
+
+        fn prop_orswot_op_is_same_as_merge(prims: Prims, op: (bool, u8, u8)) -> bool {
+            op_is_same_as_merge_in(prims, op, orswot_edit)
+        }
+
+        fn prop_map_op_is_same_as_merge(prims: Prims, op: (bool, u8, u8)) -> bool {
+            op_is_same_as_merge_in(prims, op, map_edit)
+        }
+
+        fn prop_mvreg_op_is_same_as_merge(prims: Prims, op: (bool, u8, u8)) -> bool {
+            op_is_same_as_merge_in(prims, op, mvreg_edit)
+        }
+
+        fn prop_pncounter_op_is_same_as_merge(prims: Prims, op: (bool, u8, u8)) -> bool {
+            op_is_same_as_merge_in(prims, op, pncounter_edit)
+        }
+
+        fn prop_orswot_lattice_laws(prims: Prims) -> bool {
+            lattice_laws(prims, orswot_edit)
+        }
+
+        fn prop_map_lattice_laws(prims: Prims) -> bool {
+            lattice_laws(prims, map_edit)
+        }
+
+        fn prop_mvreg_lattice_laws(prims: Prims) -> bool {
+            lattice_laws(prims, mvreg_edit)
+        }
+
+        fn prop_counter_lattice_laws(pn_prims: Prims, g_prims: Prims) -> bool {
+            let pn = replay(pn_prims.clone(), pncounter_edit);
+            let g = replay(g_prims.clone(), gcounter_edit);
+            lattice_laws(pn_prims, pncounter_edit)
+                && lattice_laws(g_prims, gcounter_edit)
+                && meet_is_lower_bound(pn[0].clone(), pn[1].clone())
+                && meet_is_lower_bound(g[0].clone(), g[1].clone())
+        }
+
+        fn prop_set_lattice_laws(a: Vec<u8>, b: Vec<u8>) -> bool {
+            let gset = |elems: Vec<u8>| {
+                let mut set = GSet::new();
+                elems.into_iter().for_each(|e| set.insert(e));
+                set
+            };
+            let (a, b) = (gset(a), gset(b));
+            leq_is_merge_noop(a.clone(), b.clone())
+                && join_is_upper_bound(a.clone(), b.clone())
+                && meet_is_lower_bound(a.clone(), b)
+                && bottom_is_least(a)
+        }
+
+        fn prop_tvclock_lattice_laws(a: TVClock<u8>, b: TVClock<u8>) -> bool {
+            leq_is_merge_noop(a.clone(), b.clone())
+                && join_is_upper_bound(a.clone(), b)
+                && bottom_is_least(a)
+        }
+    }
+}
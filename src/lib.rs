@@ -13,8 +13,15 @@
 mod error;
 pub use crate::error::Error;
 
+mod canonical;
+
 mod traits;
-pub use crate::traits::{Causal, CmRDT, CvRDT, FunkyCmRDT, FunkyCvRDT};
+#[cfg(any(test, feature = "quickcheck"))]
+pub use crate::traits::ArbitraryOp;
+pub use crate::traits::{
+    Canonical, Causal, CmRDT, CvRDT, FunkyCmRDT, FunkyCvRDT, Lattice, Meet, TrackChanges, Validate,
+    ValidateUnder, Witness,
+};
 
 /// This module contains a Last-Write-Wins Register.
 pub mod lwwreg;
@@ -24,6 +31,13 @@ pub mod mvreg;
 
 pub mod vclock;
 
+/// This module contains random actor ids and a persistent allocator for them.
+#[cfg(feature = "actor")]
+pub mod actor;
+
+/// This module contains a Vector Clock with per-actor timestamps for pruning.
+pub mod tvclock;
+
 /// This module contains an Observed-Remove Set With Out Tombstones.
 pub mod orswot;
 
@@ -42,6 +56,54 @@ pub mod map;
 /// This module contains context for editing a CRDT.
 pub mod ctx;
 
+/// This module contains a replica that owns its actor and applies its own edits.
+pub mod replica;
+
+/// This module contains a file backed log of Op's with snapshots.
+pub mod oplog;
+
+/// This module contains an anti-entropy protocol for syncing two replicas.
+pub mod sync;
+
+/// This module contains a Merkle index for finding where two Maps diverge.
+pub mod merkle;
+
+/// This module contains a compact binary encoding for CRDT's and their Op's.
+pub mod codec;
+
+/// This module contains versioned envelopes and migrations for persisted CRDT's.
+pub mod versioned;
+
+/// This module contains interoperability with riak_dt's binary formats.
+pub mod riak;
+
+/// This module contains interned actors for memory efficient states.
+pub mod intern;
+
+/// This module contains actor retirement and replica id reassignment.
+pub mod retire;
+
+/// This module contains a wrapper that validates a CRDT as it's deserialized.
+pub mod validated;
+
+/// This module contains signed Op's and CRDT's that only apply Op's signed by
+/// their author.
+#[cfg(feature = "signed")]
+pub mod signed;
+
+/// This module contains per-actor permission policies for Op's.
+pub mod policy;
+
+/// This module contains checks for the laws every CRDT must obey.
+///
+/// Pair these with the `Arbitrary` impls behind the `quickcheck` feature to
+/// property test your own CRDT's.
+pub mod laws;
+
+/// This module contains a deterministic network simulator for testing that
+/// replicas converge under partitions, duplication and reordering.
+pub mod sim;
+
 // Top-level re-exports for CRDT structures.
 pub use crate::{
     gcounter::GCounter,
@@ -2,8 +2,10 @@ use std::fmt::Debug;
 
 use serde::{Deserialize, Serialize};
 
+use crate::canonical;
 use crate::error::{self, Error, Result};
-use crate::traits::{FunkyCmRDT, FunkyCvRDT};
+use crate::riak::{self, Etf, RiakDt, Term};
+use crate::traits::{Canonical, FunkyCmRDT, FunkyCvRDT};
 
 /// Trait bound alias for lwwreg vals
 pub trait Val: Debug + Clone + PartialEq {}
@@ -64,6 +66,33 @@ impl<V: Val, M: Marker> FunkyCmRDT for LWWReg<V, M> {
     }
 }
 
+impl<V: Val + Serialize, M: Marker + Serialize> Canonical for LWWReg<V, M> {
+    fn canonical_bytes(&self) -> Vec<u8> {
+        canonical::encode(self)
+    }
+}
+
+/// riak_dt_lwwreg's state is a `{Value, Timestamp}` pair
+impl<V: Val + Etf, M: Marker + Etf> Etf for LWWReg<V, M> {
+    fn to_term(&self) -> Term {
+        Term::Tuple(vec![self.val.to_term(), self.marker.to_term()])
+    }
+
+    fn from_term(term: Term) -> Result<Self> {
+        let (val, marker) = riak::tuple2(term, "a {Value, Timestamp} register")?;
+        Ok(LWWReg {
+            val: V::from_term(val)?,
+            marker: M::from_term(marker)?,
+        })
+    }
+}
+
+impl<V: Val + Etf, M: Marker + Etf> RiakDt for LWWReg<V, M> {
+    const MODULE: &'static str = "riak_dt_lwwreg";
+    const TAG: u8 = 72;
+    const VERSION: u8 = 1;
+}
+
 impl<V: Val, M: Marker> LWWReg<V, M> {
     /// Updates value witnessed by the given marker.
     /// An Err is returned if the given marker is exactly
@@ -1,13 +1,28 @@
-use std::collections::HashMap;
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
+use std::iter::once;
 use std::mem;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+use crate::canonical;
+use crate::codec::{Compact, Decoder, Encoder};
 use crate::ctx::{AddCtx, ReadCtx, RmCtx};
-use crate::traits::{Causal, CmRDT, CvRDT};
+use crate::error::{Error, Result};
+use crate::intern::{ActorTable, Intern, Interned};
+use crate::retire::{ActorRemap, Remap};
+use crate::riak::{self, Etf, RiakDt, Term};
+#[cfg(any(test, feature = "quickcheck"))]
+use crate::traits::ArbitraryOp;
+use crate::traits::{
+    Canonical, Causal, CmRDT, CvRDT, Lattice, TrackChanges, Validate, ValidateUnder, Witness,
+};
 use crate::vclock::{Actor, Dot, VClock};
 
 /// Key Trait alias to reduce redundancy in type decl.
@@ -15,12 +30,15 @@ pub trait Key: Debug + Ord + Clone {}
 impl<T: Debug + Ord + Clone> Key for T {}
 
 /// Val Trait alias to reduce redundancy in type decl.
-pub trait Val<A: Actor>: Debug + Default + Clone + Causal<A> + CmRDT + CvRDT {}
+pub trait Val<A: Actor>:
+    Debug + Default + Clone + Causal<A> + CmRDT + CvRDT + ValidateUnder<A>
+{
+}
 
 impl<A, T> Val<A> for T
 where
     A: Actor,
-    T: Debug + Default + Clone + Causal<A> + CmRDT + CvRDT,
+    T: Debug + Default + Clone + Causal<A> + CmRDT + CvRDT + ValidateUnder<A>,
 {
 }
 
@@ -47,10 +65,39 @@ struct Entry<V: Val<A>, A: Actor> {
     // The entry clock tells us which actors edited this entry.
     clock: VClock<A>,
 
+    // The dots removed from this entry by actors that have edited it since,
+    // always behind the entry clock. These dots are gone from the nested
+    // CRDT, a stale Op or state may bring them back and we forget them again.
+    // Entries serialized before we tracked removed dots have none.
+    #[serde(default = "VClock::new")]
+    removed: VClock<A>,
+
     // The nested CRDT
     val: V,
 }
 
+/// The keys added to, removed from and updated in a Map, see `TrackChanges`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changes<K: Key, C> {
+    /// Keys that were added
+    pub added: BTreeSet<K>,
+    /// Keys that were removed
+    pub removed: BTreeSet<K>,
+    /// Keys that were present before and after, but whose entry was edited,
+    /// along with the changes to the nested CRDT
+    pub updated: BTreeMap<K, C>,
+}
+
+/// The entries an applied Op touched, as they were before it was applied,
+/// see `Map::apply_with_undo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Undo<K: Key, V, A: Actor> {
+    // the dot of the Op, empty for removes
+    op_clock: VClock<A>,
+    // the values under the keys the Op changed, None if there was no entry
+    before: BTreeMap<K, Option<V>>,
+}
+
 /// Operations which can be applied to the Map CRDT
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Op<K: Key, V: Val<A>, A: Actor> {
@@ -70,17 +117,94 @@ pub enum Op<K: Key, V: Val<A>, A: Actor> {
         /// The operation to apply on the value under `key`
         op: V::Op,
     },
+    /// Move an entry to another key in the map
+    Mv {
+        /// Actors version at the time of the move
+        dot: Dot<A>,
+        /// Key of the entry to move
+        from: K,
+        /// The clock of the `from` entry seen by the move
+        from_clock: VClock<A>,
+        /// Key to move the entry to
+        to: K,
+        /// The clock of the `to` entry seen by the move
+        to_clock: VClock<A>,
+        /// The moved value, re-witnessed under `dot`
+        val: V,
+    },
+}
+
+// The entries an apply or merge has changed, as they were before it first
+// changed them, see `TrackChanges`.
+type Touched<K, V, A> = BTreeMap<K, Option<Entry<V, A>>>;
+
+fn touch<K: Key, V: Val<A>, A: Actor>(
+    touched: &mut Option<&mut Touched<K, V, A>>,
+    key: &K,
+    entry: impl FnOnce() -> Option<Entry<V, A>>,
+) {
+    if let Some(touched) = touched.as_deref_mut() {
+        if !touched.contains_key(key) {
+            touched.insert(key.clone(), entry());
+        }
+    }
 }
 
 impl<V: Val<A>, A: Actor> Default for Entry<V, A> {
     fn default() -> Self {
         Self {
             clock: VClock::default(),
+            removed: VClock::default(),
             val: V::default(),
         }
     }
 }
 
+impl<V: Val<A>, A: Actor> Entry<V, A> {
+    /// The dots removed from this entry by a Map that has seen `clock`.
+    ///
+    /// The Map has removed every dot it has seen of an actor missing from the
+    /// entry clock, along with the dots we remember removing from the actors
+    /// still in it.
+    fn removed_under(&self, clock: &VClock<A>) -> VClock<A> {
+        let mut removed = clock.clone_without_actors(&self.clock);
+        removed.merge(self.removed.clone());
+        removed
+    }
+
+    /// Whether forgetting the clock drops any dot of the entry clock
+    fn forgets(&self, clock: &VClock<A>) -> bool {
+        self.clock
+            .iter()
+            .any(|dot| clock.get(dot.actor) >= dot.counter)
+    }
+
+    /// Forget removed dots from the nested CRDT and remember those of the
+    /// actors still in the entry clock.
+    fn forget_removed(&mut self, removed: &VClock<A>) {
+        forget_removed_from(&mut self.val, &self.clock, removed);
+        self.removed.merge(removed.clone());
+        self.removed.retain_actors(&self.clock);
+    }
+}
+
+/// Forget the removed dots from a value nested under `entry_clock`.
+///
+/// An actor with no edit left in the entry has had every edit it made to
+/// the value removed, so the value forgets it entirely. For the actors still
+/// in the entry we only know which of the Map's dots were removed, a value
+/// that isn't built from them has nothing to forget, see `forget_dots`.
+fn forget_removed_from<V: Val<A>, A: Actor>(
+    val: &mut V,
+    entry_clock: &VClock<A>,
+    removed: &VClock<A>,
+) {
+    val.forget(&removed.clone_without_actors(entry_clock));
+    let mut edited = removed.clone();
+    edited.retain_actors(entry_clock);
+    val.forget_dots(&edited);
+}
+
 impl<K: Key, V: Val<A>, A: Actor> Default for Map<K, V, A> {
     fn default() -> Self {
         Map::new()
@@ -93,10 +217,10 @@ impl<K: Key, V: Val<A>, A: Actor> Causal<A> for Map<K, V, A> {
             .into_iter()
             .filter_map(|(key, mut entry)| {
                 entry.clock.forget(&clock);
-                entry.val.forget(&clock);
                 if entry.clock.is_empty() {
                     None // remove this entry since its been forgotten
                 } else {
+                    entry.forget_removed(clock);
                     Some((key, entry))
                 }
             })
@@ -122,47 +246,707 @@ impl<K: Key, V: Val<A>, A: Actor> CmRDT for Map<K, V, A> {
     type Op = Op<K, V, A>;
 
     fn apply(&mut self, op: Self::Op) {
+        self.apply_tracked(op, None)
+    }
+
+    fn validate_op(&self, op: &Self::Op) -> Result<()> {
+        self.validate_op_under(op, &self.clock)
+    }
+}
+
+impl<K: Key, V: Val<A>, A: Actor> ValidateUnder<A> for Map<K, V, A> {
+    fn validate_op_under(&self, op: &Self::Op, clock: &VClock<A>) -> Result<()> {
+        match op {
+            Op::Rm {
+                clock: rm_clock, ..
+            } => clock.validate_rm_clock(rm_clock),
+            Op::Up { dot, key, op } => {
+                self.validate_dot(dot, key, clock)?;
+                if clock.get(&dot.actor) >= dot.counter {
+                    // we've seen this op already, applying it is a no-op
+                    return Ok(());
+                }
+
+                let default_val;
+                let val = match self.entries.get(key) {
+                    Some(entry) => &entry.val,
+                    None => {
+                        default_val = V::default();
+                        &default_val
+                    }
+                };
+
+                // Nested CRDT's only see the dots of the Op's applied to them,
+                // so we check the nested Op against the dots the Map has seen.
+                val.validate_op_under(op, clock)
+            }
+            Op::Mv {
+                dot,
+                from_clock,
+                to,
+                to_clock,
+                ..
+            } => {
+                self.validate_dot(dot, to, clock)?;
+                for rm_clock in [from_clock, to_clock] {
+                    if !rm_clock.is_empty() {
+                        clock.validate_rm_clock(rm_clock)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<K, V, A> Compact<A> for Map<K, V, A>
+where
+    K: Key + Serialize + DeserializeOwned,
+    V: Val<A> + Compact<A>,
+    A: Actor,
+{
+    fn encode(&self, enc: &mut Encoder<A>) {
+        enc.clock(&self.clock);
+        enc.u64(self.entries.len() as u64);
+        for (key, entry) in self.entries.iter() {
+            enc.value(key);
+            enc.clock_under(&entry.clock, &self.clock);
+            enc.clock_under(&entry.removed, &entry.clock);
+            entry.val.encode(enc);
+        }
+        enc.u64(self.deferred.len() as u64);
+        for (clock, keys) in self.deferred.iter() {
+            enc.clock_under(clock, &self.clock);
+            enc.value(keys);
+        }
+    }
+
+    fn decode(dec: &mut Decoder<A>) -> Result<Self> {
+        let clock = dec.clock()?;
+        let mut entries = BTreeMap::new();
+        for _ in 0..dec.seq_len()? {
+            let key = dec.value()?;
+            let entry_clock = dec.clock_under(&clock)?;
+            let entry = Entry {
+                removed: dec.clock_under(&entry_clock)?,
+                clock: entry_clock,
+                val: V::decode(dec)?,
+            };
+            entries.insert(key, entry);
+        }
+        let mut deferred = HashMap::new();
+        for _ in 0..dec.seq_len()? {
+            let rm_clock = dec.clock_under(&clock)?;
+            deferred.insert(rm_clock, dec.value()?);
+        }
+        Ok(Map {
+            clock,
+            entries,
+            deferred,
+        })
+    }
+}
+
+impl<K, V, A> Compact<A> for Op<K, V, A>
+where
+    K: Key + Serialize + DeserializeOwned,
+    V: Val<A> + Compact<A>,
+    V::Op: Compact<A>,
+    A: Actor,
+{
+    fn encode(&self, enc: &mut Encoder<A>) {
+        match self {
+            Op::Rm { clock, keyset } => {
+                enc.byte(0);
+                enc.clock(clock);
+                enc.value(keyset);
+            }
+            Op::Up { dot, key, op } => {
+                enc.byte(1);
+                enc.dot(dot);
+                enc.value(key);
+                op.encode(enc);
+            }
+            Op::Mv {
+                dot,
+                from,
+                from_clock,
+                to,
+                to_clock,
+                val,
+            } => {
+                enc.byte(2);
+                enc.dot(dot);
+                enc.value(from);
+                enc.clock(from_clock);
+                enc.value(to);
+                enc.clock(to_clock);
+                val.encode(enc);
+            }
+        }
+    }
+
+    fn decode(dec: &mut Decoder<A>) -> Result<Self> {
+        match dec.byte()? {
+            0 => Ok(Op::Rm {
+                clock: dec.clock()?,
+                keyset: dec.value()?,
+            }),
+            1 => Ok(Op::Up {
+                dot: dec.dot()?,
+                key: dec.value()?,
+                op: V::Op::decode(dec)?,
+            }),
+            2 => Ok(Op::Mv {
+                dot: dec.dot()?,
+                from: dec.value()?,
+                from_clock: dec.clock()?,
+                to: dec.value()?,
+                to_clock: dec.clock()?,
+                val: V::decode(dec)?,
+            }),
+            tag => Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<K, V, A> Intern<A> for Map<K, V, Interned<A>>
+where
+    K: Key,
+    V: Val<Interned<A>> + Intern<A>,
+    A: Actor,
+{
+    fn intern(&mut self, table: &mut ActorTable<A>) {
+        self.clock.intern(table);
+        for entry in self.entries.values_mut() {
+            entry.clock.intern(table);
+            entry.removed.intern(table);
+            entry.val.intern(table);
+        }
+        self.deferred = mem::take(&mut self.deferred)
+            .into_iter()
+            .map(|(mut clock, keys)| {
+                clock.intern(table);
+                (clock, keys)
+            })
+            .collect();
+    }
+}
+
+impl<K, V, A> Intern<A> for Op<K, V, Interned<A>>
+where
+    K: Key,
+    V: Val<Interned<A>> + Intern<A>,
+    V::Op: Intern<A>,
+    A: Actor,
+{
+    fn intern(&mut self, table: &mut ActorTable<A>) {
+        match self {
+            Op::Rm { clock, .. } => clock.intern(table),
+            Op::Up { dot, op, .. } => {
+                dot.intern(table);
+                op.intern(table);
+            }
+            Op::Mv {
+                dot,
+                from_clock,
+                to_clock,
+                val,
+                ..
+            } => {
+                dot.intern(table);
+                from_clock.intern(table);
+                to_clock.intern(table);
+                val.intern(table);
+            }
+        }
+    }
+}
+
+impl<K, V, A> Remap<A> for Map<K, V, A>
+where
+    K: Key,
+    V: Val<A> + Remap<A>,
+    A: Actor,
+{
+    fn remap(&mut self, remap: &ActorRemap<A>) -> Result<()> {
+        remap.check(&self.clock)?;
+        for clock in self.deferred.keys() {
+            remap.check(clock)?;
+        }
+
+        // the nested values check their own clocks, so we remap a copy of the
+        // entries and keep it only if every value accepts the remap
+        let mut entries = self.entries.clone();
+        for entry in entries.values_mut() {
+            entry.clock.remap(remap)?;
+            entry.removed.remap(remap)?;
+            entry.val.remap(remap)?;
+        }
+        self.entries = entries;
+        self.clock.remap(remap)?;
+        let mut deferred: HashMap<VClock<A>, BTreeSet<K>> = HashMap::new();
+        for (mut clock, keys) in mem::take(&mut self.deferred) {
+            clock.remap(remap)?;
+            deferred.entry(clock).or_default().extend(keys);
+        }
+        self.deferred = deferred;
+        Ok(())
+    }
+}
+
+impl<K, V, A> Remap<A> for Op<K, V, A>
+where
+    K: Key,
+    V: Val<A> + Remap<A>,
+    V::Op: Remap<A>,
+    A: Actor,
+{
+    fn remap(&mut self, remap: &ActorRemap<A>) -> Result<()> {
+        match self {
+            Op::Rm { clock, .. } => clock.remap(remap),
+            Op::Up { dot, op, .. } => {
+                op.remap(remap)?;
+                dot.remap(remap)
+            }
+            Op::Mv {
+                dot,
+                from_clock,
+                to_clock,
+                val,
+                ..
+            } => {
+                remap.check(from_clock)?;
+                remap.check(to_clock)?;
+                val.remap(remap)?;
+                from_clock.remap(remap)?;
+                to_clock.remap(remap)?;
+                dot.remap(remap)
+            }
+        }
+    }
+}
+
+impl<K, V, A> Map<K, V, A>
+where
+    K: Key + Etf,
+    V: Val<A> + RiakDt,
+    A: Actor + Etf,
+{
+    fn field_to_term(key: &K) -> Term {
+        Term::Tuple(vec![key.to_term(), Term::Atom(V::MODULE.to_string())])
+    }
+
+    fn field_from_term(field: Term) -> Result<K> {
+        let (name, module) = riak::tuple2(field, "a {Name, Type} field")?;
+        if module != Term::Atom(V::MODULE.to_string()) {
+            return Err(Error::UnexpectedTerm("a field of the map's value type"));
+        }
+        K::from_term(name)
+    }
+
+    // riak_dt 2.1 keeps a copy of a field's state for each dot that updated
+    // it, along with a tombstone holding what was removed from the field.
+    fn entry_from_v2_term(val: Term) -> Result<Entry<V, A>> {
+        let (copies, tombstone) = riak::tuple2(val, "a {CRDTs, Tombstone} field value")?;
+        let mut entry = Entry {
+            clock: VClock::new(),
+            removed: VClock::new(),
+            val: V::from_versioned_term(2, tombstone)?,
+        };
+        for copy in riak::list(copies, "a list of {Dot, CRDT} copies")? {
+            let (dot, copy) = riak::tuple2(copy, "a {Dot, CRDT} copy")?;
+            entry.clock.apply(Dot::from_term(dot)?);
+            entry.val.merge(V::from_versioned_term(2, copy)?);
+        }
+        Ok(entry)
+    }
+}
+
+/// riak_dt_map's state is `{Clock, Entries, Deferred}`, the entries map
+/// `{Name, Type}` fields to their dots and state and the deferred removes map
+/// clocks to lists of fields. A field's type is the riak_dt module of `V`.
+///
+/// riak_dt keeps no record of the dots removed from a field by actors that
+/// have edited it since, they are left out.
+impl<K, V, A> Etf for Map<K, V, A>
+where
+    K: Key + Etf,
+    V: Val<A> + RiakDt,
+    A: Actor + Etf,
+{
+    fn to_term(&self) -> Term {
+        let entries = riak::orddict(self.entries.iter().map(|(key, entry)| {
+            let val = Term::Tuple(vec![entry.clock.to_term(), entry.val.to_term()]);
+            (Self::field_to_term(key), val)
+        }));
+        let deferred = riak::orddict(self.deferred.iter().map(|(clock, keys)| {
+            let fields = riak::sorted_list(keys.iter().map(Self::field_to_term));
+            (clock.to_term(), fields)
+        }));
+        Term::Tuple(vec![self.clock.to_term(), entries, deferred])
+    }
+
+    fn from_term(term: Term) -> Result<Self> {
+        Self::from_versioned_term(Self::VERSION, term)
+    }
+}
+
+/// Layout 1 (riak_dt 2.0) stores a field's dots and state in orddicts, layout
+/// 2 (riak_dt 2.1) stores a copy of the state per dot in dicts, we read both.
+/// Nested states are read in the same layout version as the map.
+impl<K, V, A> RiakDt for Map<K, V, A>
+where
+    K: Key + Etf,
+    V: Val<A> + RiakDt,
+    A: Actor + Etf,
+{
+    const MODULE: &'static str = "riak_dt_map";
+    const TAG: u8 = 77;
+    const VERSION: u8 = 1;
+
+    fn from_versioned_term(version: u8, term: Term) -> Result<Self> {
+        if version != 1 && version != 2 {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let (clock, entries, deferred) = riak::tuple3(term, "a map {Clock, Entries, Deferred}")?;
+        let mut map = Map::new();
+        map.clock = VClock::from_term(clock)?;
+        for (field, val) in riak::dict_pairs(entries)? {
+            let entry = if version == 1 {
+                let (dots, val) = riak::tuple2(val, "a {Dots, CRDT} field value")?;
+                Entry {
+                    clock: VClock::from_term(dots)?,
+                    removed: VClock::new(),
+                    val: V::from_versioned_term(version, val)?,
+                }
+            } else {
+                Self::entry_from_v2_term(val)?
+            };
+            map.entries.insert(Self::field_from_term(field)?, entry);
+        }
+        for (clock, fields) in riak::dict_pairs(deferred)? {
+            let keys = riak::list(fields, "a list of deferred fields")?
+                .into_iter()
+                .map(Self::field_from_term)
+                .collect::<Result<BTreeSet<K>>>()?;
+            map.deferred.insert(VClock::from_term(clock)?, keys);
+        }
+        Ok(map)
+    }
+}
+
+impl<K: Key + Serialize, V: Val<A> + Canonical, A: Actor + Serialize> Canonical for Map<K, V, A> {
+    fn canonical_bytes(&self) -> Vec<u8> {
+        let mut out = canonical::encode(&self.clock);
+        canonical::write_seq(
+            &mut out,
+            self.entries
+                .iter()
+                .map(|(key, entry)| {
+                    let mut elem = canonical::encode(&(key, &entry.clock, &entry.removed));
+                    elem.extend(entry.val.canonical_bytes());
+                    elem
+                })
+                .collect(),
+        );
+        canonical::write_sorted(
+            &mut out,
+            self.deferred
+                .iter()
+                .map(|entry| canonical::encode(&entry))
+                .collect(),
+        );
+        out
+    }
+}
+
+impl<K: Key, V: Val<A> + Validate, A: Actor> Validate for Map<K, V, A> {
+    fn validate(&self) -> Result<()> {
+        self.clock.validate()?;
+        for entry in self.entries.values() {
+            entry.clock.validate()?;
+            match entry.clock.partial_cmp(&self.clock) {
+                _ if entry.clock.is_empty() => {
+                    return Err(Error::InvalidState(
+                        "Map entries must have non-empty clocks",
+                    ));
+                }
+                None | Some(Ordering::Greater) => {
+                    return Err(Error::InvalidState(
+                        "Map entry clocks must be dominated by the Map clock",
+                    ));
+                }
+                _ => (),
+            }
+            entry.removed.validate()?;
+            if entry
+                .removed
+                .iter()
+                .any(|dot| dot.counter >= entry.clock.get(dot.actor))
+            {
+                return Err(Error::InvalidState(
+                    "Map entries may only remember removed dots older than their own",
+                ));
+            }
+            entry.val.validate()?;
+        }
+        for clock in self.deferred.keys() {
+            clock.validate()?;
+            if clock <= &self.clock {
+                return Err(Error::InvalidState(
+                    "Map deferred removes must not be dominated by the Map clock",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<K: Key, V: Val<A> + TrackChanges, A: Actor> TrackChanges for Map<K, V, A> {
+    type Changes = Changes<K, V::Changes>;
+
+    fn changes_since(&self, before: &Self) -> Self::Changes {
+        let mut changes = Changes {
+            added: BTreeSet::new(),
+            removed: BTreeSet::new(),
+            updated: BTreeMap::new(),
+        };
+
+        for (key, entry) in self.entries.iter() {
+            match before.entries.get(key) {
+                None => {
+                    changes.added.insert(key.clone());
+                }
+                Some(old_entry) if old_entry.clock != entry.clock => {
+                    let val_changes = entry.val.changes_since(&old_entry.val);
+                    changes.updated.insert(key.clone(), val_changes);
+                }
+                Some(_) => (),
+            }
+        }
+
+        changes.removed = before
+            .entries
+            .keys()
+            .filter(|key| !self.entries.contains_key(key))
+            .cloned()
+            .collect();
+
+        changes
+    }
+
+    fn apply_with_changes(&mut self, op: Self::Op) -> Self::Changes {
+        let mut touched = BTreeMap::new();
+        self.apply_tracked(op, Some(&mut touched));
+        self.changes_to(touched)
+    }
+
+    fn merge_with_changes(&mut self, other: Self) -> Self::Changes {
+        let mut touched = BTreeMap::new();
+        self.merge_tracked(other, |_| true, Some(&mut touched));
+        self.changes_to(touched)
+    }
+}
+
+impl<K: Key, V: Val<A> + Witness<A>, A: Actor> Witness<A> for Map<K, V, A> {
+    fn witness(&mut self, dot: &Dot<A>) {
+        for entry in self.entries.values_mut() {
+            entry.clock.witness(dot);
+            entry.val.witness(dot);
+        }
+        self.clock.witness(dot);
+    }
+}
+
+impl<K: Key, V: Val<A>, A: Actor> CvRDT for Map<K, V, A> {
+    fn merge(&mut self, other: Self) {
+        self.merge_where(other, |_| true)
+    }
+}
+
+impl<K: Key, V: Val<A> + Lattice, A: Actor> Lattice for Map<K, V, A> {
+    /// Walks the cases of `merge` with `self` merged into `other`, each has
+    /// to leave `other` as it is, nested values included.
+    fn leq(&self, other: &Self) -> bool {
+        // forgetting the removed dots loses nothing of the value
+        let keeps = |val: &V, entry_clock: &VClock<A>, removed: &VClock<A>| {
+            if removed.is_empty() {
+                return true;
+            }
+            let mut forgotten = val.clone();
+            forget_removed_from(&mut forgotten, entry_clock, removed);
+            val.leq(&forgotten)
+        };
+
+        if !self.clock.leq(&other.clock) {
+            return false;
+        }
+
+        // other has our entries, or has seen and dropped them
+        let entries_seen = self
+            .entries
+            .iter()
+            .all(|(key, entry)| other.entries.contains_key(key) || entry.clock.leq(&other.clock));
+
+        let entries_kept = other.entries.iter().all(|(key, their_entry)| {
+            let our_entry = self.entries.get(key);
+
+            // each dot of other's entry is one we haven't seen, or one our
+            // entry still has
+            let clock_kept = their_entry.clock.iter().all(|dot| {
+                dot.counter > self.clock.get(dot.actor)
+                    || our_entry.map(|e| e.clock.get(dot.actor)) == Some(dot.counter)
+            });
+            if !clock_kept {
+                return false;
+            }
+
+            match our_entry {
+                Some(our_entry) => {
+                    // the dots other dropped from our entry clock, and those
+                    // either side remembers removing from the nested value
+                    let mut deleted = our_entry.clock.clone();
+                    deleted.forget(&their_entry.clock);
+                    deleted.merge(their_entry.removed_under(&other.clock));
+                    deleted.merge(our_entry.removed_under(&self.clock));
+                    let mut our_val = our_entry.val.clone();
+                    forget_removed_from(&mut our_val, &their_entry.clock, &deleted);
+                    keeps(&their_entry.val, &their_entry.clock, &deleted)
+                        && our_val.leq(&their_entry.val)
+                }
+                // we've removed every dot of the nested value we've seen
+                None => keeps(&their_entry.val, &their_entry.clock, &self.clock),
+            }
+        });
+
+        // our deferred removes don't touch other's entries, and other has
+        // seen them or deferred them too
+        let deferred_seen = self.deferred.iter().all(|(clock, keys)| {
+            let untouched = keys.iter().all(|key| match other.entries.get(key) {
+                Some(their_entry) => {
+                    their_entry
+                        .clock
+                        .iter()
+                        .all(|dot| dot.counter > clock.get(dot.actor))
+                        && keeps(&their_entry.val, &their_entry.clock, clock)
+                }
+                None => true,
+            });
+            let deferred = clock.leq(&other.clock)
+                || other
+                    .deferred
+                    .get(clock)
+                    .map(|theirs| theirs.is_superset(keys))
+                    .unwrap_or(false);
+            untouched && deferred
+        });
+
+        entries_seen && entries_kept && deferred_seen
+    }
+
+    fn is_bottom(&self) -> bool {
+        self.clock.is_empty() && self.entries.is_empty() && self.deferred.is_empty()
+    }
+}
+
+impl<K: Key, V: Val<A>, A: Actor> Map<K, V, A> {
+    /// `apply`, remembering the entries it changes if asked to
+    fn apply_tracked(&mut self, op: Op<K, V, A>, mut touched: Option<&mut Touched<K, V, A>>) {
         match op {
-            Op::Rm { clock, keyset } => self.apply_keyset_rm(keyset, clock),
+            Op::Rm { clock, keyset } => self.apply_keyset_rm(keyset, clock, touched),
             Op::Up { dot, key, op } => {
                 if self.clock.get(&dot.actor) >= dot.counter {
                     // we've seen this op already
                     return;
                 }
 
+                touch(&mut touched, &key, || self.entries.get(&key).cloned());
+                let removed = self.removed_from(&key);
                 let entry = self.entries.entry(key).or_default();
 
+                // The nested Op may carry dots of this entry that we have
+                // removed since it was made, forget them the way the replica
+                // that made the Op will once it sees our remove.
                 entry.clock.apply(dot.clone());
                 entry.val.apply(op);
+                entry.forget_removed(&removed);
 
                 self.clock.apply(dot);
-                self.apply_deferred();
+                self.apply_deferred(touched);
+            }
+            Op::Mv {
+                dot,
+                from,
+                from_clock,
+                to,
+                to_clock,
+                val,
+            } => {
+                if self.clock.get(&dot.actor) >= dot.counter {
+                    // we've seen this op already
+                    return;
+                }
+
+                // the move removes only what it has seen under `from` and `to`,
+                // concurrent edits to either key will survive the move.
+                let from_keys = Some(from).into_iter().collect();
+                self.apply_keyset_rm(from_keys, from_clock, touched.as_deref_mut());
+                let to_keys = Some(to.clone()).into_iter().collect();
+                self.apply_keyset_rm(to_keys, to_clock, touched.as_deref_mut());
+
+                touch(&mut touched, &to, || self.entries.get(&to).cloned());
+                let removed = self.removed_from(&to);
+                let entry = self.entries.entry(to).or_default();
+                entry.clock.apply(dot.clone());
+                entry.val.merge(val);
+                entry.forget_removed(&removed);
+
+                self.clock.apply(dot);
+                self.apply_deferred(touched);
             }
         }
     }
-}
 
-impl<K: Key, V: Val<A>, A: Actor> CvRDT for Map<K, V, A> {
-    fn merge(&mut self, other: Self) {
+    /// Merge the entries of `other` whose keys are in scope, our entries out
+    /// of scope are left alone even if `other` is missing them.
+    ///
+    /// We still take in the whole clock of `other`, so this is only sound if
+    /// the entries out of scope are already the same on both sides.
+    pub(crate) fn merge_where<F: Fn(&K) -> bool>(&mut self, other: Self, in_scope: F) {
+        self.merge_tracked(other, in_scope, None)
+    }
+
+    /// `merge_where`, remembering the entries it changes if asked to
+    fn merge_tracked<F: Fn(&K) -> bool>(
+        &mut self,
+        other: Self,
+        in_scope: F,
+        mut touched: Option<&mut Touched<K, V, A>>,
+    ) {
         self.entries = mem::replace(&mut self.entries, BTreeMap::new())
             .into_iter()
             .filter_map(|(key, mut entry)| {
-                if !other.entries.contains_key(&key) {
+                if !in_scope(&key) {
+                    Some((key, entry))
+                } else if !other.entries.contains_key(&key) {
                     // other doesn't contain this entry because it:
                     //  1. has seen it and dropped it
                     //  2. hasn't seen it
                     if other.clock >= entry.clock {
                         // other has seen this entry and dropped it
+                        touch(&mut touched, &key, || Some(entry));
                         None
                     } else {
                         // the other map has not seen this version of this
                         // entry, so add it. But first, we have to remove any
                         // information that may have been known at some point
                         // by the other map about this key and was removed.
+                        if entry.forgets(&other.clock) {
+                            touch(&mut touched, &key, || Some(entry.clone()));
+                        }
                         entry.clock.forget(&other.clock);
-                        let mut removed_information = other.clock.clone();
-                        removed_information.forget(&entry.clock);
-                        entry.val.forget(&removed_information);
+                        entry.forget_removed(&other.clock);
                         Some((key, entry))
                     }
                 } else {
@@ -171,7 +955,7 @@ impl<K: Key, V: Val<A>, A: Actor> CvRDT for Map<K, V, A> {
             })
             .collect();
 
-        for (key, mut entry) in other.entries {
+        for (key, mut entry) in other.entries.into_iter().filter(|(key, _)| in_scope(key)) {
             if let Some(our_entry) = self.entries.get_mut(&key) {
                 // SUBTLE: this entry is present in both maps, BUT that doesn't mean we
                 // shouldn't drop it!
@@ -181,17 +965,25 @@ impl<K: Key, V: Val<A>, A: Actor> CvRDT for Map<K, V, A> {
                 common.merge(our_entry.clock.clone_without(&other.clock));
                 if common.is_empty() {
                     // both maps had seen each others entry and removed them
-                    self.entries.remove(&key).unwrap();
+                    let our_entry = self.entries.remove(&key);
+                    touch(&mut touched, &key, || our_entry);
                 } else {
                     // we should not drop, as there is information still tracked in
                     // the common clock.
-                    our_entry.val.merge(entry.val);
+                    if common != our_entry.clock {
+                        touch(&mut touched, &key, || Some(our_entry.clone()));
+                    }
+                    let mut removed = our_entry.removed_under(&self.clock);
+                    removed.merge(entry.removed_under(&other.clock));
 
-                    let mut information_that_was_deleted = entry.clock.clone();
-                    information_that_was_deleted.merge(our_entry.clock.clone());
-                    information_that_was_deleted.forget(&common);
-                    our_entry.val.forget(&information_that_was_deleted);
+                    // forget the removed dots on both sides before merging,
+                    // otherwise a value that was overwritten on one side can look
+                    // concurrent to its overwrite once the other side forgot some
+                    // of the overwrite's dots.
                     our_entry.clock = common;
+                    our_entry.forget_removed(&removed);
+                    forget_removed_from(&mut entry.val, &our_entry.clock, &removed);
+                    our_entry.val.merge(entry.val);
                 }
             } else {
                 // we don't have this entry, is it because we:
@@ -204,10 +996,8 @@ impl<K: Key, V: Val<A>, A: Actor> CvRDT for Map<K, V, A> {
                     // but first, we have to remove the information on this entry
                     // that we have seen and deleted
                     entry.clock.forget(&self.clock);
-
-                    let mut information_we_deleted = self.clock.clone();
-                    information_we_deleted.forget(&entry.clock);
-                    entry.val.forget(&information_we_deleted);
+                    entry.forget_removed(&self.clock);
+                    touch(&mut touched, &key, || None);
                     self.entries.insert(key, entry);
                 }
             }
@@ -215,12 +1005,83 @@ impl<K: Key, V: Val<A>, A: Actor> CvRDT for Map<K, V, A> {
 
         // merge deferred removals
         for (rm_clock, keys) in other.deferred {
-            self.apply_keyset_rm(keys, rm_clock);
+            self.apply_keyset_rm(keys, rm_clock, touched.as_deref_mut());
         }
 
         self.clock.merge(other.clock);
 
-        self.apply_deferred();
+        self.apply_deferred(touched);
+    }
+
+    /// The changes to the touched entries, see `TrackChanges`
+    fn changes_to(&self, touched: Touched<K, V, A>) -> Changes<K, V::Changes>
+    where
+        V: TrackChanges,
+    {
+        let mut changes = Changes {
+            added: BTreeSet::new(),
+            removed: BTreeSet::new(),
+            updated: BTreeMap::new(),
+        };
+        for (key, before) in touched {
+            match (before, self.entries.get(&key)) {
+                (None, Some(_)) => {
+                    changes.added.insert(key);
+                }
+                (Some(_), None) => {
+                    changes.removed.insert(key);
+                }
+                (Some(before), Some(entry)) if before.clock != entry.clock => {
+                    let val_changes = entry.val.changes_since(&before.val);
+                    changes.updated.insert(key, val_changes);
+                }
+                _ => (),
+            }
+        }
+        changes
+    }
+
+    /// A copy of this map holding only the entries whose keys are in scope
+    pub(crate) fn clone_where<F: Fn(&K) -> bool>(&self, in_scope: F) -> Self {
+        Self {
+            clock: self.clock.clone(),
+            entries: self
+                .entries
+                .iter()
+                .filter(|(key, _)| in_scope(key))
+                .map(|(key, entry)| (key.clone(), entry.clone()))
+                .collect(),
+            deferred: self.deferred.clone(),
+        }
+    }
+
+    /// Iterate over the entries along with their clocks
+    pub(crate) fn iter_entries(&self) -> impl Iterator<Item = (&K, &VClock<A>, &V)> {
+        self.entries
+            .iter()
+            .map(|(key, entry)| (key, &entry.clock, &entry.val))
+    }
+
+    /// The entry under a key along with its clock
+    pub(crate) fn entry(&self, key: &K) -> Option<(&VClock<A>, &V)> {
+        self.entries
+            .get(key)
+            .map(|entry| (&entry.clock, &entry.val))
+    }
+
+    /// The keys named by removes that are waiting on Op's we haven't seen
+    pub(crate) fn deferred_keys(&self) -> impl Iterator<Item = &K> {
+        self.deferred.values().flat_map(|keys| keys.iter())
+    }
+
+    /// A copy of this map without the dots removed from its entries
+    #[cfg(test)]
+    pub(crate) fn without_removed(&self) -> Self {
+        let mut map = self.clone();
+        for entry in map.entries.values_mut() {
+            entry.removed = VClock::new();
+        }
+        map
     }
 }
 
@@ -274,8 +1135,23 @@ impl<K: Key, V: Val<A>, A: Actor> Map<K, V, A> {
     {
         let key = key.into();
         let dot = ctx.dot.clone();
-        let op = match self.entries.get(&key).map(|e| &e.val) {
-            Some(data) => f(&data, ctx),
+
+        // The nested CRDT only witnesses the dots of edits to this entry, were
+        // we to hand it the Map's whole clock, a remove of this entry would
+        // leave behind the dots of other entries and the value would linger.
+        let entry_opt = self.entries.get(&key);
+        let mut clock = entry_opt
+            .map(|entry| entry.clock.clone())
+            .unwrap_or_default();
+        clock.glb(&ctx.clock);
+        clock.apply(dot.clone());
+        let ctx = AddCtx {
+            clock,
+            dot: ctx.dot,
+        };
+
+        let op = match entry_opt {
+            Some(entry) => f(&entry.val, ctx),
             None => f(&V::default(), ctx),
         };
 
@@ -292,6 +1168,172 @@ impl<K: Key, V: Val<A>, A: Actor> Map<K, V, A> {
         }
     }
 
+    /// Remove all entries seen by the given ctx from the Map.
+    ///
+    /// This has the same reset-remove semantics as `Map::rm`, edits
+    /// made concurrently with the clear will survive it.
+    pub fn clear(&self, ctx: RmCtx<A>) -> Op<K, V, A> {
+        Op::Rm {
+            clock: ctx.clock,
+            keyset: self.entries.keys().cloned().collect(),
+        }
+    }
+
+    /// Move the entry under `from` to `to`, replacing the value seen under `to`.
+    ///
+    /// Like a file system rename, the move removes what it has seen under
+    /// both keys. A concurrent update to `from` stays under `from` while a
+    /// concurrent update to `to` is merged with the moved value.
+    ///
+    /// Returns None if there is no entry under `from`, there is nothing to move.
+    pub fn mv(&self, from: impl Into<K>, to: impl Into<K>, ctx: AddCtx<A>) -> Option<Op<K, V, A>>
+    where
+        V: Witness<A>,
+    {
+        let from = from.into();
+        let to = to.into();
+        let entry = self.entries.get(&from)?;
+        let (from_clock, mut val) = (entry.clock.clone(), entry.val.clone());
+        let to_clock = self
+            .entries
+            .get(&to)
+            .map(|entry| entry.clock.clone())
+            .unwrap_or_default();
+
+        // Every dot in the moved value belongs to `from`, if we were to move
+        // the value as is, replicas that have seen these dots would drop the
+        // value on merge. So we re-witness the value under the new dot.
+        val.witness(&ctx.dot);
+        val.forget_dots(&self.clock);
+
+        Some(Op::Mv {
+            dot: ctx.dot,
+            from,
+            from_clock,
+            to,
+            to_clock,
+            val,
+        })
+    }
+
+    /// Apply an Op, capturing what it changed so it can be undone, see `undo`.
+    ///
+    /// Only the entries under the keys the Op changes are copied.
+    pub fn apply_with_undo(&mut self, op: Op<K, V, A>) -> Undo<K, V, A> {
+        let (keys, op_clock): (BTreeSet<K>, VClock<A>) = match &op {
+            Op::Rm { keyset, .. } => (keyset.clone(), VClock::new()),
+            Op::Up { dot, key, .. } => (once(key.clone()).collect(), dot.clone().into()),
+            Op::Mv { dot, from, to, .. } => (
+                vec![from.clone(), to.clone()].into_iter().collect(),
+                dot.clone().into(),
+            ),
+        };
+
+        let mut touched = BTreeMap::new();
+        self.apply_tracked(op, Some(&mut touched));
+        let before = touched
+            .into_iter()
+            .filter(|(key, _)| keys.contains(key))
+            .map(|(key, entry)| (key, entry.map(|entry| entry.val)))
+            .collect();
+        Undo { op_clock, before }
+    }
+
+    /// Generate the Op's that undo an Op applied with `apply_with_undo`.
+    ///
+    /// Every key the Op changed is restored to the value it held before,
+    /// re-witnessed under a fresh dot, or removed if it wasn't present. Only
+    /// what the Op did is undone, edits made concurrently by other actors will
+    /// survive. The Op's use consecutive dots starting from `ctx.dot` and must
+    /// be applied in order.
+    pub fn undo(&self, undo: &Undo<K, V, A>, ctx: AddCtx<A>) -> Vec<Op<K, V, A>>
+    where
+        V: Witness<A>,
+    {
+        let mut dot = ctx.dot;
+        let mut ops = Vec::new();
+        for (key, before) in undo.before.iter() {
+            match before {
+                Some(val) => {
+                    // Moving a key onto itself replaces what we've seen of the
+                    // entry, see `mv` for why the value is re-witnessed.
+                    let mut val = val.clone();
+                    val.witness(&dot);
+                    val.forget_dots(&self.clock);
+                    ops.push(Op::Mv {
+                        dot: dot.clone(),
+                        from: key.clone(),
+                        from_clock: VClock::new(),
+                        to: key.clone(),
+                        to_clock: undo.op_clock.clone(),
+                        val,
+                    });
+                    dot.counter += 1;
+                }
+                None if !undo.op_clock.is_empty() => ops.push(Op::Rm {
+                    clock: undo.op_clock.clone(),
+                    keyset: once(key.clone()).collect(),
+                }),
+                None => (),
+            }
+        }
+        ops
+    }
+
+    /// Returns a delta that brings `older` up to date, merging the delta into
+    /// `older` has the same effect as merging this entire Map.
+    ///
+    /// A remove leaves no trace in the clock, so we find the keys removed
+    /// since `older` by comparing its entries with ours. The delta's clock
+    /// only covers the actors with dots `older` hasn't seen or with dots that
+    /// differ between the two, and only our entries holding dots of those
+    /// actors are sent. Of those, the entries `older` already has are sent
+    /// without their values.
+    pub fn diff(&self, older: &Self) -> Self
+    where
+        V: PartialEq,
+    {
+        let mut covered = self.clock.clone_without(&older.clock);
+        for (key, older_entry) in older.entries.iter() {
+            match self.entries.get(key) {
+                Some(entry) if entry == older_entry => (),
+                Some(entry) => {
+                    covered.merge(older_entry.clock.clone());
+                    covered.merge(entry.clock.clone());
+                }
+                None => covered.merge(older_entry.clock.clone()),
+            }
+        }
+
+        let entries = self
+            .entries
+            .iter()
+            .filter_map(|(key, entry)| {
+                let seen = older.entries.get(key) == Some(entry);
+                if !seen {
+                    Some((key.clone(), entry.clone()))
+                } else if entry.clock.iter().any(|dot| covered.get(dot.actor) > 0) {
+                    let entry = Entry {
+                        clock: entry.clock.clone(),
+                        removed: entry.removed.clone(),
+                        val: V::default(),
+                    };
+                    Some((key.clone(), entry))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let mut clock = self.clock.clone();
+        clock.retain_actors(&covered);
+        Self {
+            clock,
+            entries,
+            deferred: self.deferred.clone(),
+        }
+    }
+
     /// Retrieve the current read context
     pub fn read_ctx(&self) -> ReadCtx<(), A> {
         ReadCtx {
@@ -301,18 +1343,52 @@ impl<K: Key, V: Val<A>, A: Actor> Map<K, V, A> {
         }
     }
 
+    /// The dots we have removed from the entry under `key`, all the dots we
+    /// have seen if there is no entry.
+    fn removed_from(&self, key: &K) -> VClock<A> {
+        match self.entries.get(key) {
+            Some(entry) => entry.removed_under(&self.clock),
+            None => self.clock.clone(),
+        }
+    }
+
     /// apply the pending deferred removes
-    fn apply_deferred(&mut self) {
+    fn apply_deferred(&mut self, mut touched: Option<&mut Touched<K, V, A>>) {
         let deferred = mem::replace(&mut self.deferred, HashMap::new());
         for (clock, keys) in deferred {
-            self.apply_keyset_rm(keys, clock);
+            self.apply_keyset_rm(keys, clock, touched.as_deref_mut());
+        }
+    }
+
+    /// A dot we've seen before must have been used to edit this same key.
+    fn validate_dot(&self, dot: &Dot<A>, key: &K, clock: &VClock<A>) -> Result<()> {
+        clock.validate_dot(dot)?;
+
+        let seen = clock.get(&dot.actor) >= dot.counter;
+        let reused = seen
+            && self
+                .entries
+                .iter()
+                .any(|(k, entry)| entry.clock.get(&dot.actor) == dot.counter && k != key);
+        if reused {
+            Err(Error::ConflictingMarker)
+        } else {
+            Ok(())
         }
     }
 
     /// Apply a set of key removals given a clock.
-    fn apply_keyset_rm(&mut self, mut keyset: BTreeSet<K>, clock: VClock<A>) {
+    fn apply_keyset_rm(
+        &mut self,
+        mut keyset: BTreeSet<K>,
+        clock: VClock<A>,
+        mut touched: Option<&mut Touched<K, V, A>>,
+    ) {
         for key in keyset.iter() {
             if let Some(entry) = self.entries.get_mut(&key) {
+                if entry.forgets(&clock) {
+                    touch(&mut touched, key, || Some(entry.clone()));
+                }
                 entry.clock.forget(&clock);
                 if entry.clock.is_empty() {
                     // The entry clock says we have no info on this entry.
@@ -321,7 +1397,7 @@ impl<K: Key, V: Val<A>, A: Actor> Map<K, V, A> {
                 } else {
                     // The entry clock is not empty so this means we still
                     // have some information on this entry, keep it.
-                    entry.val.forget(&clock);
+                    entry.forget_removed(&clock);
                 }
             }
         }
@@ -342,6 +1418,76 @@ impl<K: Key, V: Val<A>, A: Actor> Map<K, V, A> {
     }
 }
 
+#[cfg(any(test, feature = "quickcheck"))]
+impl<K, V, A> Arbitrary for Op<K, V, A>
+where
+    K: Key + Arbitrary,
+    V: Val<A> + Arbitrary,
+    V::Op: Arbitrary,
+    A: Actor + Arbitrary,
+{
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        match u8::arbitrary(g) % 3 {
+            0 => Op::Rm {
+                clock: VClock::arbitrary(g),
+                keyset: BTreeSet::arbitrary(g),
+            },
+            1 => Op::Up {
+                dot: Dot::arbitrary(g),
+                key: K::arbitrary(g),
+                op: V::Op::arbitrary(g),
+            },
+            _ => Op::Mv {
+                dot: Dot::arbitrary(g),
+                from: K::arbitrary(g),
+                from_clock: VClock::arbitrary(g),
+                to: K::arbitrary(g),
+                to_clock: VClock::arbitrary(g),
+                val: V::arbitrary(g),
+            },
+        }
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<K, V, A> ArbitraryOp<A> for Map<K, V, A>
+where
+    K: Key + Arbitrary,
+    V: Val<A> + ArbitraryOp<A>,
+    A: Actor,
+{
+    /// Updates an arbitrary entry with an Op generated by the nested CRDT, or
+    /// removes an arbitrary entry with the context it was last read with.
+    fn arbitrary_op<G: Gen>(&self, ctx: AddCtx<A>, g: &mut G) -> Op<K, V, A> {
+        let key = K::arbitrary(g);
+        if bool::arbitrary(g) {
+            self.update(key, ctx, |val, ctx| val.arbitrary_op(ctx, g))
+        } else {
+            self.rm(key.clone(), self.get(&key).derive_rm_ctx())
+        }
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<K, V, A> Arbitrary for Map<K, V, A>
+where
+    K: Key + Arbitrary,
+    V: Val<A> + ArbitraryOp<A> + Send + 'static,
+    A: Actor + Arbitrary,
+{
+    /// Builds a Map from a sequence of arbitrary Op's by arbitrary actors,
+    /// the nested values are edited through `ArbitraryOp` so their clocks
+    /// stay consistent with the Map's clock.
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut map = Map::new();
+        for actor in Vec::<A>::arbitrary(g) {
+            let op = map.arbitrary_op(map.read_ctx().derive_add_ctx(actor), g);
+            map.apply(op);
+        }
+        map
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -365,6 +1511,7 @@ mod test {
             0,
             Entry {
                 clock: m.clock.clone(),
+                removed: VClock::new(),
                 val: Map::default(),
             },
         );
@@ -441,6 +1588,7 @@ mod test {
                 101,
                 Entry {
                     clock: vec![Dot::new(75, 1), Dot::new(93, 1)].into_iter().collect(),
+                    removed: VClock::new(),
                     val: Orswot {
                         clock: vec![Dot::new(75, 1), Dot::new(93, 1)].into_iter().collect(),
                         entries: vec![
@@ -468,6 +1616,7 @@ mod test {
                     101,
                     Entry {
                         clock: Dot::new(93, 1).into(),
+                        removed: VClock::new(),
                         val: Orswot {
                             clock: vec![Dot::new(93, 1)].into_iter().collect(),
                             entries: vec![(2, VClock::from(Dot::new(93, 1)))]
@@ -487,4 +1636,30 @@ mod test {
 
         assert_eq!(m1, m2);
     }
+
+    #[test]
+    fn test_canonical_bytes_ignore_merge_order() {
+        let mut a: Map<u8, Orswot<u8, u8>, u8> = Map::new();
+        let mut b: Map<u8, Orswot<u8, u8>, u8> = Map::new();
+        for key in 0..10 {
+            let op = a.update(key, a.get(&key).derive_add_ctx(1), |set, ctx| {
+                set.add(key, ctx)
+            });
+            a.apply(op);
+            let op = b.update(key, b.get(&key).derive_add_ctx(2), |set, ctx| {
+                set.add(key + 10, ctx)
+            });
+            b.apply(op);
+        }
+
+        let mut ab = a.clone();
+        ab.merge(b.clone());
+        let mut ba = b;
+        ba.merge(a);
+
+        assert_eq!(ab.digest(), ba.digest());
+        let decoded: Map<u8, Orswot<u8, u8>, u8> =
+            bincode::deserialize(&ab.canonical_bytes()).unwrap();
+        assert_eq!(decoded, ab);
+    }
 }
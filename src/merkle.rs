@@ -0,0 +1,383 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::canonical;
+use crate::map::{Key, Map, Val};
+use crate::traits::Canonical;
+use crate::vclock::{Actor, VClock};
+
+/// A sha256 hash of a node in the index
+pub type Hash = [u8; 32];
+
+/// The largest depth an index may have, it has `2^depth` buckets.
+pub const MAX_DEPTH: u8 = 24;
+
+/// A Merkle tree of digests over the entries of a `Map`.
+///
+/// Keys are placed in `2^depth` buckets by the range their hash falls in,
+/// each leaf hashes the keys, clocks and values of the entries in its
+/// bucket and each inner node hashes its two children. Two replicas find
+/// the buckets they disagree on by comparing their trees level by level,
+/// only descending into nodes that differ, then merge only the entries in
+/// those buckets with `delta` and `merge_delta`.
+///
+/// Values are hashed through their canonical encoding, so equal values
+/// always hash the same, see `Canonical`.
+///
+/// ``` rust
+/// use crdts::merkle::{self, MerkleIndex};
+/// use crdts::{CmRDT, Map, MVReg};
+///
+/// let mut a: Map<u32, MVReg<u32, &str>, &str> = Map::new();
+/// for key in 0..100u32 {
+///     let op = a.update(key, a.get(&key).derive_add_ctx("A"), |reg, ctx| reg.write(key, ctx));
+///     a.apply(op);
+/// }
+/// let mut b = a.clone();
+/// let op = b.update(7u32, b.get(&7).derive_add_ctx("B"), |reg, ctx| reg.write(700, ctx));
+/// b.apply(op);
+///
+/// let mut a_index = MerkleIndex::new(&a, 4);
+/// let b_index = MerkleIndex::new(&b, 4);
+///
+/// // in practice each level is exchanged over the network
+/// let mut positions = vec![0];
+/// for level in 0..a_index.depth() {
+///     let theirs = b_index.nodes(level, &positions);
+///     positions = MerkleIndex::<u32>::children(&a_index.diverging(level, &positions, &theirs));
+/// }
+/// let buckets = a_index.diverging(a_index.depth(), &positions, &b_index.nodes(a_index.depth(), &positions));
+/// assert_eq!(buckets, vec![a_index.bucket(&7)]);
+///
+/// merkle::merge_delta(&mut a, &mut a_index, merkle::delta(&b, &b_index, &buckets), &buckets);
+/// assert_eq!(a, b);
+/// assert_eq!(a_index.root(), b_index.root());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleIndex<K: Key> {
+    depth: u8,
+    // levels[0] holds the root and levels[depth] the leaves
+    levels: Vec<Vec<Hash>>,
+    // the hash of every entry, by bucket then key
+    buckets: Vec<BTreeMap<K, Hash>>,
+}
+
+impl<K: Key + Serialize> MerkleIndex<K> {
+    /// Index the entries of a map into `2^depth` buckets
+    pub fn new<V, A>(map: &Map<K, V, A>, depth: u8) -> Self
+    where
+        V: Val<A> + Canonical,
+        A: Actor + Serialize,
+    {
+        assert!(
+            depth <= MAX_DEPTH,
+            "a MerkleIndex is at most {} deep",
+            MAX_DEPTH
+        );
+        let mut index = MerkleIndex {
+            depth,
+            levels: (0..=depth).map(|level| vec![[0; 32]; 1 << level]).collect(),
+            buckets: vec![BTreeMap::new(); 1 << depth],
+        };
+        for (key, clock, val) in map.iter_entries() {
+            let bucket = index.bucket(key);
+            index.buckets[bucket].insert(key.clone(), hash_entry(key, clock, val));
+        }
+        for bucket in 0..index.buckets.len() {
+            index.levels[depth as usize][bucket] = index.hash_bucket(bucket);
+        }
+        for level in (0..depth as usize).rev() {
+            for pos in 0..index.levels[level].len() {
+                index.levels[level][pos] = index.hash_children(level, pos);
+            }
+        }
+        index
+    }
+
+    /// The depth of the tree, the leaves are at this level
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// The hash of the whole map, two maps with the same entries have the same root
+    pub fn root(&self) -> Hash {
+        self.levels[0][0]
+    }
+
+    /// The bucket a key is placed in
+    pub fn bucket(&self, key: &K) -> usize {
+        if self.depth == 0 {
+            return 0;
+        }
+        let hash = Sha256::digest(canonical::encode(key));
+        let prefix = u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]);
+        (prefix >> (32 - self.depth)) as usize
+    }
+
+    /// The hashes of the nodes at `positions` in a level, for sending to a
+    /// replica we are comparing with.
+    pub fn nodes(&self, level: u8, positions: &[usize]) -> Vec<Hash> {
+        positions
+            .iter()
+            .map(|pos| self.levels[level as usize][*pos])
+            .collect()
+    }
+
+    /// The positions in a level whose hash differs from the hash the other
+    /// replica has for that position, `theirs` lines up with `positions`.
+    pub fn diverging(&self, level: u8, positions: &[usize], theirs: &[Hash]) -> Vec<usize> {
+        positions
+            .iter()
+            .zip(theirs)
+            .filter(|(pos, hash)| &self.levels[level as usize][**pos] != *hash)
+            .map(|(pos, _)| *pos)
+            .collect()
+    }
+
+    /// The positions of the children of `positions` in the next level down
+    pub fn children(positions: &[usize]) -> Vec<usize> {
+        positions
+            .iter()
+            .flat_map(|pos| vec![pos * 2, pos * 2 + 1])
+            .collect()
+    }
+
+    /// The buckets two indexes of the same depth disagree on
+    pub fn diff(&self, other: &Self) -> Vec<usize> {
+        assert_eq!(
+            self.depth, other.depth,
+            "can't diff indexes of different depths"
+        );
+        let mut positions = vec![0];
+        for level in 0..=self.depth {
+            positions = self.diverging(level, &positions, &other.nodes(level, &positions));
+            if level < self.depth {
+                positions = Self::children(&positions);
+            }
+        }
+        positions
+    }
+
+    /// Re-index the entry under `key`, call this for every key an Op changed
+    pub fn refresh<V, A>(&mut self, map: &Map<K, V, A>, key: &K)
+    where
+        V: Val<A> + Canonical,
+        A: Actor + Serialize,
+    {
+        let bucket = self.bucket(key);
+        match map.entry(key) {
+            Some((clock, val)) => {
+                self.buckets[bucket].insert(key.clone(), hash_entry(key, clock, val));
+            }
+            None => {
+                self.buckets[bucket].remove(key);
+            }
+        }
+        self.rehash(bucket);
+    }
+
+    fn rehash(&mut self, bucket: usize) {
+        let depth = self.depth as usize;
+        self.levels[depth][bucket] = self.hash_bucket(bucket);
+        let mut pos = bucket;
+        for level in (0..depth).rev() {
+            pos /= 2;
+            self.levels[level][pos] = self.hash_children(level, pos);
+        }
+    }
+
+    fn hash_bucket(&self, bucket: usize) -> Hash {
+        let mut hasher = Sha256::new();
+        for hash in self.buckets[bucket].values() {
+            hasher.update(hash);
+        }
+        hasher.finalize().into()
+    }
+
+    fn hash_children(&self, level: usize, pos: usize) -> Hash {
+        let children = &self.levels[level + 1];
+        let mut hasher = Sha256::new();
+        hasher.update(children[pos * 2]);
+        hasher.update(children[pos * 2 + 1]);
+        hasher.finalize().into()
+    }
+}
+
+/// The entries of a map in the given buckets of its index, for a replica
+/// to merge with `merge_delta`.
+pub fn delta<K, V, A>(map: &Map<K, V, A>, index: &MerkleIndex<K>, buckets: &[usize]) -> Map<K, V, A>
+where
+    K: Key + Serialize,
+    V: Val<A> + Canonical,
+    A: Actor + Serialize,
+{
+    let keys: BTreeSet<&K> = buckets
+        .iter()
+        .flat_map(|bucket| index.buckets[*bucket].keys())
+        .collect();
+    map.clone_where(|key| keys.contains(key))
+}
+
+/// Merge the entries of a delta into a map and bring its index up to date.
+///
+/// `buckets` must be every bucket the two replicas disagree on, the entries
+/// in the other buckets are assumed to be the same on both sides. The map
+/// ends up as if we had merged the whole map the delta was taken from.
+pub fn merge_delta<K, V, A>(
+    map: &mut Map<K, V, A>,
+    index: &mut MerkleIndex<K>,
+    delta: Map<K, V, A>,
+    buckets: &[usize],
+) where
+    K: Key + Serialize,
+    V: Val<A> + Canonical,
+    A: Actor + Serialize,
+{
+    // the keys in scope on either side
+    let mut scope: BTreeSet<K> = delta
+        .iter_entries()
+        .map(|(key, _, _)| key.clone())
+        .collect();
+    for bucket in buckets {
+        scope.extend(index.buckets[*bucket].keys().cloned());
+    }
+
+    // deferred removes may be applied by the merge, touching keys out of scope
+    let mut touched = scope.clone();
+    touched.extend(map.deferred_keys().cloned());
+    touched.extend(delta.deferred_keys().cloned());
+
+    map.merge_where(delta, |key| scope.contains(key));
+    for key in touched.iter() {
+        index.refresh(map, key);
+    }
+}
+
+fn hash_entry<K, V, A>(key: &K, clock: &VClock<A>, val: &V) -> Hash
+where
+    K: Serialize,
+    V: Canonical,
+    A: Actor + Serialize,
+{
+    let mut hasher = Sha256::new();
+    hasher.update(canonical::encode(&(key, clock)));
+    hasher.update(val.canonical_bytes());
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use quickcheck::quickcheck;
+
+    use crate::{CmRDT, CvRDT, MVReg};
+
+    type TMap = Map<u8, MVReg<u8, u8>, u8>;
+
+    fn edit(map: &mut TMap, actor: u8, key: u8, val: Option<u8>) {
+        let op = match val {
+            Some(val) => map.update(key, map.get(&key).derive_add_ctx(actor), |reg, ctx| {
+                reg.write(val, ctx)
+            }),
+            None => map.rm(key, map.get(&key).derive_rm_ctx()),
+        };
+        map.apply(op);
+    }
+
+    /// Sync `b` into `a` through their indexes, returning the buckets sent
+    fn sync(a: &mut TMap, a_index: &mut MerkleIndex<u8>, b: &TMap, depth: u8) -> Vec<usize> {
+        let b_index = MerkleIndex::new(b, depth);
+        let buckets = a_index.diff(&b_index);
+        merge_delta(a, a_index, delta(b, &b_index, &buckets), &buckets);
+        buckets
+    }
+
+    #[test]
+    fn test_same_entries_same_root() {
+        let mut a = TMap::new();
+        for key in 0..50 {
+            edit(&mut a, 1, key, Some(key));
+        }
+        let b = a.clone();
+        let a_index = MerkleIndex::new(&a, 6);
+        assert_eq!(a_index, MerkleIndex::new(&b, 6));
+        assert!(a_index.diff(&MerkleIndex::new(&b, 6)).is_empty());
+        assert_ne!(a_index.root(), MerkleIndex::new(&TMap::new(), 6).root());
+    }
+
+    #[test]
+    fn test_refresh_matches_rebuilt_index() {
+        let mut map = TMap::new();
+        let mut index = MerkleIndex::new(&map, 5);
+        for key in 0..40 {
+            edit(&mut map, 1, key, Some(key));
+            index.refresh(&map, &key);
+        }
+        for key in (0..40).step_by(3) {
+            edit(&mut map, 2, key, None);
+            index.refresh(&map, &key);
+        }
+        assert_eq!(index, MerkleIndex::new(&map, 5));
+    }
+
+    #[test]
+    fn test_only_divergent_buckets_are_sent() {
+        let mut a = TMap::new();
+        for key in 0..200 {
+            edit(&mut a, 1, key, Some(key));
+        }
+        let mut b = a.clone();
+        edit(&mut b, 2, 10, Some(0));
+        edit(&mut b, 2, 20, None);
+
+        let mut a_index = MerkleIndex::new(&a, 8);
+        let buckets = sync(&mut a, &mut a_index, &b, 8);
+        let mut expected = vec![a_index.bucket(&10), a_index.bucket(&20)];
+        expected.sort();
+        expected.dedup();
+        assert_eq!(buckets, expected);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_depth_zero_is_a_full_merge() {
+        let mut a = TMap::new();
+        let mut b = TMap::new();
+        edit(&mut a, 1, 1, Some(1));
+        edit(&mut b, 2, 2, Some(2));
+
+        let mut a_index = MerkleIndex::new(&a, 0);
+        assert_eq!(sync(&mut a, &mut a_index, &b, 0), vec![0]);
+        assert_eq!(a.len().val, 2);
+    }
+
+    quickcheck! {
+        fn prop_merge_delta_is_same_as_merge(
+            base: Vec<(u8, Option<u8>)>,
+            a_edits: Vec<(u8, Option<u8>)>,
+            b_edits: Vec<(u8, Option<u8>)>
+        ) -> bool {
+            let mut a = TMap::new();
+            for (key, val) in base {
+                edit(&mut a, 0, key % 32, val);
+            }
+            let mut b = a.clone();
+            for (key, val) in a_edits {
+                edit(&mut a, 1, key % 32, val);
+            }
+            for (key, val) in b_edits {
+                edit(&mut b, 2, key % 32, val);
+            }
+
+            let mut merged = a.clone();
+            merged.merge(b.clone());
+
+            let mut a_index = MerkleIndex::new(&a, 3);
+            sync(&mut a, &mut a_index, &b, 3);
+            a == merged && a_index == MerkleIndex::new(&merged, 3)
+        }
+    }
+}
@@ -2,11 +2,24 @@ use std::cmp::Ordering;
 use std::fmt::{self, Debug, Display};
 use std::mem;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+use crate::canonical;
+use crate::codec::{Compact, Decoder, Encoder};
 use crate::ctx::{AddCtx, ReadCtx};
-use crate::traits::{Causal, CmRDT, CvRDT};
-use crate::vclock::{Actor, VClock};
+use crate::error::{Error, Result};
+use crate::intern::{ActorTable, Intern, Interned};
+use crate::retire::{ActorRemap, Remap};
+#[cfg(any(test, feature = "quickcheck"))]
+use crate::traits::ArbitraryOp;
+use crate::traits::{
+    Canonical, Causal, CmRDT, CvRDT, Lattice, TrackChanges, Validate, ValidateUnder, Witness,
+};
+use crate::vclock::{Actor, Dot, VClock};
 
 /// A Trait alias for the possible values MVReg's may hold
 pub trait Val: Debug + Clone {}
@@ -107,6 +120,143 @@ impl<V: Val, A: Actor> Causal<A> for MVReg<V, A> {
     }
 }
 
+impl<V: Val, A: Actor> Witness<A> for MVReg<V, A> {
+    fn witness(&mut self, dot: &Dot<A>) {
+        for (val_clock, _) in self.vals.iter_mut() {
+            val_clock.witness(dot);
+        }
+    }
+}
+
+impl<V: Val, A: Actor> Intern<A> for MVReg<V, Interned<A>> {
+    fn intern(&mut self, table: &mut ActorTable<A>) {
+        for (clock, _) in self.vals.iter_mut() {
+            clock.intern(table);
+        }
+    }
+}
+
+impl<V: Val, A: Actor> Intern<A> for Op<V, Interned<A>> {
+    fn intern(&mut self, table: &mut ActorTable<A>) {
+        let Op::Put { clock, .. } = self;
+        clock.intern(table);
+    }
+}
+
+impl<V: Val, A: Actor> Remap<A> for MVReg<V, A> {
+    fn remap(&mut self, remap: &ActorRemap<A>) -> Result<()> {
+        for (clock, _) in self.vals.iter() {
+            remap.check(clock)?;
+        }
+        for (clock, _) in self.vals.iter_mut() {
+            clock.remap(remap)?;
+        }
+        Ok(())
+    }
+}
+
+impl<V: Val, A: Actor> Remap<A> for Op<V, A> {
+    fn remap(&mut self, remap: &ActorRemap<A>) -> Result<()> {
+        let Op::Put { clock, .. } = self;
+        clock.remap(remap)
+    }
+}
+
+impl<V: Val + Serialize + DeserializeOwned, A: Actor> Compact<A> for MVReg<V, A> {
+    fn encode(&self, enc: &mut Encoder<A>) {
+        enc.u64(self.vals.len() as u64);
+        for (clock, val) in self.vals.iter() {
+            enc.clock(clock);
+            enc.value(val);
+        }
+    }
+
+    fn decode(dec: &mut Decoder<A>) -> Result<Self> {
+        let mut vals = Vec::new();
+        for _ in 0..dec.seq_len()? {
+            vals.push((dec.clock()?, dec.value()?));
+        }
+        Ok(MVReg { vals })
+    }
+}
+
+impl<V: Val + Serialize + DeserializeOwned, A: Actor> Compact<A> for Op<V, A> {
+    fn encode(&self, enc: &mut Encoder<A>) {
+        let Op::Put { clock, val } = self;
+        enc.clock(clock);
+        enc.value(val);
+    }
+
+    fn decode(dec: &mut Decoder<A>) -> Result<Self> {
+        Ok(Op::Put {
+            clock: dec.clock()?,
+            val: dec.value()?,
+        })
+    }
+}
+
+impl<V: Val + Serialize, A: Actor + Serialize> Canonical for MVReg<V, A> {
+    fn canonical_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        canonical::write_sorted(&mut out, self.vals.iter().map(canonical::encode).collect());
+        out
+    }
+}
+
+impl<V: Val, A: Actor> Validate for MVReg<V, A> {
+    fn validate(&self) -> Result<()> {
+        for (i, (clock, _)) in self.vals.iter().enumerate() {
+            clock.validate()?;
+            if clock.is_empty() {
+                return Err(Error::InvalidState(
+                    "MVReg values must have non-empty clocks",
+                ));
+            }
+            if self.vals[i + 1..].iter().any(|(c, _)| !clock.concurrent(c)) {
+                return Err(Error::InvalidState(
+                    "MVReg values must have pairwise concurrent clocks",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<V: Val, A: Actor> TrackChanges for MVReg<V, A> {
+    /// The new set of concurrent values, if it changed
+    type Changes = Option<Vec<V>>;
+
+    fn changes_since(&self, before: &Self) -> Self::Changes {
+        let unchanged = self.vals.len() == before.vals.len()
+            && self
+                .vals
+                .iter()
+                .all(|(clock, _)| before.vals.iter().any(|(c, _)| clock == c));
+
+        if unchanged {
+            None
+        } else {
+            Some(self.read().val)
+        }
+    }
+
+    fn apply_with_changes(&mut self, op: Self::Op) -> Self::Changes {
+        if self.apply_put(op) {
+            Some(self.read().val)
+        } else {
+            None
+        }
+    }
+
+    fn merge_with_changes(&mut self, other: Self) -> Self::Changes {
+        if self.merge_vals(other) {
+            Some(self.read().val)
+        } else {
+            None
+        }
+    }
+}
+
 impl<V: Val, A: Actor> Default for MVReg<V, A> {
     fn default() -> Self {
         Self::new()
@@ -115,19 +265,26 @@ impl<V: Val, A: Actor> Default for MVReg<V, A> {
 
 impl<V: Val, A: Actor> CvRDT for MVReg<V, A> {
     fn merge(&mut self, other: Self) {
-        self.vals = mem::replace(&mut self.vals, Vec::new())
-            .into_iter()
-            .filter(|(clock, _)| other.vals.iter().filter(|(c, _)| clock < c).count() == 0)
-            .collect();
+        self.merge_vals(other);
+    }
+}
 
-        self.vals.extend(
+impl<V: Val, A: Actor> Lattice for MVReg<V, A> {
+    /// Merging keeps the values no value on the other side has seen, so
+    /// `other` knows everything we do if none of our values are ahead of one
+    /// of its values and each of ours is seen by one of its values.
+    fn leq(&self, other: &Self) -> bool {
+        self.vals.iter().all(|(clock, _)| {
             other
                 .vals
-                .into_iter()
-                .filter(|(clock, _)| self.vals.iter().filter(|(c, _)| clock < c).count() == 0)
-                .filter(|(clock, _)| self.vals.iter().all(|(c, _)| clock != c))
-                .collect::<Vec<_>>(),
-        );
+                .iter()
+                .all(|(c, _)| c.partial_cmp(clock) != Some(Ordering::Less))
+                && other.vals.iter().any(|(c, _)| clock <= c)
+        })
+    }
+
+    fn is_bottom(&self) -> bool {
+        self.vals.is_empty()
     }
 }
 
@@ -135,34 +292,56 @@ impl<V: Val, A: Actor> CmRDT for MVReg<V, A> {
     type Op = Op<V, A>;
 
     fn apply(&mut self, op: Self::Op) {
-        match op {
-            Op::Put { clock, val } => {
-                if clock.is_empty() {
-                    return;
-                }
-                // first filter out all values that are dominated by the Op clock
-                self.vals
-                    .retain(|(val_clock, _)| match val_clock.partial_cmp(&clock) {
-                        None | Some(Ordering::Greater) => true,
-                        _ => false,
-                    });
-
-                // TAI: in the case were the Op has a context that already was present,
-                //      the above line would remove that value, the next lines would
-                //      keep the val from the Op, so.. a malformed Op could break
-                //      commutativity.
-
-                // now check if we've already seen this op
-                let mut should_add = true;
-                for (existing_clock, _) in self.vals.iter() {
-                    if existing_clock > &clock {
-                        // we've found an entry that dominates this op
-                        should_add = false;
-                    }
-                }
+        self.apply_put(op);
+    }
+
+    fn validate_op(&self, op: &Self::Op) -> Result<()> {
+        self.validate_op_under(op, &self.clock())
+    }
+
+    /// Validates the Put and skips Put's we've already seen.
+    ///
+    /// Re-applying a Put whose clock matches one of our values would replace
+    /// that value with the value of the Put (see the TAI note in `apply`), so
+    /// a replay carrying a different value can't corrupt the register here.
+    fn try_apply(&mut self, op: Self::Op) -> Result<()> {
+        self.validate_op(&op)?;
+        let seen = {
+            let Op::Put { clock, .. } = &op;
+            self.vals.iter().any(|(c, _)| c >= clock)
+        };
+        if !seen {
+            self.apply(op);
+        }
+        Ok(())
+    }
+}
+
+impl<V: Val, A: Actor> ValidateUnder<A> for MVReg<V, A> {
+    fn validate_op_under(&self, op: &Self::Op, our_clock: &VClock<A>) -> Result<()> {
+        let Op::Put { clock, .. } = op;
+        if clock.is_empty() {
+            return Err(Error::EmptyClock);
+        }
 
-                if should_add {
-                    self.vals.push((clock, val));
+        // A Put witnesses the clock it was written under plus one new dot,
+        // anything more means we're missing writes this Put depends on.
+        let mut new_dots = clock
+            .iter()
+            .filter(|dot| dot.counter > our_clock.get(dot.actor));
+        match (new_dots.next(), new_dots.next()) {
+            (Some(_), Some(_)) => Err(Error::CausalGap),
+            (Some(dot), None) if dot.counter > our_clock.get(dot.actor) + 1 => {
+                Err(Error::CausalGap)
+            }
+            (Some(_), None) => Ok(()),
+            (None, _) => {
+                // We've seen every dot of this Put, so it was written or
+                // overwritten by one of our values, otherwise the dot was reused.
+                if self.vals.iter().any(|(c, _)| c >= clock) {
+                    Ok(())
+                } else {
+                    Err(Error::ConflictingMarker)
                 }
             }
         }
@@ -183,6 +362,30 @@ impl<V: Val, A: Actor> MVReg<V, A> {
         }
     }
 
+    /// Generate an Op that undoes `op`, given the read of the register from
+    /// before `op` was applied.
+    ///
+    /// The undo overwrites only the value written by `op`, values written
+    /// concurrently by other actors are kept as siblings. Returns None if the
+    /// value written by `op` has since been overwritten, or if the previous
+    /// value can't be written back: a single actor can't recreate concurrent
+    /// values, so only a register that held exactly one value is restored.
+    pub fn undo(
+        &self,
+        op: &Op<V, A>,
+        mut before: ReadCtx<Vec<V>, A>,
+        ctx: AddCtx<A>,
+    ) -> Option<Op<V, A>> {
+        let Op::Put { clock, .. } = op;
+        if before.val.len() != 1 || self.vals.iter().all(|(c, _)| c != clock) {
+            return None;
+        }
+
+        let mut clock = clock.clone();
+        clock.apply(ctx.dot);
+        before.val.pop().map(|val| Op::Put { clock, val })
+    }
+
     /// Consumes the register and returns the values
     pub fn read(&self) -> ReadCtx<Vec<V>, A> {
         let clock = self.clock().clone();
@@ -205,6 +408,67 @@ impl<V: Val, A: Actor> MVReg<V, A> {
         }
     }
 
+    /// Apply a Put, returning whether it changed the values
+    fn apply_put(&mut self, op: Op<V, A>) -> bool {
+        let Op::Put { clock, val } = op;
+        if clock.is_empty() {
+            return false;
+        }
+        let rewritten = self.vals.iter().any(|(c, _)| c == &clock);
+
+        // first filter out all values that are dominated by the Op clock
+        let len = self.vals.len();
+        self.vals.retain(|(val_clock, _)| {
+            matches!(
+                val_clock.partial_cmp(&clock),
+                None | Some(Ordering::Greater)
+            )
+        });
+        let dropped = len - self.vals.len();
+
+        // TAI: in the case were the Op has a context that already was present,
+        //      the above line would remove that value, the next lines would
+        //      keep the val from the Op, so.. a malformed Op could break
+        //      commutativity.
+
+        // now check if we've already seen this op
+        let mut should_add = true;
+        for (existing_clock, _) in self.vals.iter() {
+            if existing_clock > &clock {
+                // we've found an entry that dominates this op
+                should_add = false;
+            }
+        }
+
+        if should_add {
+            self.vals.push((clock, val));
+        }
+
+        // a value written again under its own clock is no change
+        let rewritten = rewritten && should_add;
+        dropped > rewritten as usize || (should_add && !rewritten)
+    }
+
+    /// Merge the values of another register, returning whether ours changed
+    fn merge_vals(&mut self, other: Self) -> bool {
+        let len = self.vals.len();
+        self.vals = mem::take(&mut self.vals)
+            .into_iter()
+            .filter(|(clock, _)| other.vals.iter().filter(|(c, _)| clock < c).count() == 0)
+            .collect();
+        let dropped = len != self.vals.len();
+
+        let added = other
+            .vals
+            .into_iter()
+            .filter(|(clock, _)| self.vals.iter().filter(|(c, _)| clock < c).count() == 0)
+            .filter(|(clock, _)| self.vals.iter().all(|(c, _)| clock != c))
+            .collect::<Vec<_>>();
+        let changed = dropped || !added.is_empty();
+        self.vals.extend(added);
+        changed
+    }
+
     /// A clock with latest versions of all actors operating on this register
     fn clock(&self) -> VClock<A> {
         self.vals
@@ -215,3 +479,40 @@ impl<V: Val, A: Actor> MVReg<V, A> {
             })
     }
 }
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<V: Val + Arbitrary, A: Actor + Arbitrary> Arbitrary for Op<V, A> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Op::Put {
+            clock: VClock::arbitrary(g),
+            val: V::arbitrary(g),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<V: Val + Arbitrary, A: Actor> ArbitraryOp<A> for MVReg<V, A> {
+    /// Writes an arbitrary value under the given context.
+    fn arbitrary_op<G: Gen>(&self, ctx: AddCtx<A>, g: &mut G) -> Op<V, A> {
+        self.write(V::arbitrary(g), ctx)
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<V: Val + Arbitrary, A: Actor + Arbitrary> Arbitrary for MVReg<V, A> {
+    /// Builds an MVReg from a sequence of writes by arbitrary actors. A write
+    /// marked concurrent is made without seeing the other actors' writes.
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut reg = MVReg::new();
+        for (actor, val, concurrent) in Vec::<(A, V, bool)>::arbitrary(g) {
+            let mut clock = if concurrent {
+                Dot::new(actor.clone(), reg.clock().get(&actor)).into()
+            } else {
+                reg.clock()
+            };
+            clock.apply(clock.inc(actor));
+            reg.apply(Op::Put { clock, val });
+        }
+        reg
+    }
+}
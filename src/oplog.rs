@@ -0,0 +1,602 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::traits::{CmRDT, CvRDT};
+use crate::vclock::{Actor, Dot, VClock};
+
+const LOG_FILE: &str = "ops";
+const LOG_TMP_FILE: &str = "ops.tmp";
+const SNAPSHOT_FILE: &str = "snapshot";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+
+// Every record is framed by its length and a crc32 of its payload.
+const HEADER_LEN: u64 = 8;
+
+/// A file backed, append-only log of Op's along with a snapshot of the state
+/// the logged Op's apply on top of.
+///
+/// Every Op is logged under a dot of the actor that made it, the counter
+/// counts the Op's made by that actor (removes included), so the same Op
+/// has the same dot in every replica's log. Use `next_dot` to get the dot
+/// of an Op made locally.
+///
+/// Taking a snapshot truncates the log. If we crash between writing the
+/// snapshot and truncating the log, the Op's that remain are replayed on
+/// top of the snapshot, which is harmless since Op's are idempotent. A
+/// record that was only partially written when we crashed is dropped when
+/// the log is opened, a damaged record with intact records after it fails
+/// the open with `CorruptRecord` instead.
+///
+/// ``` rust
+/// use crdts::oplog::OpLog;
+/// use crdts::{CmRDT, Orswot};
+///
+/// let dir = std::env::temp_dir().join("crdts-oplog-doc");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let mut log: OpLog<Orswot<String, String>, String> = OpLog::open(&dir).unwrap();
+/// let mut set = log.replay().unwrap();
+///
+/// let op = set.add("apple".to_string(), set.read().derive_add_ctx("A".to_string()));
+/// log.append(log.next_dot("A".to_string()), &op).unwrap();
+/// set.apply(op);
+///
+/// // after a restart
+/// let log: OpLog<Orswot<String, String>, String> = OpLog::open(&dir).unwrap();
+/// assert_eq!(log.replay().unwrap(), set);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct OpLog<T: CmRDT, A: Actor> {
+    dir: PathBuf,
+    file: File,
+    // the dots of every Op logged, including those truncated by a snapshot
+    clock: VClock<A>,
+    // the offset of each record in the log file, by actor then counter
+    index: BTreeMap<A, BTreeMap<u64, u64>>,
+    len: u64,
+    phantom: PhantomData<T>,
+}
+
+impl<T, A> OpLog<T, A>
+where
+    T: CmRDT + Default + Serialize + DeserializeOwned,
+    T::Op: Serialize + DeserializeOwned,
+    A: Actor + Serialize + DeserializeOwned,
+{
+    /// Open the log stored in `dir`, creating it if it doesn't exist.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let clock = match read_snapshot::<T, A>(&dir)? {
+            Some((clock, _)) => clock,
+            None => VClock::new(),
+        };
+
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(LOG_FILE))?;
+
+        let mut log = OpLog {
+            dir,
+            file,
+            clock,
+            index: BTreeMap::new(),
+            len: 0,
+            phantom: PhantomData,
+        };
+        log.recover()?;
+        Ok(log)
+    }
+
+    /// Scan the log building the index, a torn record at the end of the log
+    /// is truncated away and a damaged record before the end is an error.
+    ///
+    /// One pass follows the framing of the records, a damaged record is
+    /// skipped by its length so we can tell whether intact records follow.
+    fn recover(&mut self) -> Result<()> {
+        let mut bytes = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut bytes)?;
+
+        let mut offset = 0;
+        // the end of the last intact record, and the first damaged record
+        let mut end = 0;
+        let mut damaged = None;
+        while offset + HEADER_LEN as usize <= bytes.len() {
+            let next = offset + HEADER_LEN as usize + payload_len(&bytes[offset..]);
+            if next > bytes.len() {
+                break;
+            }
+            match (decode_record::<T, A>(&bytes, offset), damaged) {
+                // a torn write only ever damages the end of the log, an
+                // intact record after a damaged one means it's corruption
+                (Some(_), Some(damaged)) => return Err(Error::CorruptRecord(damaged as u64)),
+                (Some((dot, _, _)), None) => {
+                    self.index_record(dot, offset as u64);
+                    end = next;
+                }
+                (None, _) => {
+                    damaged.get_or_insert(offset);
+                }
+            }
+            offset = next;
+        }
+
+        if end < bytes.len() {
+            self.file.set_len(end as u64)?;
+            self.file.sync_all()?;
+        }
+        self.len = end as u64;
+        Ok(())
+    }
+
+    fn index_record(&mut self, dot: Dot<A>, offset: u64) {
+        self.index
+            .entry(dot.actor.clone())
+            .or_default()
+            .insert(dot.counter, offset);
+        self.clock.apply(dot);
+    }
+
+    /// The dot to log the next Op made by `actor` under
+    pub fn next_dot(&self, actor: A) -> Dot<A> {
+        self.clock.inc(actor)
+    }
+
+    /// The dots of every Op this log has seen, including those that were
+    /// truncated by a snapshot
+    pub fn clock(&self) -> &VClock<A> {
+        &self.clock
+    }
+
+    /// Append an Op under its dot. An Op that was already logged is skipped,
+    /// an Op that skips ahead of the actor's last logged Op is rejected with
+    /// `CausalGap`, the actor's earlier Op's must be logged first.
+    pub fn append(&mut self, dot: Dot<A>, op: &T::Op) -> Result<()> {
+        self.clock.validate_dot(&dot)?;
+        if self.clock.get(&dot.actor) >= dot.counter {
+            return Ok(());
+        }
+
+        let record = encode_record(&dot, op)?;
+        self.file.write_all(&record)?;
+
+        let offset = self.len;
+        self.len += record.len() as u64;
+        self.index_record(dot, offset);
+        Ok(())
+    }
+
+    /// Flush appended Op's to disk
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// The logged Op's, in the order they were appended
+    pub fn ops(&self) -> Result<Vec<(Dot<A>, T::Op)>> {
+        let mut offsets: Vec<u64> = self
+            .index
+            .values()
+            .flat_map(|counters| counters.values().cloned())
+            .collect();
+        offsets.sort();
+        self.read_records(offsets)
+    }
+
+    /// The logged Op's made by `actor` after `counter`, in the order they
+    /// were made. Op's truncated by a snapshot are no longer available.
+    pub fn ops_after(&self, actor: &A, counter: u64) -> Result<Vec<(Dot<A>, T::Op)>> {
+        let offsets = match self.index.get(actor) {
+            Some(counters) => counters
+                .range(counter + 1..)
+                .map(|(_, offset)| *offset)
+                .collect(),
+            None => Vec::new(),
+        };
+        self.read_records(offsets)
+    }
+
+    fn read_records(&self, offsets: Vec<u64>) -> Result<Vec<(Dot<A>, T::Op)>> {
+        let mut file = &self.file;
+        let mut records = Vec::with_capacity(offsets.len());
+        for offset in offsets {
+            let mut header = [0u8; HEADER_LEN as usize];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut header)?;
+
+            let mut bytes = header.to_vec();
+            bytes.resize(HEADER_LEN as usize + payload_len(&header), 0);
+            file.read_exact(&mut bytes[HEADER_LEN as usize..])?;
+            match decode_record::<T, A>(&bytes, 0) {
+                Some((dot, op, _)) => records.push((dot, op)),
+                None => {
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "a record changed after it was appended",
+                    )))
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    /// Rebuild the state by replaying the logged Op's on top of the snapshot
+    pub fn replay(&self) -> Result<T> {
+        let mut state = match read_snapshot::<T, A>(&self.dir)? {
+            Some((_, state)) => state,
+            None => T::default(),
+        };
+        for (_, op) in self.ops()? {
+            state.apply(op);
+        }
+        Ok(state)
+    }
+
+    /// Persist `state` as the new snapshot and truncate the log.
+    ///
+    /// `state` must have every logged Op applied to it, Op's logged
+    /// after the snapshot is taken are replayed on top of it.
+    pub fn snapshot(&mut self, state: &T) -> Result<()> {
+        self.write_snapshot(state)?;
+        self.file.set_len(0)?;
+        self.file.sync_all()?;
+        self.index.clear();
+        self.len = 0;
+        Ok(())
+    }
+
+    /// Persist `state` as the new snapshot and drop the logged Op's `clock`
+    /// has seen, the rest stay available to `ops_after`.
+    ///
+    /// `state` must have every logged Op applied to it. The Op's that are
+    /// kept are replayed on top of the snapshot, which is harmless since
+    /// Op's are idempotent. Nothing is written if `clock` has seen none of
+    /// the logged Op's.
+    pub fn trim(&mut self, state: &T, clock: &VClock<A>) -> Result<()> {
+        let seen_any = self
+            .index
+            .iter()
+            .any(|(actor, counters)| counters.range(..=clock.get(actor)).next().is_some());
+        if !seen_any {
+            return Ok(());
+        }
+
+        let kept: Vec<_> = self
+            .ops()?
+            .into_iter()
+            .filter(|(dot, _)| clock.get(&dot.actor) < dot.counter)
+            .collect();
+        self.write_snapshot(state)?;
+
+        let tmp = self.dir.join(LOG_TMP_FILE);
+        let mut offsets = Vec::with_capacity(kept.len());
+        let mut len = 0;
+        {
+            let mut file = File::create(&tmp)?;
+            for (dot, op) in kept.iter() {
+                let record = encode_record(dot, op)?;
+                file.write_all(&record)?;
+                offsets.push((dot.clone(), len));
+                len += record.len() as u64;
+            }
+            file.sync_all()?;
+        }
+        // a crash before the rename leaves the whole log, which replays on
+        // top of the new snapshot just as well
+        fs::rename(&tmp, self.dir.join(LOG_FILE))?;
+        sync_dir(&self.dir)?;
+
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(self.dir.join(LOG_FILE))?;
+        self.index.clear();
+        for (dot, offset) in offsets {
+            self.index_record(dot, offset);
+        }
+        self.len = len;
+        Ok(())
+    }
+
+    /// Persist `state`, which has merged in the state of a replica that has
+    /// seen `clock`, as the new snapshot.
+    ///
+    /// The dots of `clock` count as logged from then on, though the Op's
+    /// they stand for were never appended. The logged Op's are kept and
+    /// replay on top of the snapshot.
+    pub fn absorb(&mut self, state: &T, clock: &VClock<A>) -> Result<()> {
+        self.clock.merge(clock.clone());
+        self.write_snapshot(state)
+    }
+
+    fn write_snapshot(&self, state: &T) -> Result<()> {
+        let payload = bincode::serialize(&(&self.clock, state))?;
+        let tmp = self.dir.join(SNAPSHOT_TMP_FILE);
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(&(payload.len() as u32).to_le_bytes())?;
+            file.write_all(&crc32fast::hash(&payload).to_le_bytes())?;
+            file.write_all(&payload)?;
+            file.sync_all()?;
+        }
+        // the rename is atomic, a crash leaves either the old or the new snapshot
+        fs::rename(&tmp, self.dir.join(SNAPSHOT_FILE))?;
+        // and it's only durable once the directory is synced, otherwise the
+        // old snapshot may come back after we've truncated the log
+        sync_dir(&self.dir)?;
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+// other platforms can't open a directory to sync it
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn encode_record<A: Serialize, Op: Serialize>(dot: &Dot<A>, op: &Op) -> Result<Vec<u8>> {
+    let payload = bincode::serialize(&(dot, op))?;
+    let mut record = Vec::with_capacity(HEADER_LEN as usize + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+fn payload_len(header: &[u8]) -> usize {
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&header[0..4]);
+    u32::from_le_bytes(len) as usize
+}
+
+/// Decode the record at `offset`, returning it along with the offset of the
+/// next record. None if the record is incomplete or fails its checksum.
+fn decode_record<T, A>(bytes: &[u8], offset: usize) -> Option<(Dot<A>, T::Op, usize)>
+where
+    T: CmRDT,
+    T::Op: DeserializeOwned,
+    A: Actor + DeserializeOwned,
+{
+    let payload = checked_payload(&bytes[offset..])?;
+    let (dot, op) = bincode::deserialize(payload).ok()?;
+    Some((dot, op, offset + HEADER_LEN as usize + payload.len()))
+}
+
+fn checked_payload(bytes: &[u8]) -> Option<&[u8]> {
+    if bytes.len() < HEADER_LEN as usize {
+        return None;
+    }
+    let len = payload_len(bytes);
+    let payload = bytes.get(HEADER_LEN as usize..HEADER_LEN as usize + len)?;
+
+    let mut crc = [0u8; 4];
+    crc.copy_from_slice(&bytes[4..8]);
+    if crc32fast::hash(payload) == u32::from_le_bytes(crc) {
+        Some(payload)
+    } else {
+        None
+    }
+}
+
+fn read_snapshot<T, A>(dir: &Path) -> Result<Option<(VClock<A>, T)>>
+where
+    T: DeserializeOwned,
+    A: Actor + DeserializeOwned,
+{
+    let bytes = match fs::read(dir.join(SNAPSHOT_FILE)) {
+        Ok(bytes) => bytes,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let payload = checked_payload(&bytes).ok_or(Error::CorruptSnapshot)?;
+    Ok(Some(bincode::deserialize(payload)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::Orswot;
+
+    type TLog = OpLog<Orswot<u8, u8>, u8>;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("crdts-oplog-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn add(log: &mut TLog, set: &mut Orswot<u8, u8>, actor: u8, member: u8) {
+        let op = set.add(member, set.read().derive_add_ctx(actor));
+        log.append(log.next_dot(actor), &op).unwrap();
+        set.apply(op);
+    }
+
+    #[test]
+    fn test_replay_after_reopen() {
+        let dir = temp_dir("replay");
+        let mut log = TLog::open(&dir).unwrap();
+        let mut set = Orswot::new();
+        add(&mut log, &mut set, 1, 10);
+        add(&mut log, &mut set, 2, 20);
+        let op = set.rm(10, set.contains(&10).derive_rm_ctx());
+        log.append(log.next_dot(1), &op).unwrap();
+        set.apply(op);
+        drop(log);
+
+        let log = TLog::open(&dir).unwrap();
+        assert_eq!(log.replay().unwrap(), set);
+        assert_eq!(log.clock().get(&1), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_truncates_log() {
+        let dir = temp_dir("snapshot");
+        let mut log = TLog::open(&dir).unwrap();
+        let mut set = Orswot::new();
+        add(&mut log, &mut set, 1, 10);
+        add(&mut log, &mut set, 1, 11);
+        log.snapshot(&set).unwrap();
+        assert!(log.ops().unwrap().is_empty());
+
+        add(&mut log, &mut set, 1, 12);
+        drop(log);
+
+        let log = TLog::open(&dir).unwrap();
+        assert_eq!(log.ops().unwrap().len(), 1);
+        assert_eq!(log.next_dot(1), Dot::new(1, 4));
+        assert_eq!(log.replay().unwrap(), set);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trim_keeps_unseen_ops() {
+        let dir = temp_dir("trim");
+        let mut log = TLog::open(&dir).unwrap();
+        let mut set = Orswot::new();
+        add(&mut log, &mut set, 1, 10);
+        add(&mut log, &mut set, 2, 20);
+        add(&mut log, &mut set, 1, 11);
+        log.trim(&set, &Dot::new(1, 1).into()).unwrap();
+        assert_eq!(log.ops().unwrap().len(), 2);
+        assert_eq!(log.ops_after(&1, 0).unwrap()[0].0, Dot::new(1, 2));
+
+        add(&mut log, &mut set, 2, 21);
+        drop(log);
+
+        let log = TLog::open(&dir).unwrap();
+        assert_eq!(log.ops().unwrap().len(), 3);
+        assert_eq!(log.clock().get(&1), 2);
+        assert_eq!(log.replay().unwrap(), set);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_torn_record_is_dropped() {
+        let dir = temp_dir("torn");
+        let mut log = TLog::open(&dir).unwrap();
+        let mut set = Orswot::new();
+        add(&mut log, &mut set, 1, 10);
+        let before_torn = set.clone();
+        add(&mut log, &mut set, 1, 11);
+        drop(log);
+
+        // chop the last byte off, as if we crashed mid-write
+        let file = OpenOptions::new()
+            .write(true)
+            .open(dir.join(LOG_FILE))
+            .unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - 1).unwrap();
+
+        let mut log = TLog::open(&dir).unwrap();
+        assert_eq!(log.replay().unwrap(), before_torn);
+
+        // the log is usable again after the torn record is dropped
+        let mut set = before_torn;
+        add(&mut log, &mut set, 1, 12);
+        assert_eq!(TLog::open(&dir).unwrap().replay().unwrap(), set);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_garbage_tail_is_dropped() {
+        let dir = temp_dir("garbage-tail");
+        let mut log = TLog::open(&dir).unwrap();
+        let mut set = Orswot::new();
+        add(&mut log, &mut set, 1, 10);
+        drop(log);
+
+        // the file grew but the record never made it to disk
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.join(LOG_FILE))
+            .unwrap();
+        file.write_all(&[0; 64]).unwrap();
+
+        let log = TLog::open(&dir).unwrap();
+        assert_eq!(log.replay().unwrap(), set);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_record_mid_log_is_an_error() {
+        let dir = temp_dir("corrupt");
+        let mut log = TLog::open(&dir).unwrap();
+        let mut set = Orswot::new();
+        for member in 0..3 {
+            add(&mut log, &mut set, 1, member);
+        }
+        drop(log);
+
+        // flip a bit in the payload of the first record
+        let path = dir.join(LOG_FILE);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[HEADER_LEN as usize] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+
+        match TLog::open(&dir) {
+            Err(Error::CorruptRecord(0)) => (),
+            other => panic!("expected a corrupt record, got {:?}", other),
+        }
+        // nothing was truncated
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_ops_after() {
+        let dir = temp_dir("ops-after");
+        let mut log = TLog::open(&dir).unwrap();
+        let mut set = Orswot::new();
+        for member in 0..5 {
+            add(&mut log, &mut set, 1, member);
+            add(&mut log, &mut set, 2, member);
+        }
+
+        let counters: Vec<u64> = log
+            .ops_after(&1, 3)
+            .unwrap()
+            .into_iter()
+            .map(|(dot, _)| dot.counter)
+            .collect();
+        assert_eq!(counters, vec![4, 5]);
+        assert!(log.ops_after(&3, 0).unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_append_is_idempotent_and_rejects_gaps() {
+        let dir = temp_dir("append");
+        let mut log = TLog::open(&dir).unwrap();
+        let set: Orswot<u8, u8> = Orswot::new();
+        let op = set.add(1, set.read().derive_add_ctx(1));
+
+        log.append(Dot::new(1, 1), &op).unwrap();
+        log.append(Dot::new(1, 1), &op).unwrap();
+        assert_eq!(log.ops().unwrap().len(), 1);
+
+        match log.append(Dot::new(1, 3), &op) {
+            Err(Error::CausalGap) => (),
+            other => panic!("expected a causal gap, got {:?}", other),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
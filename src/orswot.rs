@@ -1,15 +1,29 @@
+use std::cmp::Ordering;
 /// Observed-Remove Set With Out Tombstones (ORSWOT), ported directly from `riak_dt`.
 use std::collections::{HashMap, HashSet};
-use std::cmp::Ordering;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::iter::{once, FromIterator};
 use std::mem;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+use crate::canonical;
+use crate::codec::{Compact, Decoder, Encoder};
 use crate::ctx::{AddCtx, ReadCtx, RmCtx};
-use crate::traits::{Causal, CmRDT, CvRDT};
+use crate::error::{Error, Result};
+use crate::intern::{ActorTable, Intern, Interned};
+use crate::retire::{ActorRemap, Remap};
+use crate::riak::{self, Etf, RiakDt, Term};
+#[cfg(any(test, feature = "quickcheck"))]
+use crate::traits::ArbitraryOp;
+use crate::traits::{
+    Canonical, Causal, CmRDT, CvRDT, Lattice, TrackChanges, Validate, ValidateUnder, Witness,
+};
 use crate::vclock::{Actor, Dot, VClock};
 
 /// Trait bound alias for members in a set
@@ -25,6 +39,37 @@ pub struct Orswot<M: Member, A: Actor> {
     pub(crate) deferred: HashMap<VClock<A>, HashSet<M>>,
 }
 
+/// The members added to and removed from an Orswot, see `TrackChanges`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changes<M: Member> {
+    /// Members that were added
+    pub added: HashSet<M>,
+    /// Members that were removed
+    pub removed: HashSet<M>,
+}
+
+impl<M: Member> Changes<M> {
+    fn none() -> Self {
+        Changes {
+            added: HashSet::new(),
+            removed: HashSet::new(),
+        }
+    }
+
+    // a member that is removed and added back again has not changed
+    fn record_add(&mut self, member: &M) {
+        if !self.removed.remove(member) {
+            self.added.insert(member.clone());
+        }
+    }
+
+    fn record_rm(&mut self, member: &M) {
+        if !self.added.remove(member) {
+            self.removed.insert(member.clone());
+        }
+    }
+}
+
 /// Op's define an edit to an Orswot, Op's must be replayed in the exact order
 /// they were produced to guarantee convergence.
 ///
@@ -57,6 +102,49 @@ impl<M: Member, A: Actor> CmRDT for Orswot<M, A> {
     type Op = Op<M, A>;
 
     fn apply(&mut self, op: Self::Op) {
+        self.apply_tracked(op, None)
+    }
+
+    fn validate_op(&self, op: &Self::Op) -> Result<()> {
+        self.validate_op_under(op, &self.clock)
+    }
+}
+
+impl<M: Member, A: Actor> ValidateUnder<A> for Orswot<M, A> {
+    fn validate_op_under(&self, op: &Self::Op, clock: &VClock<A>) -> Result<()> {
+        match op {
+            Op::Add { dot, members } => {
+                clock.validate_dot(dot)?;
+
+                // a dot we've seen before must have added these same members
+                let seen = clock.get(&dot.actor) >= dot.counter;
+                let reused = seen
+                    && self.entries.iter().any(|(member, clock)| {
+                        clock.get(&dot.actor) == dot.counter && !members.contains(member)
+                    });
+                if reused {
+                    Err(Error::ConflictingMarker)
+                } else {
+                    Ok(())
+                }
+            }
+            Op::Rm {
+                clock: rm_clock, ..
+            } => clock.validate_rm_clock(rm_clock),
+        }
+    }
+}
+
+impl<M: Member, A: Actor> CvRDT for Orswot<M, A> {
+    /// Merge combines another `Orswot` with this one.
+    fn merge(&mut self, other: Self) {
+        self.merge_tracked(other, None)
+    }
+}
+
+impl<M: Member, A: Actor> Orswot<M, A> {
+    /// `apply`, recording the members it adds and removes if asked to
+    fn apply_tracked(&mut self, op: Op<M, A>, mut changes: Option<&mut Changes<M>>) {
         match op {
             Op::Add { dot, members } => {
                 if self.clock.get(&dot.actor) >= dot.counter {
@@ -65,23 +153,26 @@ impl<M: Member, A: Actor> CmRDT for Orswot<M, A> {
                 }
 
                 for member in members {
+                    if let Some(changes) = changes.as_deref_mut() {
+                        if !self.entries.contains_key(&member) {
+                            changes.record_add(&member);
+                        }
+                    }
                     let member_vclock = self.entries.entry(member).or_default();
                     member_vclock.apply(dot.clone());
                 }
 
                 self.clock.apply(dot);
-                self.apply_deferred();
+                self.apply_deferred(changes);
             }
             Op::Rm { clock, members } => {
-                self.apply_rm(members, clock);
+                self.apply_rm(members, clock, changes);
             }
         }
     }
-}
 
-impl<M: Member, A: Actor> CvRDT for Orswot<M, A> {
-    /// Merge combines another `Orswot` with this one.
-    fn merge(&mut self, other: Self) {
+    /// `merge`, recording the members it adds and removes if asked to
+    fn merge_tracked(&mut self, other: Self, mut changes: Option<&mut Changes<M>>) {
         self.entries = mem::replace(&mut self.entries, HashMap::new())
             .into_iter()
             .filter_map(|(entry, mut clock)| {
@@ -91,6 +182,9 @@ impl<M: Member, A: Actor> CvRDT for Orswot<M, A> {
                     //  2. hasn't seen it
                     if other.clock >= clock {
                         // other has seen this entry and dropped it
+                        if let Some(changes) = changes.as_deref_mut() {
+                            changes.record_rm(&entry);
+                        }
                         None
                     } else {
                         // the other map has not seen this version of this
@@ -117,6 +211,9 @@ impl<M: Member, A: Actor> CvRDT for Orswot<M, A> {
                 if common.is_empty() {
                     // both maps had seen each others entry and removed them
                     self.entries.remove(&entry).unwrap();
+                    if let Some(changes) = changes.as_deref_mut() {
+                        changes.record_rm(&entry);
+                    }
                 } else {
                     // we should not drop, as there is information still tracked in
                     // the common clock.
@@ -133,6 +230,9 @@ impl<M: Member, A: Actor> CvRDT for Orswot<M, A> {
                     // but first, we have to remove the information on this entry
                     // that we have seen and deleted
                     clock.forget(&self.clock);
+                    if let Some(changes) = changes.as_deref_mut() {
+                        changes.record_add(&entry);
+                    }
                     self.entries.insert(entry, clock);
                 }
             }
@@ -140,12 +240,64 @@ impl<M: Member, A: Actor> CvRDT for Orswot<M, A> {
 
         // merge deferred removals
         for (rm_clock, members) in other.deferred {
-            self.apply_rm(members, rm_clock);
+            self.apply_rm(members, rm_clock, changes.as_deref_mut());
         }
 
         self.clock.merge(other.clock);
 
-        self.apply_deferred();
+        self.apply_deferred(changes);
+    }
+}
+
+impl<M: Member, A: Actor> Lattice for Orswot<M, A> {
+    /// Walks the cases of `merge` with `self` merged into `other`, each has
+    /// to leave `other` as it is.
+    fn leq(&self, other: &Self) -> bool {
+        if !self.clock.leq(&other.clock) {
+            return false;
+        }
+
+        // other has our entries, or has seen and dropped them
+        let entries_seen = self
+            .entries
+            .iter()
+            .all(|(member, clock)| other.entries.contains_key(member) || clock.leq(&other.clock));
+
+        // each dot of other's entries is one we haven't seen, or one our
+        // entry still has
+        let entries_kept = other.entries.iter().all(|(member, their_clock)| {
+            let our_clock = self.entries.get(member);
+            their_clock.iter().all(|dot| {
+                dot.counter > self.clock.get(dot.actor)
+                    || our_clock.map(|c| c.get(dot.actor)) == Some(dot.counter)
+            })
+        });
+
+        // our deferred removes don't touch other's entries, and other has
+        // seen them or deferred them too
+        let deferred_seen = self.deferred.iter().all(|(clock, members)| {
+            let untouched = members
+                .iter()
+                .all(|member| match other.entries.get(member) {
+                    Some(their_clock) => their_clock
+                        .iter()
+                        .all(|dot| dot.counter > clock.get(dot.actor)),
+                    None => true,
+                });
+            let deferred = clock.leq(&other.clock)
+                || other
+                    .deferred
+                    .get(clock)
+                    .map(|theirs| theirs.is_superset(members))
+                    .unwrap_or(false);
+            untouched && deferred
+        });
+
+        entries_seen && entries_kept && deferred_seen
+    }
+
+    fn is_bottom(&self) -> bool {
+        self.clock.is_empty() && self.entries.is_empty() && self.deferred.is_empty()
     }
 }
 
@@ -183,6 +335,280 @@ impl<M: Member, A: Actor> Causal<A> for Orswot<M, A> {
     }
 }
 
+impl<M: Member + Serialize + DeserializeOwned, A: Actor> Compact<A> for Orswot<M, A> {
+    fn encode(&self, enc: &mut Encoder<A>) {
+        enc.clock(&self.clock);
+        enc.u64(self.entries.len() as u64);
+        for (member, clock) in self.entries.iter() {
+            enc.value(member);
+            enc.clock_under(clock, &self.clock);
+        }
+        enc.u64(self.deferred.len() as u64);
+        for (clock, members) in self.deferred.iter() {
+            enc.clock_under(clock, &self.clock);
+            enc.value(members);
+        }
+    }
+
+    fn decode(dec: &mut Decoder<A>) -> Result<Self> {
+        let clock = dec.clock()?;
+        let mut entries = HashMap::new();
+        for _ in 0..dec.seq_len()? {
+            let member = dec.value()?;
+            entries.insert(member, dec.clock_under(&clock)?);
+        }
+        let mut deferred = HashMap::new();
+        for _ in 0..dec.seq_len()? {
+            let rm_clock = dec.clock_under(&clock)?;
+            deferred.insert(rm_clock, dec.value()?);
+        }
+        Ok(Orswot {
+            clock,
+            entries,
+            deferred,
+        })
+    }
+}
+
+impl<M: Member + Serialize + DeserializeOwned, A: Actor> Compact<A> for Op<M, A> {
+    fn encode(&self, enc: &mut Encoder<A>) {
+        match self {
+            Op::Add { dot, members } => {
+                enc.byte(0);
+                enc.dot(dot);
+                enc.value(members);
+            }
+            Op::Rm { clock, members } => {
+                enc.byte(1);
+                enc.clock(clock);
+                enc.value(members);
+            }
+        }
+    }
+
+    fn decode(dec: &mut Decoder<A>) -> Result<Self> {
+        match dec.byte()? {
+            0 => Ok(Op::Add {
+                dot: dec.dot()?,
+                members: dec.value()?,
+            }),
+            1 => Ok(Op::Rm {
+                clock: dec.clock()?,
+                members: dec.value()?,
+            }),
+            tag => Err(Error::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<M: Member, A: Actor> Intern<A> for Orswot<M, Interned<A>> {
+    fn intern(&mut self, table: &mut ActorTable<A>) {
+        self.clock.intern(table);
+        for dots in self.entries.values_mut() {
+            dots.intern(table);
+        }
+        self.deferred = mem::take(&mut self.deferred)
+            .into_iter()
+            .map(|(mut clock, members)| {
+                clock.intern(table);
+                (clock, members)
+            })
+            .collect();
+    }
+}
+
+impl<M: Member, A: Actor> Intern<A> for Op<M, Interned<A>> {
+    fn intern(&mut self, table: &mut ActorTable<A>) {
+        match self {
+            Op::Add { dot, .. } => dot.intern(table),
+            Op::Rm { clock, .. } => clock.intern(table),
+        }
+    }
+}
+
+impl<M: Member, A: Actor> Remap<A> for Orswot<M, A> {
+    fn remap(&mut self, remap: &ActorRemap<A>) -> Result<()> {
+        // the entries are behind our clock, the deferred clocks may be ahead
+        remap.check(&self.clock)?;
+        for clock in self.deferred.keys() {
+            remap.check(clock)?;
+        }
+
+        self.clock.remap(remap)?;
+        for dots in self.entries.values_mut() {
+            dots.remap(remap)?;
+        }
+        let mut deferred: HashMap<VClock<A>, HashSet<M>> = HashMap::new();
+        for (mut clock, members) in mem::take(&mut self.deferred) {
+            clock.remap(remap)?;
+            deferred.entry(clock).or_default().extend(members);
+        }
+        self.deferred = deferred;
+        Ok(())
+    }
+}
+
+impl<M: Member, A: Actor> Remap<A> for Op<M, A> {
+    fn remap(&mut self, remap: &ActorRemap<A>) -> Result<()> {
+        match self {
+            Op::Add { dot, .. } => dot.remap(remap),
+            Op::Rm { clock, .. } => clock.remap(remap),
+        }
+    }
+}
+
+/// riak_dt_orswot's state is `{Clock, Entries, Deferred}`, the entries map
+/// members to the dots that added them and the deferred removes map clocks to
+/// lists of members.
+impl<M: Member + Etf, A: Actor + Etf> Etf for Orswot<M, A> {
+    fn to_term(&self) -> Term {
+        let entries = riak::orddict(
+            self.entries
+                .iter()
+                .map(|(member, dots)| (member.to_term(), dots.to_term())),
+        );
+        let deferred = riak::orddict(self.deferred.iter().map(|(clock, members)| {
+            let members = riak::sorted_list(members.iter().map(Etf::to_term));
+            (clock.to_term(), members)
+        }));
+        Term::Tuple(vec![self.clock.to_term(), entries, deferred])
+    }
+
+    fn from_term(term: Term) -> Result<Self> {
+        let (clock, entries, deferred) =
+            riak::tuple3(term, "an orswot {Clock, Entries, Deferred}")?;
+        let mut state = Orswot::new();
+        state.clock = VClock::from_term(clock)?;
+        for (member, dots) in riak::dict_pairs(entries)? {
+            state
+                .entries
+                .insert(M::from_term(member)?, VClock::from_term(dots)?);
+        }
+        for (clock, members) in riak::dict_pairs(deferred)? {
+            let members = riak::list(members, "a list of deferred members")?
+                .into_iter()
+                .map(M::from_term)
+                .collect::<Result<HashSet<M>>>()?;
+            state.deferred.insert(VClock::from_term(clock)?, members);
+        }
+        Ok(state)
+    }
+}
+
+/// Layout 1 keeps the entries and deferred removes in orddicts, layout 2 in
+/// dicts, we read both.
+impl<M: Member + Etf, A: Actor + Etf> RiakDt for Orswot<M, A> {
+    const MODULE: &'static str = "riak_dt_orswot";
+    const TAG: u8 = 75;
+    const VERSION: u8 = 1;
+
+    fn from_versioned_term(version: u8, term: Term) -> Result<Self> {
+        match version {
+            1 | 2 => Self::from_term(term),
+            _ => Err(Error::UnsupportedVersion(version)),
+        }
+    }
+}
+
+impl<M: Member + Serialize, A: Actor + Serialize> Canonical for Orswot<M, A> {
+    fn canonical_bytes(&self) -> Vec<u8> {
+        let mut out = canonical::encode(&self.clock);
+        canonical::write_sorted(
+            &mut out,
+            self.entries
+                .iter()
+                .map(|entry| canonical::encode(&entry))
+                .collect(),
+        );
+        canonical::write_sorted(
+            &mut out,
+            self.deferred
+                .iter()
+                .map(|(clock, members)| {
+                    let mut elem = canonical::encode(clock);
+                    canonical::write_sorted(
+                        &mut elem,
+                        members.iter().map(canonical::encode).collect(),
+                    );
+                    elem
+                })
+                .collect(),
+        );
+        out
+    }
+}
+
+impl<M: Member, A: Actor> Validate for Orswot<M, A> {
+    fn validate(&self) -> Result<()> {
+        self.clock.validate()?;
+        for clock in self.entries.values() {
+            clock.validate()?;
+            match clock.partial_cmp(&self.clock) {
+                _ if clock.is_empty() => {
+                    return Err(Error::InvalidState(
+                        "Orswot members must have non-empty clocks",
+                    ));
+                }
+                None | Some(Ordering::Greater) => {
+                    return Err(Error::InvalidState(
+                        "Orswot member clocks must be dominated by the Orswot clock",
+                    ));
+                }
+                _ => (),
+            }
+        }
+        for clock in self.deferred.keys() {
+            clock.validate()?;
+            if clock <= &self.clock {
+                return Err(Error::InvalidState(
+                    "Orswot deferred removes must not be dominated by the Orswot clock",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<M: Member, A: Actor> TrackChanges for Orswot<M, A> {
+    type Changes = Changes<M>;
+
+    fn changes_since(&self, before: &Self) -> Self::Changes {
+        let added = self
+            .entries
+            .keys()
+            .filter(|member| !before.entries.contains_key(member))
+            .cloned()
+            .collect();
+        let removed = before
+            .entries
+            .keys()
+            .filter(|member| !self.entries.contains_key(member))
+            .cloned()
+            .collect();
+        Changes { added, removed }
+    }
+    fn apply_with_changes(&mut self, op: Self::Op) -> Self::Changes {
+        let mut changes = Changes::none();
+        self.apply_tracked(op, Some(&mut changes));
+        changes
+    }
+
+    fn merge_with_changes(&mut self, other: Self) -> Self::Changes {
+        let mut changes = Changes::none();
+        self.merge_tracked(other, Some(&mut changes));
+        changes
+    }
+}
+
+impl<M: Member, A: Actor> Witness<A> for Orswot<M, A> {
+    fn witness(&mut self, dot: &Dot<A>) {
+        for member_clock in self.entries.values_mut() {
+            member_clock.witness(dot);
+        }
+        self.clock.witness(dot);
+    }
+}
+
 impl<M: Member, A: Actor> Orswot<M, A> {
     /// Returns a new `Orswot` instance.
     pub fn new() -> Self {
@@ -227,13 +653,46 @@ impl<M: Member, A: Actor> Orswot<M, A> {
         }
     }
 
+    /// Remove all members seen by the given ctx.
+    ///
+    /// Members added concurrently to this clear (i.e. not seen by the ctx)
+    /// will survive the clear.
+    pub fn clear(&self, ctx: RmCtx<A>) -> Op<M, A> {
+        self.rm_all(self.entries.keys().cloned(), ctx)
+    }
+
+    /// Generate an Op that undoes `op`, given the read of the set from before
+    /// `op` was applied.
+    ///
+    /// Undoing an add removes the members that were not present before, but
+    /// only the dot of the add is removed so concurrent adds of the same
+    /// members will survive. Undoing a remove re-adds the removed members
+    /// that were present before under the dot of `ctx`.
+    pub fn undo(&self, op: &Op<M, A>, before: ReadCtx<HashSet<M>, A>, ctx: AddCtx<A>) -> Op<M, A> {
+        match op {
+            Op::Add { dot, members } => Op::Rm {
+                clock: dot.clone().into(),
+                members: members.difference(&before.val).cloned().collect(),
+            },
+            Op::Rm { members, .. } => self.add_all(members.intersection(&before.val).cloned(), ctx),
+        }
+    }
+
     /// Remove a member using a witnessing clock.
-    fn apply_rm(&mut self, members: HashSet<M>, clock: VClock<A>) {
+    fn apply_rm(
+        &mut self,
+        members: HashSet<M>,
+        clock: VClock<A>,
+        mut changes: Option<&mut Changes<M>>,
+    ) {
         for member in members.iter() {
             if let Some(member_clock) = self.entries.get_mut(&member) {
                 member_clock.forget(&clock);
                 if member_clock.is_empty() {
                     self.entries.remove(&member);
+                    if let Some(changes) = changes.as_deref_mut() {
+                        changes.record_rm(member);
+                    }
                 }
             }
         }
@@ -270,6 +729,42 @@ impl<M: Member, A: Actor> Orswot<M, A> {
         }
     }
 
+    /// Returns a delta that brings `older` up to date, merging the delta into
+    /// `older` has the same effect as merging this entire Orswot.
+    ///
+    /// A remove leaves no trace in the clock, so we find the members removed
+    /// since `older` by comparing its entries with ours. The delta's clock
+    /// only covers the actors with dots `older` hasn't seen or with dots that
+    /// differ between the two, and only our entries holding dots of those
+    /// actors are sent, `older` already has the rest.
+    pub fn diff(&self, older: &Self) -> Self {
+        let mut covered = self.clock.clone_without(&older.clock);
+        for (member, older_clock) in older.entries.iter() {
+            if self.entries.get(member) != Some(older_clock) {
+                covered.merge(older_clock.clone());
+                covered.merge(self.entries.get(member).cloned().unwrap_or_default());
+            }
+        }
+
+        let entries = self
+            .entries
+            .iter()
+            .filter(|(member, clock)| {
+                older.entries.get(member) != Some(clock)
+                    || clock.iter().any(|dot| covered.get(dot.actor) > 0)
+            })
+            .map(|(member, clock)| (member.clone(), clock.clone()))
+            .collect();
+
+        let mut clock = self.clock.clone();
+        clock.retain_actors(&covered);
+        Self {
+            clock,
+            entries,
+            deferred: self.deferred.clone(),
+        }
+    }
+
     /// Retrieve the current read context
     pub fn read_ctx(&self) -> ReadCtx<(), A> {
         ReadCtx {
@@ -279,18 +774,65 @@ impl<M: Member, A: Actor> Orswot<M, A> {
         }
     }
 
-    fn apply_deferred(&mut self) {
+    fn apply_deferred(&mut self, mut changes: Option<&mut Changes<M>>) {
         let deferred = mem::replace(&mut self.deferred, HashMap::new());
         for (clock, entries) in deferred.into_iter() {
-            self.apply_rm(entries, clock)
+            self.apply_rm(entries, clock, changes.as_deref_mut())
         }
     }
 }
 
+#[cfg(any(test, feature = "quickcheck"))]
+impl<M: Member + Arbitrary, A: Actor + Arbitrary> Arbitrary for Op<M, A> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        if bool::arbitrary(g) {
+            Op::Add {
+                dot: Dot::arbitrary(g),
+                members: HashSet::arbitrary(g),
+            }
+        } else {
+            Op::Rm {
+                clock: VClock::arbitrary(g),
+                members: HashSet::arbitrary(g),
+            }
+        }
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<M: Member + Arbitrary, A: Actor> ArbitraryOp<A> for Orswot<M, A> {
+    /// Adds an arbitrary member under the given context or removes one
+    /// with the context it was last read with.
+    fn arbitrary_op<G: Gen>(&self, ctx: AddCtx<A>, g: &mut G) -> Op<M, A> {
+        let member = M::arbitrary(g);
+        if bool::arbitrary(g) {
+            self.add(member, ctx)
+        } else {
+            self.rm(member.clone(), self.contains(&member).derive_rm_ctx())
+        }
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<M: Member + Arbitrary, A: Actor + Arbitrary> Arbitrary for Orswot<M, A> {
+    /// Builds an Orswot from a sequence of adds and removes by arbitrary actors.
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut orswot = Orswot::new();
+        for (actor, member, is_add) in Vec::<(A, M, bool)>::arbitrary(g) {
+            let op = if is_add {
+                orswot.add(member, orswot.read().derive_add_ctx(actor))
+            } else {
+                orswot.rm(member.clone(), orswot.contains(&member).derive_rm_ctx())
+            };
+            orswot.apply(op);
+        }
+        orswot
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    extern crate rand;
 
     #[test]
     // a bug found with rust quickcheck where deferred operations
@@ -393,4 +935,34 @@ mod tests {
         a.merge(c);
         assert!(a.read().val.is_empty());
     }
+
+    #[test]
+    fn test_canonical_bytes_ignore_insertion_order() {
+        let mut a: Orswot<u16, u8> = Orswot::new();
+        let mut b: Orswot<u16, u8> = Orswot::new();
+        for member in 0..100 {
+            a.apply(a.add(member, a.read().derive_add_ctx(1)));
+        }
+        for member in (0..100).rev() {
+            b.apply(b.add(member, b.read().derive_add_ctx(2)));
+        }
+        // a deferred remove of members neither side has seen yet
+        let mut c = a.clone();
+        c.merge(b.clone());
+        let rm = c.rm(7, c.contains(&7).derive_rm_ctx());
+
+        let mut ab = a.clone();
+        ab.apply(rm.clone());
+        ab.merge(b.clone());
+        let mut ba = b;
+        ba.apply(rm);
+        ba.merge(a);
+
+        assert_eq!(ab, ba);
+        assert_eq!(ab.canonical_bytes(), ba.canonical_bytes());
+        assert_eq!(ab.digest(), ba.digest());
+
+        let decoded: Orswot<u16, u8> = bincode::deserialize(&ab.canonical_bytes()).unwrap();
+        assert_eq!(decoded, ab);
+    }
 }
@@ -1,8 +1,24 @@
+use std::collections::BTreeSet;
+
 use num_bigint::BigInt;
 use serde::{Deserialize, Serialize};
 
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+use crate::canonical;
+#[cfg(any(test, feature = "quickcheck"))]
+use crate::ctx::AddCtx;
+use crate::error::Result;
 use crate::gcounter::GCounter;
-use crate::traits::{Causal, CmRDT, CvRDT};
+use crate::intern::{ActorTable, Intern, Interned};
+use crate::retire::{ActorRemap, Fold, Remap, RetireActor};
+use crate::riak::{self, Etf, RiakDt, Term};
+#[cfg(any(test, feature = "quickcheck"))]
+use crate::traits::ArbitraryOp;
+use crate::traits::{
+    Canonical, Causal, CmRDT, CvRDT, Lattice, Meet, TrackChanges, Validate, ValidateUnder, Witness,
+};
 use crate::vclock::{Actor, Dot, VClock};
 
 /// `PNCounter` allows the counter to be both incremented and decremented
@@ -27,8 +43,8 @@ use crate::vclock::{Actor, Dot, VClock};
 /// ```
 #[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
 pub struct PNCounter<A: Actor> {
-    p: GCounter<A>,
-    n: GCounter<A>,
+    pub(crate) p: GCounter<A>,
+    pub(crate) n: GCounter<A>,
 }
 
 /// The Direction of an Op.
@@ -50,6 +66,16 @@ pub struct Op<A: Actor> {
     pub dir: Dir,
 }
 
+/// The Op retiring an actor, folding its increments and its decrements into
+/// the successor's.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Retire<A> {
+    /// Folds the increments
+    pub p: Fold<A>,
+    /// Folds the decrements
+    pub n: Fold<A>,
+}
+
 impl<A: Actor> Default for PNCounter<A> {
     fn default() -> Self {
         Self::new()
@@ -65,6 +91,19 @@ impl<A: Actor> CmRDT for PNCounter<A> {
             Op { dot, dir: Dir::Neg } => self.n.apply(dot),
         }
     }
+
+    fn validate_op(&self, op: &Self::Op) -> Result<()> {
+        match op {
+            Op { dot, dir: Dir::Pos } => self.p.validate_op(dot),
+            Op { dot, dir: Dir::Neg } => self.n.validate_op(dot),
+        }
+    }
+}
+
+impl<A: Actor> ValidateUnder<A> for PNCounter<A> {
+    fn validate_op_under(&self, op: &Self::Op, clock: &VClock<A>) -> Result<()> {
+        clock.validate_dot(&op.dot)
+    }
 }
 
 impl<A: Actor> CvRDT for PNCounter<A> {
@@ -74,11 +113,167 @@ impl<A: Actor> CvRDT for PNCounter<A> {
     }
 }
 
+impl<A: Actor> Lattice for PNCounter<A> {
+    fn leq(&self, other: &Self) -> bool {
+        self.p.leq(&other.p) && self.n.leq(&other.n)
+    }
+
+    fn is_bottom(&self) -> bool {
+        self.p.is_bottom() && self.n.is_bottom()
+    }
+}
+
+impl<A: Actor> Meet for PNCounter<A> {
+    fn meet(&mut self, other: &Self) {
+        self.p.meet(&other.p);
+        self.n.meet(&other.n);
+    }
+}
+
 impl<A: Actor> Causal<A> for PNCounter<A> {
     fn forget(&mut self, clock: &VClock<A>) {
         self.p.forget(&clock);
         self.n.forget(&clock);
     }
+
+    fn forget_dots(&mut self, clock: &VClock<A>) {
+        self.p.forget_dots(clock);
+        self.n.forget_dots(clock);
+    }
+}
+
+impl<A: Actor> Witness<A> for PNCounter<A> {
+    fn witness(&mut self, dot: &Dot<A>) {
+        self.p.witness(dot);
+        self.n.witness(dot);
+    }
+}
+
+impl<A: Actor + Serialize> Canonical for PNCounter<A> {
+    fn canonical_bytes(&self) -> Vec<u8> {
+        canonical::encode(self)
+    }
+}
+
+impl<A: Actor> Intern<A> for PNCounter<Interned<A>> {
+    fn intern(&mut self, table: &mut ActorTable<A>) {
+        self.p.intern(table);
+        self.n.intern(table);
+    }
+}
+
+impl<A: Actor> Intern<A> for Op<Interned<A>> {
+    fn intern(&mut self, table: &mut ActorTable<A>) {
+        self.dot.intern(table);
+    }
+}
+
+impl<A: Actor> RetireActor<A> for PNCounter<A> {
+    type Op = Retire<A>;
+
+    fn retire(&self, retired: A, successor: A) -> Retire<A> {
+        Retire {
+            p: Fold::new(&self.p.inner, retired.clone(), successor.clone()),
+            n: Fold::new(&self.n.inner, retired, successor),
+        }
+    }
+
+    fn apply_retire(&mut self, Retire { p, n }: Retire<A>) -> Result<()> {
+        n.validate(&self.n.inner)?;
+        p.apply(&mut self.p.inner)?;
+        n.apply(&mut self.n.inner)
+    }
+}
+
+impl<A: Actor> Remap<A> for PNCounter<A> {
+    fn remap(&mut self, remap: &ActorRemap<A>) -> Result<()> {
+        remap.check(&self.n.inner)?;
+        self.p.remap(remap)?;
+        self.n.remap(remap)
+    }
+}
+
+impl<A: Actor> Remap<A> for Op<A> {
+    fn remap(&mut self, remap: &ActorRemap<A>) -> Result<()> {
+        self.dot.remap(remap)
+    }
+}
+
+/// riak_dt_pncounter's state is a list of `{Actor, Inc, Dec}` sorted by actor
+impl<A: Actor + Etf> Etf for PNCounter<A> {
+    fn to_term(&self) -> Term {
+        let (p, n) = (&self.p.inner, &self.n.inner);
+        let actors: BTreeSet<&A> = p.dots.keys().chain(n.dots.keys()).collect();
+        let counts = actors.into_iter().map(|actor| {
+            Term::Tuple(vec![
+                actor.to_term(),
+                p.get(actor).to_term(),
+                n.get(actor).to_term(),
+            ])
+        });
+        riak::sorted_list(counts)
+    }
+
+    fn from_term(term: Term) -> Result<Self> {
+        let mut counter = PNCounter::new();
+        for counts in riak::list(term, "a list of {Actor, Inc, Dec} counts")? {
+            let (actor, inc, dec) = riak::tuple3(counts, "an {Actor, Inc, Dec} count")?;
+            let actor = A::from_term(actor)?;
+            let (inc, dec) = (u64::from_term(inc)?, u64::from_term(dec)?);
+            if inc > 0 {
+                counter.p.inner.dots.insert(actor.clone(), inc);
+            }
+            if dec > 0 {
+                counter.n.inner.dots.insert(actor, dec);
+            }
+        }
+        Ok(counter)
+    }
+}
+
+/// Layout 1 was a pair of gcounters, riak_dt 2.0 writes layout 2
+impl<A: Actor + Etf> RiakDt for PNCounter<A> {
+    const MODULE: &'static str = "riak_dt_pncounter";
+    const TAG: u8 = 71;
+    const VERSION: u8 = 2;
+}
+
+impl<A: Actor> Validate for PNCounter<A> {
+    fn validate(&self) -> Result<()> {
+        self.p.validate()?;
+        self.n.validate()
+    }
+}
+
+impl<A: Actor> TrackChanges for PNCounter<A> {
+    /// The value of the counter before and after, if it changed
+    type Changes = Option<(BigInt, BigInt)>;
+
+    fn changes_since(&self, before: &Self) -> Self::Changes {
+        let (old, new) = (before.read(), self.read());
+        if old != new {
+            Some((old, new))
+        } else {
+            None
+        }
+    }
+
+    fn apply_with_changes(&mut self, op: Self::Op) -> Self::Changes {
+        let change = match op.dir {
+            Dir::Pos => BigInt::from(self.p.gain(&op.dot.clone().into())),
+            Dir::Neg => -BigInt::from(self.n.gain(&op.dot.clone().into())),
+        };
+        self.apply(op);
+        self.changed_by(change)
+    }
+
+    fn merge_with_changes(&mut self, other: Self) -> Self::Changes {
+        let gain = self.p.gain(&other.p.inner);
+        let loss = self.n.gain(&other.n.inner);
+        let change = BigInt::from(gain) - BigInt::from(loss);
+        self.merge(other);
+        self.changed_by(change)
+    }
 }
 
 impl<A: Actor> PNCounter<A> {
@@ -106,6 +301,24 @@ impl<A: Actor> PNCounter<A> {
         }
     }
 
+    /// The value before and after it changed by `change`, if it changed
+    fn changed_by(&self, change: BigInt) -> Option<(BigInt, BigInt)> {
+        if change == BigInt::default() {
+            None
+        } else {
+            let new = self.read();
+            Some((&new - change, new))
+        }
+    }
+
+    /// Generate an Op that undoes the given Op by counting in the opposite direction.
+    pub fn undo(&self, op: &Op<A>, actor: A) -> Op<A> {
+        match op.dir {
+            Dir::Pos => self.dec(actor),
+            Dir::Neg => self.inc(actor),
+        }
+    }
+
     /// Return the current value of this counter (P-N).
     pub fn read(&self) -> BigInt {
         let p: BigInt = self.p.read().into();
@@ -114,6 +327,55 @@ impl<A: Actor> PNCounter<A> {
     }
 }
 
+#[cfg(any(test, feature = "quickcheck"))]
+impl Arbitrary for Dir {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        if bool::arbitrary(g) {
+            Dir::Pos
+        } else {
+            Dir::Neg
+        }
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Actor + Arbitrary> Arbitrary for Op<A> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Op {
+            dot: Dot::arbitrary(g),
+            dir: Dir::arbitrary(g),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Actor> ArbitraryOp<A> for PNCounter<A> {
+    /// Increments or decrements the counter by the actor of the given context.
+    fn arbitrary_op<G: Gen>(&self, ctx: AddCtx<A>, g: &mut G) -> Op<A> {
+        match Dir::arbitrary(g) {
+            Dir::Pos => self.inc(ctx.dot.actor),
+            Dir::Neg => self.dec(ctx.dot.actor),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Actor + Arbitrary> Arbitrary for PNCounter<A> {
+    /// Builds a PNCounter from a sequence of increments and decrements by
+    /// arbitrary actors.
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut counter = PNCounter::new();
+        for (actor, dir) in Vec::<(A, Dir)>::arbitrary(g) {
+            let op = match dir {
+                Dir::Pos => counter.inc(actor),
+                Dir::Neg => counter.dec(actor),
+            };
+            counter.apply(op);
+        }
+        counter
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -182,4 +444,49 @@ mod test {
         a.apply(a.inc("A"));
         assert_eq!(a.read(), 2.into());
     }
+
+    #[test]
+    fn test_changes() {
+        let mut a = PNCounter::new();
+        let op = a.inc("A");
+        assert_eq!(a.apply_with_changes(op), Some((0.into(), 1.into())));
+
+        let mut b = a.clone();
+        b.apply(b.inc("B"));
+        b.apply(b.dec("B"));
+
+        // b's state differs but its value does not
+        assert_eq!(a.merge_with_changes(b), None);
+        assert_eq!(a.read(), 1.into());
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut a = PNCounter::new();
+        let op = a.inc("A");
+        a.apply(op.clone());
+        a.apply(a.inc("B"));
+
+        a.apply(a.undo(&op, "A"));
+        assert_eq!(a.read(), 1.into());
+
+        let op = a.dec("B");
+        a.apply(op.clone());
+        a.apply(a.undo(&op, "A"));
+        assert_eq!(a.read(), 1.into());
+    }
+
+    #[test]
+    fn test_try_apply() {
+        let mut a = PNCounter::new();
+        assert_eq!(a.try_apply(a.inc("A")), Ok(()));
+        assert_eq!(a.try_apply(a.dec("A")), Ok(()));
+
+        let gap_op = Op {
+            dot: Dot::new("A", 3),
+            dir: Dir::Neg,
+        };
+        assert_eq!(a.try_apply(gap_op), Err(crate::Error::CausalGap));
+        assert_eq!(a.read(), 0.into());
+    }
 }
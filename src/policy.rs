@@ -0,0 +1,526 @@
+//! Per-actor permission policies for Op's.
+//!
+//! Authenticating an Op (see the `signed` module) tells us who made it, a
+//! `Policy` decides whether they were allowed to. `Guarded` wraps a CRDT and
+//! checks every Op against its policy before validating and applying it, an
+//! Op that isn't permitted fails with `Error::PermissionDenied`. So does an
+//! Op carrying dots of an actor other than the one it's applied for, the
+//! policy of that actor would not be the one that applies.
+//!
+//! `Guarded` takes the actor's word for who sent the Op. With the `signed`
+//! feature, `signed::Authenticated` takes a policy too and checks it against
+//! the actor that signed the Op.
+//!
+//! - `Grants` gives each actor the right to add, to remove, or both. It
+//!   covers the Op's of `Orswot`, `GCounter`, `PNCounter`, `MVReg` and
+//!   `Map`, including the Op's nested in a `Map` update.
+//! - `KeyScopes` limits actors to a set of `Map` keys, on top of another
+//!   policy.
+//! - Any `Fn(&A, &Op) -> bool` is a policy too, for rules of your own.
+//!
+//! ```
+//! use crdts::policy::{Grant, Grants, Guarded, KeyScopes};
+//! use crdts::{Error, Map, Orswot};
+//!
+//! let mut grants = Grants::new();
+//! grants.grant("admin", Grant::all());
+//! grants.grant("guest", Grant::add_only());
+//! let mut policy = KeyScopes::new(grants);
+//! policy.restrict("guest", vec!["guestbook"]);
+//!
+//! let mut map: Guarded<Map<&str, Orswot<&str, &str>, &str>, _> =
+//!     Guarded::new(Map::new(), policy);
+//!
+//! let write = |map: &Map<_, Orswot<_, _>, _>, key, member| {
+//!     let ctx = map.len().derive_add_ctx("guest");
+//!     map.update(key, ctx, |set, ctx| set.add(member, ctx))
+//! };
+//! map.apply(&"guest", write(map.state(), "guestbook", "hello")).unwrap();
+//!
+//! // guests can't write outside the guestbook, or remove anything
+//! let op = write(map.state(), "config", "debug");
+//! assert_eq!(map.apply(&"guest", op), Err(Error::PermissionDenied));
+//!
+//! let op = map.state().rm("guestbook", map.state().get(&"guestbook").derive_rm_ctx());
+//! assert_eq!(map.apply(&"guest", op.clone()), Err(Error::PermissionDenied));
+//! map.apply(&"admin", op).unwrap();
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::map::{self, Key, Val};
+use crate::mvreg;
+use crate::orswot::{self, Member};
+use crate::pncounter::{self, Dir};
+use crate::traits::{CmRDT, CvRDT};
+use crate::vclock::{Actor, Dot, VClock};
+use crate::{GCounter, MVReg, Map, Orswot, PNCounter};
+
+/// Decides whether an actor may make an Op.
+pub trait Policy<A, Op> {
+    /// Ok if `actor` may make `op`, `Error::PermissionDenied` otherwise.
+    fn check(&self, actor: &A, op: &Op) -> Result<()>;
+}
+
+impl<A, Op, F: Fn(&A, &Op) -> bool> Policy<A, Op> for F {
+    fn check(&self, actor: &A, op: &Op) -> Result<()> {
+        permit(self(actor, op))
+    }
+}
+
+fn permit(permitted: bool) -> Result<()> {
+    if permitted {
+        Ok(())
+    } else {
+        Err(Error::PermissionDenied)
+    }
+}
+
+/// Permits every Op, for wrappers that only need to know who made it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Anyone;
+
+impl<A, Op> Policy<A, Op> for Anyone {
+    fn check(&self, _actor: &A, _op: &Op) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Op's that can name the actors whose dots they carry.
+pub trait Authored<A: Actor> {
+    /// The actors whose dots this Op carries, empty for Op's like removes
+    /// that make no new dot. `seen` is the clock of the replica applying the
+    /// Op, the dots of an Op that carries a clock are the ones ahead of it.
+    fn authors(&self, seen: &VClock<A>) -> Vec<&A>;
+}
+
+impl<A: Actor> Authored<A> for Dot<A> {
+    fn authors(&self, _seen: &VClock<A>) -> Vec<&A> {
+        vec![&self.actor]
+    }
+}
+
+impl<A: Actor> Authored<A> for pncounter::Op<A> {
+    fn authors(&self, _seen: &VClock<A>) -> Vec<&A> {
+        vec![&self.dot.actor]
+    }
+}
+
+impl<M: Member, A: Actor> Authored<A> for orswot::Op<M, A> {
+    fn authors(&self, _seen: &VClock<A>) -> Vec<&A> {
+        match self {
+            orswot::Op::Add { dot, .. } => vec![&dot.actor],
+            orswot::Op::Rm { .. } => vec![],
+        }
+    }
+}
+
+impl<V: mvreg::Val, A: Actor> Authored<A> for mvreg::Op<V, A> {
+    /// A put is written under the writer's read clock plus one new dot of
+    /// its own, so the writer is whoever is ahead of what we have seen.
+    fn authors(&self, seen: &VClock<A>) -> Vec<&A> {
+        let mvreg::Op::Put { clock, .. } = self;
+        clock
+            .iter()
+            .filter(|dot| dot.counter > seen.get(dot.actor))
+            .map(|dot| dot.actor)
+            .collect()
+    }
+}
+
+impl<K: Key, V: Val<A>, A: Actor> Authored<A> for map::Op<K, V, A>
+where
+    V::Op: Authored<A>,
+{
+    /// The nested Op of an update is built with the update's dot, so its
+    /// authors must be the update's author too.
+    fn authors(&self, seen: &VClock<A>) -> Vec<&A> {
+        match self {
+            map::Op::Rm { .. } => vec![],
+            map::Op::Up { dot, op, .. } => {
+                let mut authors = vec![&dot.actor];
+                authors.extend(op.authors(seen));
+                authors
+            }
+            map::Op::Mv { dot, .. } => vec![&dot.actor],
+        }
+    }
+}
+
+/// CRDT's that know the clock of every dot they have seen, the clock their
+/// Op's are authored against.
+pub trait Seen<A: Actor> {
+    /// The clock of every dot this CRDT has seen
+    fn seen(&self) -> VClock<A>;
+}
+
+impl<A: Actor> Seen<A> for GCounter<A> {
+    fn seen(&self) -> VClock<A> {
+        self.inner.clone()
+    }
+}
+
+impl<A: Actor> Seen<A> for PNCounter<A> {
+    fn seen(&self) -> VClock<A> {
+        let mut seen = self.p.inner.clone();
+        seen.merge(self.n.inner.clone());
+        seen
+    }
+}
+
+impl<M: Member, A: Actor> Seen<A> for Orswot<M, A> {
+    fn seen(&self) -> VClock<A> {
+        self.read_ctx().add_clock
+    }
+}
+
+impl<V: mvreg::Val, A: Actor> Seen<A> for MVReg<V, A> {
+    fn seen(&self) -> VClock<A> {
+        self.read_ctx().add_clock
+    }
+}
+
+impl<K: Key, V: Val<A>, A: Actor> Seen<A> for Map<K, V, A> {
+    fn seen(&self) -> VClock<A> {
+        self.len().add_clock
+    }
+}
+
+/// What an actor may do
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Grant {
+    /// May add to sets, increment counters and write registers
+    pub add: bool,
+    /// May remove from sets and maps, move map entries and decrement counters
+    pub rm: bool,
+}
+
+impl Grant {
+    /// May do anything
+    pub fn all() -> Self {
+        Grant {
+            add: true,
+            rm: true,
+        }
+    }
+
+    /// May add but never remove
+    pub fn add_only() -> Self {
+        Grant {
+            add: true,
+            rm: false,
+        }
+    }
+}
+
+/// Per-actor grants, an actor without a grant may do nothing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grants<A: Ord> {
+    grants: BTreeMap<A, Grant>,
+}
+
+impl<A: Actor> Default for Grants<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Actor> Grants<A> {
+    /// Grants that permit nothing
+    pub fn new() -> Self {
+        Grants {
+            grants: BTreeMap::new(),
+        }
+    }
+
+    /// Give an actor a grant, replacing the one it had
+    pub fn grant(&mut self, actor: A, grant: Grant) {
+        self.grants.insert(actor, grant);
+    }
+
+    /// Take away all of an actor's rights
+    pub fn revoke(&mut self, actor: &A) {
+        self.grants.remove(actor);
+    }
+
+    /// The actor's grant
+    pub fn get(&self, actor: &A) -> Grant {
+        self.grants.get(actor).cloned().unwrap_or_default()
+    }
+}
+
+impl<A: Actor> Policy<A, Dot<A>> for Grants<A> {
+    fn check(&self, actor: &A, _dot: &Dot<A>) -> Result<()> {
+        permit(self.get(actor).add)
+    }
+}
+
+impl<A: Actor> Policy<A, pncounter::Op<A>> for Grants<A> {
+    fn check(&self, actor: &A, op: &pncounter::Op<A>) -> Result<()> {
+        match op.dir {
+            Dir::Pos => permit(self.get(actor).add),
+            Dir::Neg => permit(self.get(actor).rm),
+        }
+    }
+}
+
+impl<M: Member, A: Actor> Policy<A, orswot::Op<M, A>> for Grants<A> {
+    fn check(&self, actor: &A, op: &orswot::Op<M, A>) -> Result<()> {
+        match op {
+            orswot::Op::Add { .. } => permit(self.get(actor).add),
+            orswot::Op::Rm { .. } => permit(self.get(actor).rm),
+        }
+    }
+}
+
+impl<V: mvreg::Val, A: Actor> Policy<A, mvreg::Op<V, A>> for Grants<A> {
+    fn check(&self, actor: &A, _op: &mvreg::Op<V, A>) -> Result<()> {
+        permit(self.get(actor).add)
+    }
+}
+
+impl<K: Key, V: Val<A>, A: Actor> Policy<A, map::Op<K, V, A>> for Grants<A>
+where
+    Self: Policy<A, V::Op>,
+{
+    /// An update is checked by the Op it applies to the value, removes and
+    /// moves need the right to remove.
+    fn check(&self, actor: &A, op: &map::Op<K, V, A>) -> Result<()> {
+        match op {
+            map::Op::Up { op, .. } => self.check(actor, op),
+            map::Op::Rm { .. } | map::Op::Mv { .. } => permit(self.get(actor).rm),
+        }
+    }
+}
+
+/// Limits actors to a set of `Map` keys, Op's within their keys are then
+/// checked by the inner policy. Actors without a scope may use any key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyScopes<A: Ord, K: Ord, P> {
+    scopes: BTreeMap<A, BTreeSet<K>>,
+    inner: P,
+}
+
+impl<A: Actor, K: Key, P> KeyScopes<A, K, P> {
+    /// No actor is limited yet
+    pub fn new(inner: P) -> Self {
+        KeyScopes {
+            scopes: BTreeMap::new(),
+            inner,
+        }
+    }
+
+    /// Limit an actor to the given keys, replacing its previous scope
+    pub fn restrict(&mut self, actor: A, keys: impl IntoIterator<Item = K>) {
+        self.scopes.insert(actor, keys.into_iter().collect());
+    }
+
+    /// Let an actor use any key again
+    pub fn unrestrict(&mut self, actor: &A) {
+        self.scopes.remove(actor);
+    }
+
+    /// The policy checked within an actor's keys
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// The policy checked within an actor's keys, e.g. to change grants
+    pub fn inner_mut(&mut self) -> &mut P {
+        &mut self.inner
+    }
+}
+
+impl<K: Key, V: Val<A>, A: Actor, P> Policy<A, map::Op<K, V, A>> for KeyScopes<A, K, P>
+where
+    P: Policy<A, map::Op<K, V, A>>,
+{
+    fn check(&self, actor: &A, op: &map::Op<K, V, A>) -> Result<()> {
+        if let Some(scope) = self.scopes.get(actor) {
+            let in_scope = match op {
+                map::Op::Up { key, .. } => scope.contains(key),
+                map::Op::Rm { keyset, .. } => keyset.is_subset(scope),
+                map::Op::Mv { from, to, .. } => scope.contains(from) && scope.contains(to),
+            };
+            permit(in_scope)?;
+        }
+        self.inner.check(actor, op)
+    }
+}
+
+/// Wraps a CRDT so that every Op is checked against a policy before it's
+/// applied.
+#[derive(Debug, Clone)]
+pub struct Guarded<T, P> {
+    state: T,
+    policy: P,
+}
+
+impl<T: CmRDT, P> Guarded<T, P> {
+    /// Wrap a state, checking Op's against the policy
+    pub fn new(state: T, policy: P) -> Self {
+        Guarded { state, policy }
+    }
+
+    /// The current state
+    pub fn state(&self) -> &T {
+        &self.state
+    }
+
+    /// The policy Op's are checked against
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    /// The policy Op's are checked against, e.g. to change grants
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+
+    /// Unwrap the state
+    pub fn into_inner(self) -> T {
+        self.state
+    }
+
+    /// Apply an Op made by `actor`, the authenticated sender of the Op. An
+    /// Op that isn't permitted, carries dots of another actor or fails
+    /// validation leaves the state untouched.
+    pub fn apply<A>(&mut self, actor: &A, op: T::Op) -> Result<()>
+    where
+        A: Actor,
+        T: Seen<A>,
+        T::Op: Authored<A>,
+        P: Policy<A, T::Op>,
+    {
+        self.policy.check(actor, &op)?;
+        self.state.validate_op(&op)?;
+        let seen = self.state.seen();
+        permit(op.authors(&seen).into_iter().all(|author| author == actor))?;
+        self.state.apply(op);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn grants() -> Grants<u8> {
+        let mut grants = Grants::new();
+        grants.grant(1, Grant::all());
+        grants.grant(2, Grant::add_only());
+        grants
+    }
+
+    #[test]
+    fn test_add_only_actor_cant_remove() {
+        let mut set: Guarded<Orswot<u8, u8>, _> = Guarded::new(Orswot::new(), grants());
+        let op = set.state().add(7, set.state().read_ctx().derive_add_ctx(2));
+        set.apply(&2, op).unwrap();
+
+        let op = set.state().rm(7, set.state().contains(&7).derive_rm_ctx());
+        assert_eq!(set.apply(&2, op.clone()), Err(Error::PermissionDenied));
+        assert!(set.state().contains(&7).val);
+
+        set.apply(&1, op).unwrap();
+        assert!(!set.state().contains(&7).val);
+    }
+
+    #[test]
+    fn test_counter_grants() {
+        let mut counter = Guarded::new(PNCounter::new(), grants());
+        counter.apply(&2, counter.state().inc(2)).unwrap();
+        assert_eq!(
+            counter.apply(&2, counter.state().dec(2)),
+            Err(Error::PermissionDenied)
+        );
+        assert_eq!(
+            counter.apply(&3, counter.state().inc(3)),
+            Err(Error::PermissionDenied)
+        );
+        counter.apply(&1, counter.state().dec(1)).unwrap();
+        assert_eq!(counter.state().read(), 0.into());
+    }
+
+    #[test]
+    fn test_nested_map_ops_are_checked() {
+        let mut map: Guarded<Map<u8, Orswot<u8, u8>, u8>, _> = Guarded::new(Map::new(), grants());
+        let op = map
+            .state()
+            .update(0, map.state().len().derive_add_ctx(2), |set, ctx| {
+                set.add(7, ctx)
+            });
+        map.apply(&2, op).unwrap();
+
+        let op = map
+            .state()
+            .update(0, map.state().len().derive_add_ctx(2), |set, _| {
+                set.rm(7, set.contains(&7).derive_rm_ctx())
+            });
+        assert_eq!(map.apply(&2, op), Err(Error::PermissionDenied));
+
+        let op = map.state().rm(0, map.state().get(&0).derive_rm_ctx());
+        assert_eq!(map.apply(&2, op), Err(Error::PermissionDenied));
+        assert!(map.state().get(&0).val.unwrap().contains(&7).val);
+    }
+
+    #[test]
+    fn test_key_scopes() {
+        let mut policy = KeyScopes::new(grants());
+        policy.restrict(1, vec![0]);
+        let mut map: Guarded<Map<u8, MVReg<u8, u8>, u8>, _> = Guarded::new(Map::new(), policy);
+
+        let write = |map: &Map<u8, MVReg<u8, u8>, u8>, actor, key| {
+            map.update(key, map.len().derive_add_ctx(actor), |reg, ctx| {
+                reg.write(key, ctx)
+            })
+        };
+        map.apply(&1, write(map.state(), 1, 0)).unwrap();
+        assert_eq!(
+            map.apply(&1, write(map.state(), 1, 1)),
+            Err(Error::PermissionDenied)
+        );
+        map.apply(&2, write(map.state(), 2, 1)).unwrap();
+
+        let op = map.state().rm(1, map.state().get(&1).derive_rm_ctx());
+        assert_eq!(map.apply(&1, op.clone()), Err(Error::PermissionDenied));
+        map.policy_mut().unrestrict(&1);
+        map.apply(&1, op).unwrap();
+        assert_eq!(map.state().len().val, 1);
+    }
+
+    #[test]
+    fn test_op_of_another_actor_is_denied() {
+        let mut set: Guarded<Orswot<u8, u8>, _> = Guarded::new(Orswot::new(), grants());
+        let op = set.state().add(7, set.state().read_ctx().derive_add_ctx(1));
+        assert_eq!(set.apply(&2, op.clone()), Err(Error::PermissionDenied));
+        assert!(!set.state().contains(&7).val);
+        set.apply(&1, op).unwrap();
+
+        let mut reg: Guarded<MVReg<u8, u8>, _> = Guarded::new(MVReg::new(), grants());
+        let op = reg
+            .state()
+            .write(7, reg.state().read_ctx().derive_add_ctx(1));
+        assert_eq!(reg.apply(&2, op.clone()), Err(Error::PermissionDenied));
+        reg.apply(&1, op).unwrap();
+        assert_eq!(reg.state().read().val, vec![7]);
+    }
+
+    #[test]
+    fn test_closure_policy() {
+        let even_members_only = |_: &u8, op: &orswot::Op<u8, u8>| -> bool {
+            match op {
+                orswot::Op::Add { members, .. } => members.iter().all(|m| m % 2 == 0),
+                orswot::Op::Rm { .. } => true,
+            }
+        };
+        let mut set = Guarded::new(Orswot::new(), even_members_only);
+        let op = set.state().add(2, set.state().read_ctx().derive_add_ctx(1));
+        set.apply(&1, op).unwrap();
+        let op = set.state().add(3, set.state().read_ctx().derive_add_ctx(1));
+        assert_eq!(set.apply(&1, op), Err(Error::PermissionDenied));
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::VecDeque;
+
+use crate::ctx::AddCtx;
+use crate::gcounter::GCounter;
+use crate::map::{self, Key, Map, Val};
+use crate::mvreg::{self, MVReg};
+use crate::orswot::{self, Member, Orswot};
+use crate::pncounter::{self, PNCounter};
+use crate::traits::{CmRDT, CvRDT, Lattice};
+use crate::vclock::{Actor, Dot};
+
+/// A replica of a CRDT owned by a single actor.
+///
+/// Every edit made through a `Replica` is generated from the current state,
+/// applied locally and queued for replication in one step, so an edit can
+/// never reuse the dot of an edit that wasn't applied yet.
+///
+/// ``` rust
+/// use crdts::replica::Replica;
+/// use crdts::Orswot;
+///
+/// let mut a = Replica::new("A", Orswot::new());
+/// let mut b = Replica::new("B", Orswot::new());
+///
+/// a.add("apple");
+/// a.add("banana");
+/// b.add("cherry");
+///
+/// for op in a.drain_outbound() {
+///     b.apply(op);
+/// }
+/// b.rm("apple");
+///
+/// assert!(b.state().contains(&"banana").val);
+/// assert!(!b.state().contains(&"apple").val);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Replica<T: CmRDT, A: Actor> {
+    actor: A,
+    state: T,
+    outbound: VecDeque<T::Op>,
+}
+
+impl<T: CmRDT, A: Actor> Replica<T, A> {
+    /// Create a replica owned by `actor` from an initial state
+    pub fn new(actor: A, state: T) -> Self {
+        Replica {
+            actor,
+            state,
+            outbound: VecDeque::new(),
+        }
+    }
+
+    /// The actor that owns this replica
+    pub fn actor(&self) -> &A {
+        &self.actor
+    }
+
+    /// The current state of the replica
+    pub fn state(&self) -> &T {
+        &self.state
+    }
+
+    /// Consume the replica, returning its state
+    pub fn into_state(self) -> T {
+        self.state
+    }
+
+    /// Make an edit: the Op is built by `f` from the current state, applied
+    /// locally, queued for replication and returned.
+    pub fn edit<F>(&mut self, f: F) -> T::Op
+    where
+        F: FnOnce(&T, &A) -> T::Op,
+        T::Op: Clone,
+    {
+        let op = f(&self.state, &self.actor);
+        self.state.apply(op.clone());
+        self.outbound.push_back(op.clone());
+        op
+    }
+
+    /// Apply an Op received from another replica, it is not queued for replication.
+    pub fn apply(&mut self, op: T::Op) {
+        self.state.apply(op);
+    }
+
+    /// Merge in the state of another replica
+    pub fn merge(&mut self, other: T)
+    where
+        T: CvRDT,
+    {
+        self.state.merge(other);
+    }
+
+    /// Merge in the state of another replica unless we already know
+    /// everything it knows, returns whether it was merged.
+    pub fn merge_if_new(&mut self, other: T) -> bool
+    where
+        T: Lattice,
+    {
+        if other.leq(&self.state) {
+            false
+        } else {
+            self.state.merge(other);
+            true
+        }
+    }
+
+    /// The Op's made on this replica that have not yet been drained
+    pub fn outbound(&self) -> &VecDeque<T::Op> {
+        &self.outbound
+    }
+
+    /// Take the queued Op's in the order they were made
+    pub fn drain_outbound(&mut self) -> Vec<T::Op> {
+        self.outbound.drain(..).collect()
+    }
+}
+
+impl<M: Member, A: Actor> Replica<Orswot<M, A>, A> {
+    /// Add a member to the set
+    pub fn add(&mut self, member: M) -> orswot::Op<M, A> {
+        self.edit(|set, actor| set.add(member, set.read_ctx().derive_add_ctx(actor.clone())))
+    }
+
+    /// Remove a member from the set
+    pub fn rm(&mut self, member: M) -> orswot::Op<M, A> {
+        self.edit(|set, _| {
+            let ctx = set.contains(&member).derive_rm_ctx();
+            set.rm(member, ctx)
+        })
+    }
+}
+
+impl<K: Key, V: Val<A>, A: Actor> Replica<Map<K, V, A>, A>
+where
+    V::Op: Clone,
+{
+    /// Update the value under a key, see `Map::update`
+    pub fn update<F>(&mut self, key: impl Into<K>, f: F) -> map::Op<K, V, A>
+    where
+        F: FnOnce(&V, AddCtx<A>) -> V::Op,
+    {
+        let key = key.into();
+        self.edit(|map, actor| {
+            let ctx = map.get(&key).derive_add_ctx(actor.clone());
+            map.update(key, ctx, f)
+        })
+    }
+
+    /// Remove a key from the map
+    pub fn rm(&mut self, key: impl Into<K>) -> map::Op<K, V, A> {
+        let key = key.into();
+        self.edit(|map, _| {
+            let ctx = map.get(&key).derive_rm_ctx();
+            map.rm(key, ctx)
+        })
+    }
+}
+
+impl<V: mvreg::Val, A: Actor> Replica<MVReg<V, A>, A> {
+    /// Write a value to the register, overwriting the values we've seen
+    pub fn write(&mut self, val: V) -> mvreg::Op<V, A> {
+        self.edit(|reg, actor| reg.write(val, reg.read_ctx().derive_add_ctx(actor.clone())))
+    }
+}
+
+impl<A: Actor> Replica<GCounter<A>, A> {
+    /// Increment the counter
+    pub fn inc(&mut self) -> Dot<A> {
+        self.edit(|counter, actor| counter.inc(actor.clone()))
+    }
+}
+
+impl<A: Actor> Replica<PNCounter<A>, A> {
+    /// Increment the counter
+    pub fn inc(&mut self) -> pncounter::Op<A> {
+        self.edit(|counter, actor| counter.inc(actor.clone()))
+    }
+
+    /// Decrement the counter
+    pub fn dec(&mut self) -> pncounter::Op<A> {
+        self.edit(|counter, actor| counter.dec(actor.clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_edits_are_applied_before_the_next_edit() {
+        let mut replica = Replica::new("A", Orswot::new());
+        let first = replica.add(1);
+        let second = replica.add(2);
+
+        match (first, second) {
+            (orswot::Op::Add { dot: d1, .. }, orswot::Op::Add { dot: d2, .. }) => {
+                assert_eq!(d1, Dot::new("A", 1));
+                assert_eq!(d2, Dot::new("A", 2));
+            }
+            _ => panic!("expected two adds"),
+        }
+        assert_eq!(replica.outbound().len(), 2);
+    }
+
+    #[test]
+    fn test_ops_replicate_through_outbound() {
+        let mut a = Replica::new("A", Map::<&str, MVReg<u8, &str>, &str>::new());
+        let mut b = Replica::new("B", Map::new());
+
+        a.update("x", |reg, ctx| reg.write(1, ctx));
+        a.update("y", |reg, ctx| reg.write(2, ctx));
+        a.rm("y");
+        for op in a.drain_outbound() {
+            b.apply(op);
+        }
+        assert!(a.outbound().is_empty());
+        assert!(b.outbound().is_empty());
+        assert_eq!(a.state(), b.state());
+
+        b.update("x", |reg, ctx| reg.write(3, ctx));
+        a.merge(b.state().clone());
+        assert_eq!(a.state().get(&"x").val.unwrap().read().val, vec![3]);
+    }
+
+    #[test]
+    fn test_merge_if_new() {
+        let mut a = Replica::new("A", Orswot::new());
+        let mut b = Replica::new("B", Orswot::new());
+
+        a.add("apple");
+        assert!(b.merge_if_new(a.state().clone()));
+        assert!(!b.merge_if_new(a.state().clone()));
+        assert!(!a.merge_if_new(b.state().clone()));
+
+        b.rm("apple");
+        assert!(a.merge_if_new(b.state().clone()));
+        assert!(!a.state().contains(&"apple").val);
+    }
+
+    #[test]
+    fn test_merge_if_new_with_concurrent_removes() {
+        let mut a = Replica::new("A", Orswot::new());
+        let mut b = Replica::new("B", Orswot::new());
+        a.add("apple");
+        b.add("pear");
+        a.merge(b.state().clone());
+        b.merge(a.state().clone());
+
+        // the clocks stay the same, only the removed members tell the
+        // states apart
+        a.rm("apple");
+        b.rm("pear");
+        assert_eq!(a.state().read().add_clock, b.state().read().add_clock);
+
+        let b_state = b.state().clone();
+        assert!(b.merge_if_new(a.state().clone()));
+        assert!(a.merge_if_new(b_state));
+        assert!(a.state().read().val.is_empty());
+        assert_eq!(a.state(), b.state());
+        assert!(!a.merge_if_new(b.state().clone()));
+    }
+
+    #[test]
+    fn test_merge_if_new_with_concurrent_map_removes() {
+        let mut a = Replica::new("A", Map::<&str, MVReg<u8, &str>, &str>::new());
+        let mut b = Replica::new("B", a.state().clone());
+        a.update("x", |reg, ctx| reg.write(1, ctx));
+        b.update("y", |reg, ctx| reg.write(2, ctx));
+        a.merge(b.state().clone());
+        b.merge(a.state().clone());
+
+        a.rm("x");
+        b.rm("y");
+        let b_state = b.state().clone();
+        assert!(b.merge_if_new(a.state().clone()));
+        assert!(a.merge_if_new(b_state));
+        assert!(a.state().is_empty().val);
+        assert_eq!(a.state(), b.state());
+
+        // b removes its own write along with a's first one, while a
+        // concurrently overwrites its first write
+        a.update("z", |reg, ctx| reg.write(3, ctx));
+        b.merge(a.state().clone());
+        b.update("z", |reg, ctx| reg.write(5, ctx));
+        a.update("z", |reg, ctx| reg.write(4, ctx));
+        b.rm("z");
+        assert!(a.merge_if_new(b.state().clone()));
+        assert_eq!(a.state().get(&"z").val.unwrap().read().val, vec![4]);
+        assert!(!a.merge_if_new(b.state().clone()));
+    }
+
+    #[test]
+    fn test_counters() {
+        let mut g = Replica::new("A", GCounter::new());
+        g.inc();
+        g.inc();
+        assert_eq!(g.state().read(), 2u8.into());
+
+        let mut pn = Replica::new("A", PNCounter::new());
+        pn.inc();
+        pn.dec();
+        pn.dec();
+        assert_eq!(pn.state().read(), (-1).into());
+        assert_eq!(pn.drain_outbound().len(), 3);
+    }
+}
@@ -0,0 +1,421 @@
+//! Retiring actors and reassigning replica ids.
+//!
+//! An actor stays in every clock it ever wrote to, long after the device
+//! behind it is decommissioned. Two tools drop or rename such actors, both
+//! only safe once every replica has agreed to them:
+//!
+//! - `RetireActor` folds a retired actor's `GCounter` or `PNCounter` counts
+//!   into a successor, so the retired actor's entry can go. The successor
+//!   generates the retire Op, like an increment of its own.
+//! - `Remap` renames actors across an entire state or Op, e.g. an `Orswot` or
+//!   a `Map`, by an `ActorRemap`. The new ids must be ids no replica has ever
+//!   used, the dots of two actors can't be told apart once they share an id,
+//!   a remap onto an actor the state already holds is rejected.
+//!
+//! The protocol is the same for both:
+//!
+//! 1. A `Retirement` is proposed, naming the retiring actors and the replicas
+//!    that must acknowledge it.
+//! 2. Each replica stops writing as the retiring actors and acknowledges
+//!    once every Op it made as them has been delivered.
+//! 3. Once every replica has acknowledged, each applies the retire Op's or
+//!    the remap. A replica that has retired an actor must not merge states or
+//!    apply Op's from a replica that hasn't, they would bring the actor back.
+//!
+//! # Examples
+//!
+//! ```
+//! use crdts::retire::{ActorRemap, Remap, RetireActor, Retirement};
+//! use crdts::{CmRDT, GCounter};
+//!
+//! let mut counter = GCounter::new();
+//! counter.apply(counter.inc("old-phone"));
+//! counter.apply(counter.inc("laptop"));
+//!
+//! let mut remap = ActorRemap::new();
+//! remap.insert("laptop", "laptop-2").unwrap();
+//! let mut retirement = Retirement::new(remap, vec!["laptop"]);
+//! retirement.ack(&"laptop");
+//! assert!(retirement.is_acknowledged());
+//!
+//! let op = counter.retire("old-phone", "laptop");
+//! counter.apply_retire(op).unwrap();
+//! counter.remap(retirement.remap().unwrap()).unwrap();
+//! assert_eq!(counter.read(), 2u8.into());
+//! assert_eq!(counter.inc("laptop-2").counter, 3);
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::traits::CmRDT;
+use crate::vclock::{Actor, Dot, VClock};
+
+/// A retire Op for a single clock entry, the retired actor's counter added
+/// to the successor's.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fold<A> {
+    /// The retired actor and the counter being folded
+    pub retired: Dot<A>,
+    /// The successor and its counter with the retired counts added
+    pub successor: Dot<A>,
+}
+
+impl<A: Actor> Fold<A> {
+    /// Fold `retired` into `successor` as `clock` has seen them
+    pub fn new(clock: &VClock<A>, retired: A, successor: A) -> Self {
+        let counter = clock.get(&retired);
+        Fold {
+            successor: Dot::new(successor.clone(), clock.get(&successor) + counter),
+            retired: Dot::new(retired, counter),
+        }
+    }
+
+    /// Check that the fold covers every count of the retired actor in `clock`
+    pub fn validate(&self, clock: &VClock<A>) -> Result<()> {
+        if clock.get(&self.retired.actor) > self.retired.counter {
+            Err(Error::RetiredActorAhead)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Apply the fold to a clock of counts
+    pub fn apply(self, clock: &mut VClock<A>) -> Result<()> {
+        self.validate(clock)?;
+        clock.dots.remove(&self.retired.actor);
+        clock.apply(self.successor);
+        Ok(())
+    }
+}
+
+/// Counters whose retired actors can be folded into a successor
+pub trait RetireActor<A: Actor> {
+    /// The Op retiring an actor
+    type Op;
+
+    /// Generate the Op folding `retired`'s counts into `successor`, it's
+    /// generated by the successor.
+    fn retire(&self, retired: A, successor: A) -> Self::Op;
+
+    /// Apply a retire Op, a rejected Op leaves the counter untouched.
+    ///
+    /// Applying the same Op again is a no-op.
+    fn apply_retire(&mut self, op: Self::Op) -> Result<()>;
+}
+
+/// New ids for a set of actors
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActorRemap<A: Actor> {
+    ids: BTreeMap<A, A>,
+}
+
+impl<A: Actor> Default for ActorRemap<A> {
+    fn default() -> Self {
+        ActorRemap::new()
+    }
+}
+
+impl<A: Actor> ActorRemap<A> {
+    /// A remap that leaves every actor as it is
+    pub fn new() -> Self {
+        ActorRemap {
+            ids: BTreeMap::new(),
+        }
+    }
+
+    /// Give `actor` the id `new_id`, fails if `actor` already has a new id or
+    /// another actor was given `new_id`.
+    pub fn insert(&mut self, actor: A, new_id: A) -> Result<()> {
+        if self.ids.contains_key(&actor) || self.ids.values().any(|id| id == &new_id) {
+            return Err(Error::DuplicateActor);
+        }
+        self.ids.insert(actor, new_id);
+        Ok(())
+    }
+
+    /// The new id of an actor, if it's remapped
+    pub fn get(&self, actor: &A) -> Option<&A> {
+        self.ids.get(actor)
+    }
+
+    /// The actor under its new id
+    pub fn actor(&self, actor: A) -> A {
+        match self.ids.get(&actor) {
+            Some(id) => id.clone(),
+            None => actor,
+        }
+    }
+
+    /// The number of remapped actors
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Whether no actor is remapped
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// The remapped actors and their new ids
+    pub fn iter(&self) -> impl Iterator<Item = (&A, &A)> {
+        self.ids.iter()
+    }
+
+    /// Check that no two actors of `clock` end up under the same id, e.g. an
+    /// actor remapped onto an actor of the clock that keeps its id.
+    pub fn check(&self, clock: &VClock<A>) -> Result<()> {
+        let mut ids = BTreeSet::new();
+        if clock
+            .iter()
+            .all(|dot| ids.insert(self.actor(dot.actor.clone())))
+        {
+            Ok(())
+        } else {
+            Err(Error::DuplicateActor)
+        }
+    }
+}
+
+/// States and Op's whose actors can be renamed
+pub trait Remap<A: Actor> {
+    /// Give every remapped actor its new id.
+    ///
+    /// Fails with `Error::DuplicateActor` if two actors would end up under
+    /// one id, which would merge their dots. A rejected remap leaves the
+    /// value untouched.
+    fn remap(&mut self, remap: &ActorRemap<A>) -> Result<()>;
+}
+
+impl<A: Actor> Remap<A> for VClock<A> {
+    fn remap(&mut self, remap: &ActorRemap<A>) -> Result<()> {
+        if remap.is_empty() {
+            return Ok(());
+        }
+        remap.check(self)?;
+        for dot in mem::take(self) {
+            self.apply(Dot::new(remap.actor(dot.actor), dot.counter));
+        }
+        Ok(())
+    }
+}
+
+impl<A: Actor> Remap<A> for Dot<A> {
+    fn remap(&mut self, remap: &ActorRemap<A>) -> Result<()> {
+        if let Some(id) = remap.get(&self.actor) {
+            self.actor = id.clone();
+        }
+        Ok(())
+    }
+}
+
+/// A remap waiting for every replica to acknowledge it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Retirement<A: Actor> {
+    remap: ActorRemap<A>,
+    awaiting: BTreeSet<A>,
+}
+
+impl<A: Actor> Retirement<A> {
+    /// Propose a remap to the given replicas
+    pub fn new<I: IntoIterator<Item = A>>(remap: ActorRemap<A>, replicas: I) -> Self {
+        Retirement {
+            remap,
+            awaiting: replicas.into_iter().collect(),
+        }
+    }
+
+    /// Record a replica's acknowledgement, returns false if it wasn't
+    /// awaited.
+    pub fn ack(&mut self, replica: &A) -> bool {
+        self.awaiting.remove(replica)
+    }
+
+    /// The replicas that haven't acknowledged yet
+    pub fn awaiting(&self) -> impl Iterator<Item = &A> {
+        self.awaiting.iter()
+    }
+
+    /// Whether every replica has acknowledged
+    pub fn is_acknowledged(&self) -> bool {
+        self.awaiting.is_empty()
+    }
+
+    /// The remap, once every replica has acknowledged it
+    pub fn remap(&self) -> Result<&ActorRemap<A>> {
+        if self.is_acknowledged() {
+            Ok(&self.remap)
+        } else {
+            Err(Error::Unacknowledged(self.awaiting.len()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use quickcheck::quickcheck;
+
+    use crate::{map, CmRDT, CvRDT, GCounter, Map, Orswot, PNCounter};
+
+    type TMap = Map<u8, Orswot<u8, u8>, u8>;
+
+    fn remap_of(pairs: &[(u8, u8)]) -> ActorRemap<u8> {
+        let mut remap = ActorRemap::new();
+        for (actor, id) in pairs {
+            remap.insert(*actor, *id).unwrap();
+        }
+        remap
+    }
+
+    #[test]
+    fn test_remap_rejects_duplicates() {
+        let mut remap = remap_of(&[(1, 10)]);
+        assert_eq!(remap.insert(1, 11), Err(Error::DuplicateActor));
+        assert_eq!(remap.insert(2, 10), Err(Error::DuplicateActor));
+        // swapping ids is a valid remap
+        assert_eq!(remap.insert(10, 1), Ok(()));
+    }
+
+    #[test]
+    fn test_remap_onto_a_held_actor_is_rejected() {
+        let mut clock: VClock<u8> = vec![Dot::new(1, 3), Dot::new(2, 5)].into_iter().collect();
+        let before = clock.clone();
+        assert_eq!(
+            clock.remap(&remap_of(&[(1, 2)])),
+            Err(Error::DuplicateActor)
+        );
+        assert_eq!(clock, before);
+
+        // 2 moving out of the way makes room for 1
+        clock.remap(&remap_of(&[(1, 2), (2, 3)])).unwrap();
+        assert_eq!(clock.get(&2), 3);
+        assert_eq!(clock.get(&3), 5);
+
+        let mut map = TMap::new();
+        for actor in 1..3 {
+            let op = map.update(actor, map.get(&actor).derive_add_ctx(actor), |set, ctx| {
+                set.add(actor, ctx)
+            });
+            map.apply(op);
+        }
+        let before = map.clone();
+        assert_eq!(map.remap(&remap_of(&[(1, 2)])), Err(Error::DuplicateActor));
+        assert_eq!(map, before);
+
+        let mut op = map.rm(1, map.len().derive_rm_ctx());
+        op.remap(&remap_of(&[(1, 3)])).unwrap();
+        assert_eq!(op.remap(&remap_of(&[(3, 2)])), Err(Error::DuplicateActor));
+    }
+
+    #[test]
+    fn test_retirement_waits_for_every_replica() {
+        let mut retirement = Retirement::new(remap_of(&[(1, 10)]), vec![1, 2, 3]);
+        assert!(retirement.ack(&1));
+        assert!(!retirement.ack(&1));
+        assert_eq!(retirement.remap(), Err(Error::Unacknowledged(2)));
+        assert_eq!(retirement.awaiting().collect::<Vec<_>>(), vec![&2, &3]);
+
+        retirement.ack(&2);
+        retirement.ack(&3);
+        assert_eq!(retirement.remap(), Ok(&remap_of(&[(1, 10)])));
+    }
+
+    #[test]
+    fn test_retire_gcounter() {
+        let mut a = GCounter::new();
+        for actor in &[1, 1, 2, 3, 3, 3] {
+            a.apply(a.inc(*actor));
+        }
+        let mut b = a.clone();
+
+        // 3 folds 1's counts into its own
+        let op = a.retire(1, 3);
+        a.apply_retire(op.clone()).unwrap();
+        assert_eq!(a.read(), 6u8.into());
+        assert_eq!(a.inner.get(&1), 0);
+        assert_eq!(a.inner.get(&3), 5);
+
+        // the retire Op is idempotent and commutes with the successor's
+        // own increments
+        a.apply_retire(op.clone()).unwrap();
+        let inc = a.inc(3);
+        a.apply(inc.clone());
+        b.apply(inc);
+        b.apply_retire(op).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(b.read(), 7u8.into());
+    }
+
+    #[test]
+    fn test_retire_rejects_unfolded_counts() {
+        let mut counter = GCounter::new();
+        counter.apply(counter.inc(1));
+        let op = counter.retire(1, 2);
+        counter.apply(counter.inc(1));
+
+        let before = counter.clone();
+        assert_eq!(counter.apply_retire(op), Err(Error::RetiredActorAhead));
+        assert_eq!(counter, before);
+    }
+
+    #[test]
+    fn test_retire_pncounter() {
+        let mut counter = PNCounter::new();
+        for _ in 0..3 {
+            counter.apply(counter.inc(1));
+        }
+        counter.apply(counter.dec(1));
+        counter.apply(counter.dec(2));
+
+        let op = counter.retire(1, 2);
+        counter.apply_retire(op).unwrap();
+        assert_eq!(counter.read(), 1.into());
+        assert_eq!(counter.inc(2).dot.counter, 4);
+        assert_eq!(counter.dec(2).dot.counter, 3);
+        assert_eq!(counter.inc(1).dot.counter, 1);
+    }
+
+    quickcheck! {
+        fn prop_remap_to_fresh_ids_is_reversible(map: TMap, ops: Vec<map::Op<u8, Orswot<u8, u8>, u8>>) -> bool {
+            // arbitrary actors are at most 100, the fresh ids are above them
+            let to_fresh: Vec<(u8, u8)> = (0..=100).map(|a| (a, a + 128)).collect();
+            let from_fresh: Vec<(u8, u8)> = to_fresh.iter().map(|(a, id)| (*id, *a)).collect();
+
+            let mut remapped = map.clone();
+            remapped.remap(&remap_of(&to_fresh)).unwrap();
+            remapped.remap(&remap_of(&from_fresh)).unwrap();
+
+            let mut with_ops = map.clone();
+            let mut remapped_with_ops = remapped.clone();
+            remapped_with_ops.remap(&remap_of(&to_fresh)).unwrap();
+            for op in ops {
+                let mut remapped_op = op.clone();
+                remapped_op.remap(&remap_of(&to_fresh)).unwrap();
+                with_ops.apply(op);
+                remapped_with_ops.apply(remapped_op);
+            }
+            remapped_with_ops.remap(&remap_of(&from_fresh)).unwrap();
+
+            remapped == map && remapped_with_ops == with_ops
+        }
+
+        fn prop_remap_commutes_with_merge(a: Orswot<u8, u8>, b: Orswot<u8, u8>) -> bool {
+            let remap = remap_of(&[(0, 200), (1, 0), (2, 201)]);
+            let mut merged = a.clone();
+            merged.merge(b.clone());
+            merged.remap(&remap).unwrap();
+
+            let (mut ra, mut rb) = (a, b);
+            ra.remap(&remap).unwrap();
+            rb.remap(&remap).unwrap();
+            ra.merge(rb);
+
+            ra == merged
+        }
+    }
+}
@@ -0,0 +1,906 @@
+//! Interoperability with riak_dt's binary formats.
+//!
+//! riak_dt's `to_binary/1` writes a CRDT as its type tag, the version of its
+//! state's layout and the Erlang external term format (ETF) encoding of the
+//! state: `<<Tag:8, Vers:8, (term_to_binary(State))/binary>>`. `to_binary`
+//! and `from_binary` read and write these binaries for `Orswot`, `Map`,
+//! `GCounter`, `PNCounter` and `LWWReg`. A `VClock` has no tag, riak_dt
+//! embeds a riak_dt_vclock as its `[{Actor, Counter}]` list, so it's read
+//! and written with `binary_to_term` and `term_to_binary`.
+//!
+//! riak_dt 2.1 keeps the entries of orswots and maps in Erlang `dict`'s
+//! (layout version 2). A `dict` places its keys by Erlang's internal term
+//! hash, so we read these layouts but write the orddict based version 1
+//! layouts of riak_dt 2.0, which riak_dt's `from_binary/1` upgrades on load.
+//!
+//! Actors, members, map keys and register values are converted to terms with
+//! `Etf`, a `String` is an Erlang binary and the integer types are integers.
+//!
+//! ``` rust
+//! use crdts::riak::{self, Term};
+//! use crdts::{CmRDT, Orswot};
+//!
+//! let mut set: Orswot<String, String> = Orswot::new();
+//! set.apply(set.add("apple".to_string(), set.read().derive_add_ctx("a".to_string())));
+//!
+//! let bytes = riak::to_binary(&set);
+//! assert_eq!(&bytes[..2], &[75, 1]);
+//! assert_eq!(riak::from_binary::<Orswot<String, String>>(&bytes).unwrap(), set);
+//!
+//! // the state is an ordinary Erlang term
+//! let term = riak::binary_to_term(&bytes[2..]).unwrap();
+//! assert!(matches!(term, Term::Tuple(ref elems) if elems.len() == 3));
+//! ```
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::io::{self, Read};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::error::{Error, Result};
+use crate::vclock::{Actor, Dot, VClock};
+
+/// The first byte of every ETF encoded term
+pub const ETF_VERSION: u8 = 131;
+
+const SMALL_INTEGER_EXT: u8 = 97;
+const INTEGER_EXT: u8 = 98;
+const ATOM_EXT: u8 = 100;
+const SMALL_TUPLE_EXT: u8 = 104;
+const LARGE_TUPLE_EXT: u8 = 105;
+const NIL_EXT: u8 = 106;
+const STRING_EXT: u8 = 107;
+const LIST_EXT: u8 = 108;
+const BINARY_EXT: u8 = 109;
+const SMALL_BIG_EXT: u8 = 110;
+const LARGE_BIG_EXT: u8 = 111;
+const SMALL_ATOM_EXT: u8 = 115;
+const ATOM_UTF8_EXT: u8 = 118;
+const SMALL_ATOM_UTF8_EXT: u8 = 119;
+const COMPRESSED: u8 = 80;
+
+// deeper than any riak_dt state nests, and shallow enough for the stack
+const MAX_DEPTH: usize = 128;
+
+/// An Erlang term, of the kinds riak_dt states are built from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Term {
+    /// An integer
+    Int(i128),
+    /// An atom
+    Atom(String),
+    /// A tuple
+    Tuple(Vec<Term>),
+    /// A proper list, the empty list is `[]`
+    List(Vec<Term>),
+    /// A non-empty list whose tail is not a list, e.g. `[Key | Value]`
+    ImproperList(Vec<Term>, Box<Term>),
+    /// A binary
+    Binary(Vec<u8>),
+}
+
+impl Term {
+    /// The position of the term's kind in Erlang's term order
+    fn rank(&self) -> u8 {
+        match self {
+            Term::Int(_) => 0,
+            Term::Atom(_) => 1,
+            Term::Tuple(_) => 2,
+            Term::List(elems) if elems.is_empty() => 3,
+            Term::List(_) | Term::ImproperList(..) => 4,
+            Term::Binary(_) => 5,
+        }
+    }
+
+    /// The elements of a list and its tail, `None` for a proper list
+    fn as_list(&self) -> Option<(&[Term], Option<&Term>)> {
+        match self {
+            Term::List(elems) => Some((elems, None)),
+            Term::ImproperList(elems, tail) => Some((elems, Some(tail))),
+            _ => None,
+        }
+    }
+}
+
+/// Terms are ordered the way Erlang orders them, this is the order of the
+/// keys of an orddict.
+impl Ord for Term {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Term::Int(a), Term::Int(b)) => a.cmp(b),
+            (Term::Atom(a), Term::Atom(b)) => a.cmp(b),
+            (Term::Binary(a), Term::Binary(b)) => a.cmp(b),
+            (Term::Tuple(a), Term::Tuple(b)) => a.len().cmp(&b.len()).then_with(|| a.cmp(b)),
+            _ => match (self.as_list(), other.as_list()) {
+                (Some(a), Some(b)) => cmp_lists(a, b),
+                _ => self.rank().cmp(&other.rank()),
+            },
+        }
+    }
+}
+
+impl PartialOrd for Term {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Lists are compared cell by cell, so once one list runs out of elements
+// its tail is compared with the rest of the other.
+fn cmp_lists(
+    (a, a_tail): (&[Term], Option<&Term>),
+    (b, b_tail): (&[Term], Option<&Term>),
+) -> Ordering {
+    let common = a.len().min(b.len());
+    let ord = a[..common].cmp(&b[..common]);
+    if ord != Ordering::Equal {
+        return ord;
+    }
+    // the rank of what's left of a list, a proper list ends with `[]`
+    let rest = |elems: &[Term], tail: Option<&Term>| {
+        if elems.len() > common {
+            4
+        } else {
+            tail.map_or(3, Term::rank)
+        }
+    };
+    match (a.len() > common, b.len() > common, a_tail, b_tail) {
+        (false, false, Some(a_tail), Some(b_tail)) => a_tail.cmp(b_tail),
+        _ => rest(a, a_tail).cmp(&rest(b, b_tail)),
+    }
+}
+
+/// Conversion between values and Erlang terms
+pub trait Etf: Sized {
+    /// The term representing this value
+    fn to_term(&self) -> Term;
+
+    /// Read a value from its term
+    fn from_term(term: Term) -> Result<Self>;
+}
+
+impl Etf for Term {
+    fn to_term(&self) -> Term {
+        self.clone()
+    }
+
+    fn from_term(term: Term) -> Result<Self> {
+        Ok(term)
+    }
+}
+
+macro_rules! int_etf {
+    ($($int:ty),*) => {
+        $(
+            impl Etf for $int {
+                fn to_term(&self) -> Term {
+                    Term::Int(i128::from(*self))
+                }
+
+                fn from_term(term: Term) -> Result<Self> {
+                    match term {
+                        Term::Int(n) => <$int>::try_from(n).map_err(|_| {
+                            Error::UnexpectedTerm(concat!("an integer in ", stringify!($int)))
+                        }),
+                        _ => Err(Error::UnexpectedTerm("an integer")),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+int_etf!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl Etf for String {
+    fn to_term(&self) -> Term {
+        Term::Binary(self.as_bytes().to_vec())
+    }
+
+    fn from_term(term: Term) -> Result<Self> {
+        match term {
+            Term::Binary(bytes) => {
+                String::from_utf8(bytes).map_err(|_| Error::UnexpectedTerm("a UTF-8 binary"))
+            }
+            _ => Err(Error::UnexpectedTerm("a binary")),
+        }
+    }
+}
+
+impl Etf for Vec<u8> {
+    fn to_term(&self) -> Term {
+        Term::Binary(self.clone())
+    }
+
+    fn from_term(term: Term) -> Result<Self> {
+        match term {
+            Term::Binary(bytes) => Ok(bytes),
+            _ => Err(Error::UnexpectedTerm("a binary")),
+        }
+    }
+}
+
+/// A riak_dt_vclock is an orddict of actors to their counters
+impl<A: Actor + Etf> Etf for VClock<A> {
+    fn to_term(&self) -> Term {
+        orddict(
+            self.dots
+                .iter()
+                .map(|(actor, counter)| (actor.to_term(), counter.to_term())),
+        )
+    }
+
+    fn from_term(term: Term) -> Result<Self> {
+        let mut clock = VClock::new();
+        for (actor, counter) in dict_pairs(term)? {
+            clock
+                .dots
+                .insert(A::from_term(actor)?, counter_from_term(counter)?);
+        }
+        Ok(clock)
+    }
+}
+
+/// A dot is an `{Actor, Counter}` pair
+impl<A: Actor + Etf> Etf for Dot<A> {
+    fn to_term(&self) -> Term {
+        Term::Tuple(vec![self.actor.to_term(), self.counter.to_term()])
+    }
+
+    fn from_term(term: Term) -> Result<Self> {
+        let (actor, counter) = tuple2(term, "an {Actor, Counter} dot")?;
+        Ok(Dot::new(A::from_term(actor)?, counter_from_term(counter)?))
+    }
+}
+
+/// A CRDT with a riak_dt counterpart
+pub trait RiakDt: Etf {
+    /// The riak_dt module implementing the type, names the type of map fields
+    const MODULE: &'static str;
+
+    /// The tag riak_dt starts binaries of this type with
+    const TAG: u8;
+
+    /// The layout version we write, `Etf::to_term` writes this layout
+    const VERSION: u8;
+
+    /// Read a state in the given layout version
+    fn from_versioned_term(version: u8, term: Term) -> Result<Self> {
+        if version == Self::VERSION {
+            Self::from_term(term)
+        } else {
+            Err(Error::UnsupportedVersion(version))
+        }
+    }
+}
+
+/// Write a CRDT the way riak_dt's `to_binary/1` does
+pub fn to_binary<T: RiakDt>(val: &T) -> Vec<u8> {
+    let mut bytes = vec![T::TAG, T::VERSION];
+    bytes.extend(term_to_binary(&val.to_term()));
+    bytes
+}
+
+/// Read a CRDT written by riak_dt's `to_binary/1`
+pub fn from_binary<T: RiakDt>(bytes: &[u8]) -> Result<T> {
+    if bytes.len() < 2 {
+        return Err(Error::UnexpectedEnd);
+    }
+    if bytes[0] != T::TAG {
+        return Err(Error::WrongTag {
+            expected: T::TAG,
+            found: bytes[0],
+        });
+    }
+    T::from_versioned_term(bytes[1], binary_to_term(&bytes[2..])?)
+}
+
+/// Encode a term like Erlang's `term_to_binary/1`
+pub fn term_to_binary(term: &Term) -> Vec<u8> {
+    let mut bytes = vec![ETF_VERSION];
+    write_term(&mut bytes, term);
+    bytes
+}
+
+/// Encode a term like Erlang's `term_to_binary(Term, [{compressed, Level}])`,
+/// the term is only compressed when that makes it smaller.
+pub fn term_to_binary_compressed(term: &Term, level: u32) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut body = Vec::new();
+    write_term(&mut body, term);
+
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::new(level));
+    zlib.write_all(&body).expect("writes to a Vec don't fail");
+    let deflated = zlib.finish().expect("writes to a Vec don't fail");
+
+    let mut bytes = vec![ETF_VERSION];
+    if deflated.len() + 5 < body.len() {
+        bytes.push(COMPRESSED);
+        bytes.extend(&(body.len() as u32).to_be_bytes());
+        bytes.extend(deflated);
+    } else {
+        bytes.extend(body);
+    }
+    bytes
+}
+
+/// Decode a term like Erlang's `binary_to_term/1`, compressed or not
+pub fn binary_to_term(bytes: &[u8]) -> Result<Term> {
+    let mut dec = Decoder { bytes, depth: 0 };
+    let version = dec.byte()?;
+    if version != ETF_VERSION {
+        return Err(Error::NotEtf(version));
+    }
+    if dec.bytes.first() == Some(&COMPRESSED) {
+        dec.byte()?;
+        let size = dec.u32()? as usize;
+        // the size is untrusted, so it can't bound an allocation up front
+        let mut inflated = Vec::new();
+        ZlibDecoder::new(dec.bytes)
+            .take(size as u64 + 1)
+            .read_to_end(&mut inflated)
+            .map_err(Error::Compression)?;
+        if inflated.len() != size {
+            return Err(Error::Compression(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the inflated term doesn't have its recorded size",
+            )));
+        }
+        return Decoder {
+            bytes: &inflated,
+            depth: 0,
+        }
+        .finish();
+    }
+    dec.finish()
+}
+
+fn write_term(out: &mut Vec<u8>, term: &Term) {
+    match term {
+        Term::Int(n) => write_int(out, *n),
+        Term::Atom(atom) if atom.chars().all(|c| (c as u32) < 256) => {
+            out.push(ATOM_EXT);
+            out.extend(&(atom.chars().count() as u16).to_be_bytes());
+            out.extend(atom.chars().map(|c| c as u8));
+        }
+        Term::Atom(atom) => {
+            out.push(ATOM_UTF8_EXT);
+            out.extend(&(atom.len() as u16).to_be_bytes());
+            out.extend(atom.as_bytes());
+        }
+        Term::Tuple(elems) => {
+            if elems.len() < 256 {
+                out.push(SMALL_TUPLE_EXT);
+                out.push(elems.len() as u8);
+            } else {
+                out.push(LARGE_TUPLE_EXT);
+                out.extend(&(elems.len() as u32).to_be_bytes());
+            }
+            for elem in elems {
+                write_term(out, elem);
+            }
+        }
+        Term::List(elems) if elems.is_empty() => out.push(NIL_EXT),
+        Term::List(elems) => {
+            // Erlang writes lists of bytes as strings
+            let bytes: Option<Vec<u8>> = elems
+                .iter()
+                .map(|elem| match elem {
+                    Term::Int(n) => u8::try_from(*n).ok(),
+                    _ => None,
+                })
+                .collect();
+            match bytes {
+                Some(bytes) if bytes.len() <= usize::from(u16::MAX) => {
+                    out.push(STRING_EXT);
+                    out.extend(&(bytes.len() as u16).to_be_bytes());
+                    out.extend(bytes);
+                }
+                _ => write_list(out, elems, &Term::List(vec![])),
+            }
+        }
+        Term::ImproperList(elems, tail) => write_list(out, elems, tail),
+        Term::Binary(bytes) => {
+            out.push(BINARY_EXT);
+            out.extend(&(bytes.len() as u32).to_be_bytes());
+            out.extend(bytes);
+        }
+    }
+}
+
+fn write_int(out: &mut Vec<u8>, n: i128) {
+    if let Ok(byte) = u8::try_from(n) {
+        out.push(SMALL_INTEGER_EXT);
+        out.push(byte);
+    } else if let Ok(n) = i32::try_from(n) {
+        out.push(INTEGER_EXT);
+        out.extend(&n.to_be_bytes());
+    } else {
+        let mut magnitude = n.unsigned_abs();
+        let mut digits = Vec::new();
+        while magnitude > 0 {
+            digits.push(magnitude as u8);
+            magnitude >>= 8;
+        }
+        out.push(SMALL_BIG_EXT);
+        out.push(digits.len() as u8);
+        out.push((n < 0) as u8);
+        out.extend(digits);
+    }
+}
+
+fn write_list(out: &mut Vec<u8>, elems: &[Term], tail: &Term) {
+    out.push(LIST_EXT);
+    out.extend(&(elems.len() as u32).to_be_bytes());
+    for elem in elems {
+        write_term(out, elem);
+    }
+    write_term(out, tail);
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn finish(mut self) -> Result<Term> {
+        let term = self.term()?;
+        if !self.bytes.is_empty() {
+            return Err(Error::TrailingBytes);
+        }
+        Ok(term)
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            return Err(Error::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<usize> {
+        let bytes = self.take(2)?;
+        Ok(usize::from(u16::from_be_bytes([bytes[0], bytes[1]])))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // a length of elements that each take at least a byte
+    fn len(&mut self) -> Result<usize> {
+        let len = self.u32()? as usize;
+        if len > self.bytes.len() {
+            return Err(Error::UnexpectedEnd);
+        }
+        Ok(len)
+    }
+
+    fn terms(&mut self, n: usize) -> Result<Vec<Term>> {
+        (0..n).map(|_| self.term()).collect()
+    }
+
+    fn term(&mut self) -> Result<Term> {
+        if self.depth == MAX_DEPTH {
+            return Err(Error::TooDeep);
+        }
+        self.depth += 1;
+        let term = self.term_body();
+        self.depth -= 1;
+        term
+    }
+
+    fn term_body(&mut self) -> Result<Term> {
+        match self.byte()? {
+            SMALL_INTEGER_EXT => Ok(Term::Int(i128::from(self.byte()?))),
+            INTEGER_EXT => {
+                let bytes = self.take(4)?;
+                let n = i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                Ok(Term::Int(i128::from(n)))
+            }
+            SMALL_BIG_EXT => {
+                let n = usize::from(self.byte()?);
+                self.big(n)
+            }
+            LARGE_BIG_EXT => {
+                let n = self.len()?;
+                self.big(n)
+            }
+            ATOM_EXT => {
+                let len = self.u16()?;
+                Ok(Term::Atom(
+                    self.take(len)?.iter().map(|&b| char::from(b)).collect(),
+                ))
+            }
+            SMALL_ATOM_EXT => {
+                let len = usize::from(self.byte()?);
+                Ok(Term::Atom(
+                    self.take(len)?.iter().map(|&b| char::from(b)).collect(),
+                ))
+            }
+            ATOM_UTF8_EXT => {
+                let len = self.u16()?;
+                self.utf8_atom(len)
+            }
+            SMALL_ATOM_UTF8_EXT => {
+                let len = usize::from(self.byte()?);
+                self.utf8_atom(len)
+            }
+            SMALL_TUPLE_EXT => {
+                let arity = usize::from(self.byte()?);
+                Ok(Term::Tuple(self.terms(arity)?))
+            }
+            LARGE_TUPLE_EXT => {
+                let arity = self.len()?;
+                Ok(Term::Tuple(self.terms(arity)?))
+            }
+            NIL_EXT => Ok(Term::List(vec![])),
+            STRING_EXT => {
+                let len = self.u16()?;
+                let bytes = self.take(len)?;
+                Ok(Term::List(
+                    bytes.iter().map(|&b| Term::Int(i128::from(b))).collect(),
+                ))
+            }
+            LIST_EXT => {
+                let len = self.len()?;
+                let mut elems = self.terms(len)?;
+                match self.term()? {
+                    Term::List(rest) => {
+                        elems.extend(rest);
+                        Ok(Term::List(elems))
+                    }
+                    Term::ImproperList(rest, tail) => {
+                        elems.extend(rest);
+                        Ok(Term::ImproperList(elems, tail))
+                    }
+                    tail if elems.is_empty() => Ok(tail),
+                    tail => Ok(Term::ImproperList(elems, Box::new(tail))),
+                }
+            }
+            BINARY_EXT => {
+                let len = self.len()?;
+                Ok(Term::Binary(self.take(len)?.to_vec()))
+            }
+            tag => Err(Error::UnsupportedTerm(tag)),
+        }
+    }
+
+    fn big(&mut self, n: usize) -> Result<Term> {
+        let sign = self.byte()?;
+        let digits = self.take(n)?;
+        let mut magnitude: u128 = 0;
+        for (i, &digit) in digits.iter().enumerate() {
+            if digit == 0 {
+                continue;
+            }
+            if i >= 16 {
+                return Err(Error::IntegerOverflow);
+            }
+            magnitude |= u128::from(digit) << (8 * i);
+        }
+        let n = if sign == 0 {
+            i128::try_from(magnitude).map_err(|_| Error::IntegerOverflow)?
+        } else if magnitude == i128::MIN.unsigned_abs() {
+            i128::MIN
+        } else {
+            -i128::try_from(magnitude).map_err(|_| Error::IntegerOverflow)?
+        };
+        Ok(Term::Int(n))
+    }
+
+    fn utf8_atom(&mut self, len: usize) -> Result<Term> {
+        let bytes = self.take(len)?.to_vec();
+        String::from_utf8(bytes)
+            .map(Term::Atom)
+            .map_err(|_| Error::UnexpectedTerm("a UTF-8 atom"))
+    }
+}
+
+/// An orddict, a list of `{Key, Value}` pairs sorted by key
+pub(crate) fn orddict(pairs: impl IntoIterator<Item = (Term, Term)>) -> Term {
+    let mut pairs: Vec<(Term, Term)> = pairs.into_iter().collect();
+    pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Term::List(
+        pairs
+            .into_iter()
+            .map(|(key, val)| Term::Tuple(vec![key, val]))
+            .collect(),
+    )
+}
+
+/// A sorted list of terms
+pub(crate) fn sorted_list(terms: impl IntoIterator<Item = Term>) -> Term {
+    let mut terms: Vec<Term> = terms.into_iter().collect();
+    terms.sort();
+    Term::List(terms)
+}
+
+/// The key value pairs of an orddict or of an Erlang `dict`
+pub(crate) fn dict_pairs(term: Term) -> Result<Vec<(Term, Term)>> {
+    match term {
+        Term::Tuple(mut elems) if elems.len() == 9 && elems[0] == Term::Atom("dict".into()) => {
+            // {dict, Size, N, MaxN, BSo, ExpSize, ConSize, Empty, Segs}, the
+            // segments are tuples of buckets holding `[Key | Value]` cells
+            let segs = elems.pop().expect("a dict has 9 elements");
+            let mut pairs = Vec::new();
+            for seg in tuple(segs, "the segments of a dict")? {
+                for bucket in tuple(seg, "a segment of a dict")? {
+                    for cell in list(bucket, "a bucket of a dict")? {
+                        pairs.push(dict_cell(cell)?);
+                    }
+                }
+            }
+            Ok(pairs)
+        }
+        term => list(term, "an orddict or a dict")?
+            .into_iter()
+            .map(|pair| tuple2(pair, "an orddict {Key, Value} pair"))
+            .collect(),
+    }
+}
+
+fn dict_cell(cell: Term) -> Result<(Term, Term)> {
+    match cell {
+        Term::List(mut elems) if !elems.is_empty() => {
+            let key = elems.remove(0);
+            Ok((key, Term::List(elems)))
+        }
+        Term::ImproperList(mut elems, tail) => {
+            let key = elems.remove(0);
+            if elems.is_empty() {
+                Ok((key, *tail))
+            } else {
+                Ok((key, Term::ImproperList(elems, tail)))
+            }
+        }
+        _ => Err(Error::UnexpectedTerm("a [Key | Value] dict cell")),
+    }
+}
+
+/// The elements of a proper list
+pub(crate) fn list(term: Term, expected: &'static str) -> Result<Vec<Term>> {
+    match term {
+        Term::List(elems) => Ok(elems),
+        _ => Err(Error::UnexpectedTerm(expected)),
+    }
+}
+
+/// The elements of a tuple
+pub(crate) fn tuple(term: Term, expected: &'static str) -> Result<Vec<Term>> {
+    match term {
+        Term::Tuple(elems) => Ok(elems),
+        _ => Err(Error::UnexpectedTerm(expected)),
+    }
+}
+
+/// The elements of a 2-tuple
+pub(crate) fn tuple2(term: Term, expected: &'static str) -> Result<(Term, Term)> {
+    match term {
+        Term::Tuple(elems) if elems.len() == 2 => {
+            let mut elems = elems.into_iter();
+            Ok((elems.next().unwrap(), elems.next().unwrap()))
+        }
+        _ => Err(Error::UnexpectedTerm(expected)),
+    }
+}
+
+/// The elements of a 3-tuple
+pub(crate) fn tuple3(term: Term, expected: &'static str) -> Result<(Term, Term, Term)> {
+    match term {
+        Term::Tuple(elems) if elems.len() == 3 => {
+            let mut elems = elems.into_iter();
+            Ok((
+                elems.next().unwrap(),
+                elems.next().unwrap(),
+                elems.next().unwrap(),
+            ))
+        }
+        _ => Err(Error::UnexpectedTerm(expected)),
+    }
+}
+
+/// A counter of a clock, riak_dt never stores a 0 counter
+pub(crate) fn counter_from_term(term: Term) -> Result<u64> {
+    match u64::from_term(term)? {
+        0 => Err(Error::UnexpectedTerm("a positive counter")),
+        counter => Ok(counter),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use quickcheck::quickcheck;
+
+    use crate::{GCounter, LWWReg, Map, Orswot, PNCounter};
+
+    type TSet = Orswot<u8, u8>;
+    type TMap = Map<u8, TSet, u8>;
+
+    fn atom(name: &str) -> Term {
+        Term::Atom(name.to_string())
+    }
+
+    fn round_trips<T: RiakDt + PartialEq>(val: T) -> bool {
+        let bytes = to_binary(&val);
+        // a truncated binary is always rejected, never misread
+        (0..bytes.len()).all(|len| from_binary::<T>(&bytes[..len]).is_err())
+            && from_binary::<T>(&bytes).unwrap() == val
+    }
+
+    #[test]
+    fn test_term_to_binary_matches_erlang() {
+        // term_to_binary({a, 1})
+        let term = Term::Tuple(vec![atom("a"), Term::Int(1)]);
+        assert_eq!(
+            term_to_binary(&term),
+            vec![131, 104, 2, 100, 0, 1, 97, 97, 1]
+        );
+
+        // term_to_binary([1, 2]) is a string, term_to_binary([]) is nil
+        let bytes = Term::List(vec![Term::Int(1), Term::Int(2)]);
+        assert_eq!(term_to_binary(&bytes), vec![131, 107, 0, 2, 1, 2]);
+        assert_eq!(term_to_binary(&Term::List(vec![])), vec![131, 106]);
+
+        // term_to_binary(-1), term_to_binary(1 bsl 32), term_to_binary(-(1 bsl 32))
+        assert_eq!(
+            term_to_binary(&Term::Int(-1)),
+            vec![131, 98, 255, 255, 255, 255]
+        );
+        assert_eq!(
+            term_to_binary(&Term::Int(1 << 32)),
+            vec![131, 110, 5, 0, 0, 0, 0, 0, 1]
+        );
+        assert_eq!(
+            term_to_binary(&Term::Int(-(1 << 32))),
+            vec![131, 110, 5, 1, 0, 0, 0, 0, 1]
+        );
+
+        // term_to_binary([a | <<"b">>])
+        let cell = Term::ImproperList(vec![atom("a")], Box::new(Term::Binary(b"b".to_vec())));
+        assert_eq!(
+            term_to_binary(&cell),
+            vec![131, 108, 0, 0, 0, 1, 100, 0, 1, 97, 109, 0, 0, 0, 1, 98]
+        );
+    }
+
+    #[test]
+    fn test_erlang_term_order() {
+        let ordered = vec![
+            Term::Int(-3),
+            Term::Int(7),
+            atom("a"),
+            atom("b"),
+            Term::Tuple(vec![atom("z")]),
+            Term::Tuple(vec![atom("a"), atom("a")]),
+            Term::List(vec![]),
+            Term::List(vec![Term::Int(1)]),
+            Term::ImproperList(vec![Term::Int(1), Term::Int(2)], Box::new(atom("t"))),
+            Term::List(vec![Term::Int(1), Term::Int(2)]),
+            Term::List(vec![Term::Int(1), Term::Int(2), Term::Int(0)]),
+            Term::ImproperList(
+                vec![Term::Int(1), Term::Int(2)],
+                Box::new(Term::Binary(vec![])),
+            ),
+            Term::List(vec![Term::Int(2)]),
+            Term::Binary(vec![]),
+            Term::Binary(vec![0]),
+        ];
+        let mut sorted = ordered.clone();
+        sorted.reverse();
+        sorted.sort();
+        assert_eq!(sorted, ordered);
+    }
+
+    #[test]
+    fn test_compressed_terms() {
+        let set = Term::List((0..100).map(|n| Term::Binary(vec![n % 4; 8])).collect());
+        let compressed = term_to_binary_compressed(&set, 6);
+        assert_eq!(compressed[1], COMPRESSED);
+        assert!(compressed.len() < term_to_binary(&set).len());
+        assert_eq!(binary_to_term(&compressed).unwrap(), set);
+
+        // small terms are left uncompressed, like Erlang does
+        let small = Term::Int(1);
+        assert_eq!(term_to_binary_compressed(&small, 6), term_to_binary(&small));
+
+        let mut lying = compressed.clone();
+        lying[5] += 1;
+        assert!(matches!(binary_to_term(&lying), Err(Error::Compression(_))));
+    }
+
+    #[test]
+    fn test_rejects_unsupported_terms() {
+        // term_to_binary(1.0)
+        let float = [131, 70, 63, 240, 0, 0, 0, 0, 0, 0];
+        assert!(matches!(
+            binary_to_term(&float),
+            Err(Error::UnsupportedTerm(70))
+        ));
+        assert!(matches!(
+            binary_to_term(&[130, 106]),
+            Err(Error::NotEtf(130))
+        ));
+        assert!(matches!(
+            binary_to_term(&[131, 106, 106]),
+            Err(Error::TrailingBytes)
+        ));
+
+        // a 17 byte bignum
+        let mut big = vec![131, 110, 17, 0];
+        big.extend(vec![1; 17]);
+        assert!(matches!(binary_to_term(&big), Err(Error::IntegerOverflow)));
+
+        // a clock with a zero counter
+        let clock = Term::List(vec![Term::Tuple(vec![Term::Int(1), Term::Int(0)])]);
+        assert!(VClock::<u8>::from_term(clock).is_err());
+    }
+
+    #[test]
+    fn test_rejects_deeply_nested_terms() {
+        // {{{...{}...}}}, each level a one element tuple
+        let nested = |depth: usize| {
+            let mut bytes = vec![ETF_VERSION];
+            bytes.extend([SMALL_TUPLE_EXT, 1].repeat(depth - 1));
+            bytes.extend(&[SMALL_TUPLE_EXT, 0]);
+            bytes
+        };
+        assert!(binary_to_term(&nested(MAX_DEPTH)).is_ok());
+        assert!(matches!(
+            binary_to_term(&nested(MAX_DEPTH + 1)),
+            Err(Error::TooDeep)
+        ));
+        assert!(matches!(
+            binary_to_term(&nested(1_000_000)),
+            Err(Error::TooDeep)
+        ));
+    }
+
+    quickcheck! {
+        fn prop_term_round_trips(ints: Vec<i64>, bins: Vec<Vec<u8>>, atoms: Vec<u8>) -> bool {
+            let ints = Term::List(ints.into_iter().map(|n| Term::Int(i128::from(n) << 40)).collect());
+            let bins = Term::Tuple(bins.into_iter().map(Term::Binary).collect());
+            let atoms = Term::List(atoms.into_iter().map(|n| Term::Atom(n.to_string())).collect());
+            let tail = Box::new(atom("tail"));
+            let term = Term::ImproperList(vec![ints, bins, atoms], tail);
+            binary_to_term(&term_to_binary(&term)).unwrap() == term
+                && binary_to_term(&term_to_binary_compressed(&term, 9)).unwrap() == term
+        }
+
+        fn prop_gcounter_round_trips(counter: GCounter<u8>) -> bool {
+            round_trips(counter)
+        }
+
+        fn prop_pncounter_round_trips(counter: PNCounter<u8>) -> bool {
+            round_trips(counter)
+        }
+
+        fn prop_lwwreg_round_trips(val: String, marker: u64) -> bool {
+            round_trips(LWWReg { val, marker })
+        }
+
+        fn prop_orswot_round_trips(set: TSet) -> bool {
+            round_trips(set)
+        }
+
+        fn prop_map_round_trips(map: TMap) -> bool {
+            // riak_dt has no place for the dots removed from a field
+            from_binary::<TMap>(&to_binary(&map)).unwrap() == map.without_removed()
+                && round_trips(map.without_removed())
+        }
+    }
+}
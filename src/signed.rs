@@ -0,0 +1,404 @@
+//! Signed Op's, for replicas that don't trust every client.
+//!
+//! Nothing stops a client from building an Op with another actor's `Dot`. A
+//! `Signed` Op carries its author's public key and an ed25519 signature over
+//! the encoded Op. A `Keyring` binds each actor to the one key allowed to
+//! write as it, and an `Authenticated` CRDT only applies an Op if every dot in
+//! it belongs to the actor bound to the key that signed it.
+//!
+//! An `MVReg` put carries the clock it was written under rather than a dot,
+//! its author is the one actor of that clock ahead of the replica applying
+//! it. Removes carry a clock as well but make no new dot, so they don't name
+//! an author. They are accepted from any bound key, and can only drop what
+//! their clock has seen.
+//!
+//! This module is behind the `signed` feature.
+//!
+//! ```
+//! use crdts::signed::{Authenticated, Keyring, Signed, SigningKey};
+//! use crdts::Orswot;
+//!
+//! let alice = SigningKey::from_bytes(&[1; 32]);
+//! let mallory = SigningKey::from_bytes(&[2; 32]);
+//!
+//! let mut keyring = Keyring::new();
+//! keyring.bind("alice".to_string(), alice.verifying_key()).unwrap();
+//! keyring.bind("mallory".to_string(), mallory.verifying_key()).unwrap();
+//! let mut set: Authenticated<Orswot<u8, String>, String> =
+//!     Authenticated::new(Orswot::new(), keyring);
+//!
+//! let op = set.state().add(1, set.state().read_ctx().derive_add_ctx("alice".to_string()));
+//! set.apply(&Signed::sign(&op, &alice)).unwrap();
+//!
+//! // mallory can't write as alice
+//! let forged = set.state().add(2, set.state().read_ctx().derive_add_ctx("alice".to_string()));
+//! assert!(set.apply(&Signed::sign(&forged, &mallory)).is_err());
+//! assert!(!set.state().contains(&2).val);
+//! ```
+
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+use ed25519_dalek::{Signature, Signer};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+pub use crate::policy::{Authored, Seen};
+
+use crate::canonical;
+use crate::error::{Error, Result};
+use crate::policy::{Anyone, Policy};
+use crate::traits::CmRDT;
+use crate::vclock::{Actor, VClock};
+
+/// An Op signed by its author.
+///
+/// The signature covers the encoded Op, which is kept as it was signed, so
+/// Op's whose encoding depends on how they were built (e.g. the `HashSet`
+/// of an `Orswot` Op) still verify after a round trip.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signed<Op> {
+    payload: Vec<u8>,
+    key: VerifyingKey,
+    signature: Signature,
+    op: PhantomData<Op>,
+}
+
+impl<Op: Serialize + DeserializeOwned> Signed<Op> {
+    /// Sign an Op with its author's key
+    pub fn sign(op: &Op, key: &SigningKey) -> Self {
+        let payload = canonical::encode(op);
+        let signature = key.sign(&payload);
+        Signed {
+            payload,
+            key: key.verifying_key(),
+            signature,
+            op: PhantomData,
+        }
+    }
+
+    /// The public key the Op claims to be signed with
+    pub fn key(&self) -> &VerifyingKey {
+        &self.key
+    }
+
+    /// Check the signature and decode the Op. This says nothing about which
+    /// actor the key belongs to, see `Keyring::verify`.
+    pub fn verify(&self) -> Result<Op> {
+        self.key
+            .verify_strict(&self.payload, &self.signature)
+            .map_err(|_| Error::BadSignature)?;
+        Ok(bincode::deserialize(&self.payload)?)
+    }
+}
+
+/// Binds actors to the keys allowed to sign their Op's.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keyring<A: Ord> {
+    keys: BTreeMap<A, VerifyingKey>,
+}
+
+impl<A: Actor> Default for Keyring<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Actor> Keyring<A> {
+    /// An empty keyring
+    pub fn new() -> Self {
+        Keyring {
+            keys: BTreeMap::new(),
+        }
+    }
+
+    /// Bind an actor to a key. An actor's key can't be changed once bound,
+    /// a new key writes as a new actor.
+    pub fn bind(&mut self, actor: A, key: VerifyingKey) -> Result<()> {
+        match self.keys.get(&actor) {
+            Some(bound) if *bound != key => Err(Error::KeyConflict),
+            _ => {
+                self.keys.insert(actor, key);
+                Ok(())
+            }
+        }
+    }
+
+    /// The key bound to the actor
+    pub fn key(&self, actor: &A) -> Option<&VerifyingKey> {
+        self.keys.get(actor)
+    }
+
+    /// Check a signed Op's signature, returning the actor bound to its key
+    /// along with the Op. Whether the Op's dots belong to that actor depends
+    /// on the state it's applied to, see `Keyring::check_authors`.
+    pub fn verify<Op>(&self, signed: &Signed<Op>) -> Result<(A, Op)>
+    where
+        Op: Serialize + DeserializeOwned,
+    {
+        let op = signed.verify()?;
+        let signer = self
+            .keys
+            .iter()
+            .find(|(_, key)| *key == signed.key())
+            .map(|(actor, _)| actor.clone())
+            .ok_or(Error::UnknownSigner)?;
+        Ok((signer, op))
+    }
+
+    /// Check that every dot an Op carries belongs to `signer`, `seen` is the
+    /// clock of the replica the Op is applied to.
+    pub fn check_authors<Op: Authored<A>>(
+        &self,
+        signer: &A,
+        op: &Op,
+        seen: &VClock<A>,
+    ) -> Result<()> {
+        if op.authors(seen).into_iter().all(|actor| actor == signer) {
+            Ok(())
+        } else {
+            Err(Error::WrongSigner)
+        }
+    }
+}
+
+/// Wraps a CRDT so that it only applies Op's signed by their authors, and
+/// permitted by a policy for the actor that signed them (see the `policy`
+/// module).
+#[derive(Debug, Clone)]
+pub struct Authenticated<T, A: Ord, P = Anyone> {
+    state: T,
+    keyring: Keyring<A>,
+    policy: P,
+}
+
+impl<T, A> Authenticated<T, A>
+where
+    T: CmRDT + Seen<A>,
+    T::Op: Authored<A> + Serialize + DeserializeOwned,
+    A: Actor,
+{
+    /// Wrap a state, trusting the keys in the keyring
+    pub fn new(state: T, keyring: Keyring<A>) -> Self {
+        Self::with_policy(state, keyring, Anyone)
+    }
+}
+
+impl<T, A, P> Authenticated<T, A, P>
+where
+    T: CmRDT + Seen<A>,
+    T::Op: Authored<A> + Serialize + DeserializeOwned,
+    A: Actor,
+    P: Policy<A, T::Op>,
+{
+    /// Wrap a state, trusting the keys in the keyring and checking the Op's
+    /// of their actors against the policy
+    pub fn with_policy(state: T, keyring: Keyring<A>, policy: P) -> Self {
+        Authenticated {
+            state,
+            keyring,
+            policy,
+        }
+    }
+
+    /// The current state
+    pub fn state(&self) -> &T {
+        &self.state
+    }
+
+    /// The keys Op's are checked against
+    pub fn keyring(&self) -> &Keyring<A> {
+        &self.keyring
+    }
+
+    /// The keys Op's are checked against, e.g. to bind a new actor
+    pub fn keyring_mut(&mut self) -> &mut Keyring<A> {
+        &mut self.keyring
+    }
+
+    /// The policy Op's are checked against
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    /// The policy Op's are checked against, e.g. to change grants
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+
+    /// Unwrap the state
+    pub fn into_inner(self) -> T {
+        self.state
+    }
+
+    /// Verify a signed Op and apply it, returning the actor that signed it.
+    /// An Op that fails verification, isn't permitted or fails validation
+    /// leaves the state untouched.
+    pub fn apply(&mut self, signed: &Signed<T::Op>) -> Result<A> {
+        let (signer, op) = self.keyring.verify(signed)?;
+        // authors are derived against our clock, validate first so an Op
+        // we're not ready for is a causal gap rather than a forgery
+        self.state.validate_op(&op)?;
+        self.keyring
+            .check_authors(&signer, &op, &self.state.seen())?;
+        self.policy.check(&signer, &op)?;
+        self.state.apply(op);
+        Ok(signer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::policy::{Grant, Grants};
+    use crate::{orswot, GCounter, MVReg, Map, Orswot};
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn keyring() -> Keyring<u8> {
+        let mut keyring = Keyring::new();
+        keyring.bind(1, key(1).verifying_key()).unwrap();
+        keyring.bind(2, key(2).verifying_key()).unwrap();
+        keyring
+    }
+
+    #[test]
+    fn test_author_can_write() {
+        let mut set: Authenticated<Orswot<u8, u8>, u8> =
+            Authenticated::new(Orswot::new(), keyring());
+        let op = set.state().add(7, set.state().read_ctx().derive_add_ctx(1));
+        set.apply(&Signed::sign(&op, &key(1))).unwrap();
+        assert!(set.state().contains(&7).val);
+
+        let op = set.state().rm(7, set.state().contains(&7).derive_rm_ctx());
+        set.apply(&Signed::sign(&op, &key(2))).unwrap();
+        assert!(!set.state().contains(&7).val);
+    }
+
+    #[test]
+    fn test_forged_dot_is_rejected() {
+        let mut counter = Authenticated::new(GCounter::new(), keyring());
+        let op = counter.state().inc(1);
+        assert!(matches!(
+            counter.apply(&Signed::sign(&op, &key(2))),
+            Err(Error::WrongSigner)
+        ));
+
+        let op = counter.state().inc(3);
+        assert!(matches!(
+            counter.apply(&Signed::sign(&op, &key(3))),
+            Err(Error::UnknownSigner)
+        ));
+        assert_eq!(counter.state(), &GCounter::new());
+    }
+
+    #[test]
+    fn test_tampered_op_is_rejected() {
+        let counter = GCounter::new();
+        let mut signed = Signed::sign(&counter.inc(1), &key(1));
+        signed.key = key(2).verifying_key();
+        assert!(matches!(signed.verify(), Err(Error::BadSignature)));
+
+        let mut signed = Signed::sign(&counter.inc(1), &key(1));
+        let last = signed.payload.len() - 1;
+        signed.payload[last] ^= 1;
+        assert!(matches!(signed.verify(), Err(Error::BadSignature)));
+    }
+
+    #[test]
+    fn test_nested_op_must_share_author() {
+        let mut map: Authenticated<Map<u8, Orswot<u8, u8>, u8>, u8> =
+            Authenticated::new(Map::new(), keyring());
+        let op = map
+            .state()
+            .update(0, map.state().len().derive_add_ctx(1), |set, _| {
+                set.add(7, set.read_ctx().derive_add_ctx(2))
+            });
+        assert!(matches!(
+            map.apply(&Signed::sign(&op, &key(1))),
+            Err(Error::WrongSigner)
+        ));
+
+        let op = map
+            .state()
+            .update(0, map.state().len().derive_add_ctx(1), |set, ctx| {
+                set.add(7, ctx)
+            });
+        map.apply(&Signed::sign(&op, &key(1))).unwrap();
+        assert!(map.state().get(&0).val.unwrap().contains(&7).val);
+    }
+
+    #[test]
+    fn test_forged_put_is_rejected() {
+        let mut reg: Authenticated<MVReg<u8, u8>, u8> = Authenticated::new(MVReg::new(), keyring());
+        let op = reg
+            .state()
+            .write(7, reg.state().read_ctx().derive_add_ctx(1));
+        assert_eq!(reg.apply(&Signed::sign(&op, &key(1))).unwrap(), 1);
+
+        let op = reg
+            .state()
+            .write(8, reg.state().read_ctx().derive_add_ctx(1));
+        assert!(matches!(
+            reg.apply(&Signed::sign(&op, &key(2))),
+            Err(Error::WrongSigner)
+        ));
+        assert_eq!(reg.state().read().val, vec![7]);
+
+        let mut map: Authenticated<Map<u8, MVReg<u8, u8>, u8>, u8> =
+            Authenticated::new(Map::new(), keyring());
+        let op = map
+            .state()
+            .update(0, map.state().len().derive_add_ctx(2), |reg, ctx| {
+                reg.write(7, ctx)
+            });
+        map.apply(&Signed::sign(&op, &key(2))).unwrap();
+        assert_eq!(map.state().get(&0).val.unwrap().read().val, vec![7]);
+    }
+
+    #[test]
+    fn test_policy_checks_signer() {
+        let mut grants = Grants::new();
+        grants.grant(1, Grant::all());
+        grants.grant(2, Grant::add_only());
+        let mut set: Authenticated<Orswot<u8, u8>, u8, _> =
+            Authenticated::with_policy(Orswot::new(), keyring(), grants);
+        let op = set.state().add(7, set.state().read_ctx().derive_add_ctx(2));
+        set.apply(&Signed::sign(&op, &key(2))).unwrap();
+
+        let op = set.state().rm(7, set.state().contains(&7).derive_rm_ctx());
+        assert!(matches!(
+            set.apply(&Signed::sign(&op, &key(2))),
+            Err(Error::PermissionDenied)
+        ));
+        assert!(set.state().contains(&7).val);
+        set.apply(&Signed::sign(&op, &key(1))).unwrap();
+        assert!(!set.state().contains(&7).val);
+    }
+
+    #[test]
+    fn test_signed_op_survives_round_trip() {
+        let set: Orswot<u8, u8> = Orswot::new();
+        let op = set.add_all(0..20, set.read_ctx().derive_add_ctx(1));
+        let signed = Signed::sign(&op, &key(1));
+        let bytes = bincode::serialize(&signed).unwrap();
+        let decoded: Signed<orswot::Op<u8, u8>> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(keyring().verify(&decoded).unwrap(), (1, op));
+    }
+
+    #[test]
+    fn test_rebinding_is_rejected() {
+        let mut keyring = keyring();
+        keyring.bind(1, key(1).verifying_key()).unwrap();
+        assert!(matches!(
+            keyring.bind(1, key(2).verifying_key()),
+            Err(Error::KeyConflict)
+        ));
+        assert_eq!(keyring.key(&1), Some(&key(1).verifying_key()));
+    }
+}
@@ -0,0 +1,552 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::{self, Debug, Display};
+
+use crate::error::Error;
+use crate::traits::{CmRDT, CvRDT};
+
+/// A small deterministic random number generator (splitmix64), the same seed
+/// always produces the same sequence of numbers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// Create a generator from a seed
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    /// The next number in the sequence
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, `n` must be non-zero
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// A single step of a simulation.
+///
+/// Messages wait on the link between two replicas until a `Deliver` or
+/// `Duplicate` picks them, so messages are delayed and reordered by the
+/// schedule itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The replica makes an edit and sends the Op to every other replica,
+    /// the seed drives the edit function.
+    Edit {
+        /// the editing replica
+        replica: usize,
+        /// seed for the edit function
+        seed: u64,
+    },
+    /// `from` sends its entire state to `to`
+    Sync {
+        /// the sending replica
+        from: usize,
+        /// the receiving replica
+        to: usize,
+    },
+    /// Deliver the `nth` (modulo the number waiting) message on the link
+    /// from `from` to `to`, nothing happens if the link is empty or cut.
+    Deliver {
+        /// the sending replica
+        from: usize,
+        /// the receiving replica
+        to: usize,
+        /// which of the waiting messages to deliver
+        nth: usize,
+    },
+    /// Same as `Deliver`, but the message stays on the link and will be
+    /// delivered again.
+    Duplicate {
+        /// the sending replica
+        from: usize,
+        /// the receiving replica
+        to: usize,
+        /// which of the waiting messages to deliver
+        nth: usize,
+    },
+    /// Cut the network in two, the replicas in `side` can't reach the others.
+    Partition {
+        /// one side of the partition
+        side: BTreeSet<usize>,
+    },
+    /// Heal the partition
+    Heal,
+}
+
+/// The weights used to pick each kind of Event when generating a schedule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// number of Events in a schedule
+    pub steps: usize,
+    /// weight of `Edit` events
+    pub edits: u32,
+    /// weight of `Sync` events
+    pub syncs: u32,
+    /// weight of `Deliver` events
+    pub deliveries: u32,
+    /// weight of `Duplicate` events
+    pub duplicates: u32,
+    /// weight of `Partition` and `Heal` events
+    pub partitions: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            steps: 100,
+            edits: 8,
+            syncs: 2,
+            deliveries: 12,
+            duplicates: 2,
+            partitions: 1,
+        }
+    }
+}
+
+/// Why a simulation failed.
+#[derive(Debug, PartialEq)]
+pub enum Reason {
+    /// The replicas did not converge once every message was delivered
+    Diverged,
+    /// A replica rejected an Op for a reason other than missing its causal past
+    Rejected(usize, Error),
+    /// A replica still had Op's waiting on their causal past after every
+    /// message was delivered
+    Stuck(usize),
+}
+
+/// A failing schedule along with the replicas it produced.
+#[derive(Debug)]
+pub struct Failure<T> {
+    /// the seed the schedule was generated from
+    pub seed: u64,
+    /// why the schedule failed
+    pub reason: Reason,
+    /// the (shrunk) failing schedule
+    pub schedule: Vec<Event>,
+    /// the replicas at the point of failure
+    pub replicas: Vec<T>,
+}
+
+impl<T: Debug> Display for Failure<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "seed {}: {:?}", self.seed, self.reason)?;
+        writeln!(f)?;
+        writeln!(f, "schedule: [")?;
+        for event in self.schedule.iter() {
+            writeln!(f, "  {:?},", event)?;
+        }
+        writeln!(f, "]")?;
+        writeln!(f)?;
+        writeln!(f, "replicas: [")?;
+        for replica in self.replicas.iter() {
+            writeln!(f, "  {:?},", replica)?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Msg<T: CmRDT> {
+    Op(T::Op),
+    State(T),
+}
+
+/// Runs replicas of a CRDT over a simulated network.
+///
+/// Replicas sync by shipping Op's and by shipping their entire state.
+/// An Op that arrives before the Op's it depends on (rejected by `try_apply`
+/// with `CausalGap` or `RmClockAhead`) is held back by the receiver until it
+/// can be applied. At the end of a schedule the network is healed, every
+/// waiting message is delivered and all replicas must be equal.
+///
+/// ``` rust
+/// use crdts::sim::{Config, Sim};
+/// use crdts::{CmRDT, Orswot};
+///
+/// let replicas = vec![Orswot::<u8, usize>::new(); 3];
+/// let sim = Sim::new(replicas, Config::default(), |set, replica, rng| {
+///     // remove the member if it's present, otherwise add it
+///     let member = rng.below(4) as u8;
+///     let contains = set.contains(&member);
+///     if contains.val {
+///         set.rm(member, contains.derive_rm_ctx())
+///     } else {
+///         set.add(member, contains.derive_add_ctx(replica))
+///     }
+/// });
+///
+/// for seed in 0..10 {
+///     if let Err(failure) = sim.check(seed) {
+///         panic!("{}", failure);
+///     }
+/// }
+/// ```
+pub struct Sim<T, F> {
+    replicas: Vec<T>,
+    config: Config,
+    edit: F,
+}
+
+struct Network<T: CmRDT> {
+    replicas: Vec<T>,
+    links: BTreeMap<(usize, usize), VecDeque<Msg<T>>>,
+    held: Vec<Vec<T::Op>>,
+    side: Option<BTreeSet<usize>>,
+}
+
+impl<T, F> Sim<T, F>
+where
+    T: CmRDT + CvRDT + Debug + Clone + PartialEq,
+    T::Op: Clone,
+    F: Fn(&T, usize, &mut Rng) -> T::Op,
+{
+    /// Create a simulation over the given initial replicas. `edit` is called
+    /// with the replica, its index and a seeded `Rng` to produce an Op.
+    pub fn new(replicas: Vec<T>, config: Config, edit: F) -> Self {
+        Sim {
+            replicas,
+            config,
+            edit,
+        }
+    }
+
+    /// Generate a schedule from a seed
+    pub fn schedule(&self, seed: u64) -> Vec<Event> {
+        let mut rng = Rng::new(seed);
+        let n = self.replicas.len();
+        let c = &self.config;
+        let total = c.edits + c.syncs + c.deliveries + c.duplicates + c.partitions;
+        let mut partitioned = false;
+        let mut schedule = Vec::with_capacity(c.steps);
+
+        if n < 2 || total == 0 {
+            return schedule;
+        }
+
+        for _ in 0..c.steps {
+            let from = rng.below(n);
+            let to = (from + 1 + rng.below(n - 1)) % n;
+            let nth = rng.below(n);
+            let roll = rng.below(total as usize) as u32;
+
+            let event = if roll < c.edits {
+                Event::Edit {
+                    replica: from,
+                    seed: rng.next_u64(),
+                }
+            } else if roll < c.edits + c.syncs {
+                Event::Sync { from, to }
+            } else if roll < c.edits + c.syncs + c.deliveries {
+                Event::Deliver { from, to, nth }
+            } else if roll < total - c.partitions {
+                Event::Duplicate { from, to, nth }
+            } else if partitioned {
+                partitioned = false;
+                Event::Heal
+            } else {
+                partitioned = true;
+                Event::Partition {
+                    side: (0..n).filter(|_| rng.below(2) == 0).collect(),
+                }
+            };
+            schedule.push(event);
+        }
+        schedule
+    }
+
+    /// Run a schedule and return the converged replicas
+    pub fn run(&self, schedule: &[Event]) -> Result<Vec<T>, (Reason, Vec<T>)> {
+        let mut net = Network {
+            replicas: self.replicas.clone(),
+            links: BTreeMap::new(),
+            held: vec![Vec::new(); self.replicas.len()],
+            side: None,
+        };
+
+        for event in schedule.iter() {
+            net.step(event, &self.edit)?;
+        }
+        net.flush()?;
+
+        if net.replicas.windows(2).all(|pair| pair[0] == pair[1]) {
+            Ok(net.replicas)
+        } else {
+            Err((Reason::Diverged, net.replicas))
+        }
+    }
+
+    /// Run the schedule generated from `seed`, on failure the schedule is
+    /// shrunk to a minimal schedule that still fails.
+    pub fn check(&self, seed: u64) -> Result<Vec<T>, Failure<T>> {
+        let schedule = self.schedule(seed);
+        match self.run(&schedule) {
+            Ok(replicas) => Ok(replicas),
+            Err(_) => {
+                let schedule = self.shrink(schedule);
+                let (reason, replicas) = self
+                    .run(&schedule)
+                    .expect_err("shrinking keeps the schedule failing");
+                Err(Failure {
+                    seed,
+                    reason,
+                    schedule,
+                    replicas,
+                })
+            }
+        }
+    }
+
+    /// Remove events from a failing schedule for as long as it keeps failing,
+    /// first in large chunks then one event at a time.
+    pub fn shrink(&self, mut schedule: Vec<Event>) -> Vec<Event> {
+        let mut chunk = schedule.len() / 2;
+        while chunk > 0 {
+            let mut start = 0;
+            while start < schedule.len() {
+                let end = (start + chunk).min(schedule.len());
+                let mut candidate = schedule.clone();
+                candidate.drain(start..end);
+                if self.run(&candidate).is_err() {
+                    schedule = candidate;
+                } else {
+                    start += chunk;
+                }
+            }
+            chunk /= 2;
+        }
+        schedule
+    }
+}
+
+impl<T> Network<T>
+where
+    T: CmRDT + CvRDT + Clone,
+    T::Op: Clone,
+{
+    fn step<F>(&mut self, event: &Event, edit: &F) -> Result<(), (Reason, Vec<T>)>
+    where
+        F: Fn(&T, usize, &mut Rng) -> T::Op,
+    {
+        let n = self.replicas.len();
+        match event {
+            Event::Edit { replica, seed } => {
+                let replica = *replica % n;
+                let op = edit(&self.replicas[replica], replica, &mut Rng::new(*seed));
+                self.deliver(replica, Msg::Op(op.clone()))?;
+                for peer in (0..n).filter(|peer| *peer != replica) {
+                    self.send(replica, peer, Msg::Op(op.clone()));
+                }
+            }
+            Event::Sync { from, to } => {
+                let state = self.replicas[*from % n].clone();
+                self.send(*from % n, *to % n, Msg::State(state));
+            }
+            Event::Deliver { from, to, nth } => {
+                if let Some(msg) = self.take(*from % n, *to % n, *nth, false) {
+                    self.deliver(*to % n, msg)?;
+                }
+            }
+            Event::Duplicate { from, to, nth } => {
+                if let Some(msg) = self.take(*from % n, *to % n, *nth, true) {
+                    self.deliver(*to % n, msg)?;
+                }
+            }
+            Event::Partition { side } => self.side = Some(side.clone()),
+            Event::Heal => self.side = None,
+        }
+        Ok(())
+    }
+
+    fn is_cut(&self, from: usize, to: usize) -> bool {
+        match &self.side {
+            Some(side) => side.contains(&from) != side.contains(&to),
+            None => false,
+        }
+    }
+
+    // Take the nth message waiting on a link, `keep` leaves it on the link
+    // so it is delivered again later.
+    fn take(&mut self, from: usize, to: usize, nth: usize, keep: bool) -> Option<Msg<T>> {
+        if self.is_cut(from, to) {
+            return None;
+        }
+        let link = self.links.get_mut(&(from, to))?;
+        if link.is_empty() {
+            return None;
+        }
+        let nth = nth % link.len();
+        if keep {
+            Some(link[nth].clone())
+        } else {
+            link.remove(nth)
+        }
+    }
+
+    fn send(&mut self, from: usize, to: usize, msg: Msg<T>) {
+        self.links.entry((from, to)).or_default().push_back(msg);
+    }
+
+    fn deliver(&mut self, to: usize, msg: Msg<T>) -> Result<(), (Reason, Vec<T>)> {
+        match msg {
+            Msg::Op(op) => self.held[to].push(op),
+            Msg::State(state) => self.replicas[to].merge(state),
+        }
+
+        // apply held Op's until none of the remaining can be applied
+        loop {
+            let held = std::mem::take(&mut self.held[to]);
+            let before = held.len();
+            for op in held {
+                match self.replicas[to].try_apply(op.clone()) {
+                    Ok(()) => (),
+                    Err(Error::CausalGap) | Err(Error::RmClockAhead) => self.held[to].push(op),
+                    Err(err) => return Err((Reason::Rejected(to, err), self.replicas.clone())),
+                }
+            }
+            if self.held[to].len() == before {
+                return Ok(());
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), (Reason, Vec<T>)> {
+        self.side = None;
+        let links = std::mem::take(&mut self.links);
+        for ((_, to), link) in links {
+            for msg in link {
+                self.deliver(to, msg)?;
+            }
+        }
+        match self.held.iter().position(|held| !held.is_empty()) {
+            Some(replica) => Err((Reason::Stuck(replica), self.replicas.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{GCounter, MVReg, Orswot, PNCounter};
+
+    fn check_seeds<T, F>(sim: &Sim<T, F>)
+    where
+        T: CmRDT + CvRDT + Debug + Clone + PartialEq,
+        T::Op: Clone,
+        F: Fn(&T, usize, &mut Rng) -> T::Op,
+    {
+        for seed in 0..50 {
+            if let Err(failure) = sim.check(seed) {
+                panic!("{}", failure);
+            }
+        }
+    }
+
+    #[test]
+    fn test_orswot_converges() {
+        let sim = Sim::new(
+            vec![Orswot::<u8, usize>::new(); 4],
+            Config::default(),
+            |set, replica, rng| {
+                let member = rng.below(5) as u8;
+                let contains = set.contains(&member);
+                if contains.val {
+                    set.rm(member, contains.derive_rm_ctx())
+                } else {
+                    set.add(member, contains.derive_add_ctx(replica))
+                }
+            },
+        );
+        check_seeds(&sim);
+    }
+
+    #[test]
+    fn test_mvreg_converges() {
+        let sim = Sim::new(
+            vec![MVReg::<u8, usize>::new(); 3],
+            Config::default(),
+            |reg, replica, rng| reg.write(rng.below(10) as u8, reg.read().derive_add_ctx(replica)),
+        );
+        check_seeds(&sim);
+    }
+
+    #[test]
+    fn test_counters_converge() {
+        let gcounter = Sim::new(
+            vec![GCounter::<usize>::new(); 3],
+            Config::default(),
+            |counter, replica, _| counter.inc(replica),
+        );
+        check_seeds(&gcounter);
+
+        let pncounter = Sim::new(
+            vec![PNCounter::<usize>::new(); 3],
+            Config::default(),
+            |counter, replica, rng| {
+                if rng.below(2) == 0 {
+                    counter.inc(replica)
+                } else {
+                    counter.dec(replica)
+                }
+            },
+        );
+        check_seeds(&pncounter);
+    }
+
+    #[test]
+    fn test_schedule_is_deterministic() {
+        let sim = Sim::new(
+            vec![GCounter::<usize>::new(); 3],
+            Config::default(),
+            |counter, replica, _| counter.inc(replica),
+        );
+        assert_eq!(sim.schedule(7), sim.schedule(7));
+        assert_ne!(sim.schedule(7), sim.schedule(8));
+    }
+
+    // A register where the last delivered write wins, replicas that see
+    // writes in different orders disagree.
+    #[derive(Debug, Clone, PartialEq, Default)]
+    struct LastDelivered(u8);
+
+    impl CmRDT for LastDelivered {
+        type Op = u8;
+
+        fn apply(&mut self, op: u8) {
+            self.0 = op;
+        }
+    }
+
+    impl CvRDT for LastDelivered {
+        fn merge(&mut self, other: Self) {
+            self.0 = other.0;
+        }
+    }
+
+    #[test]
+    fn test_divergence_is_shrunk() {
+        let sim = Sim::new(
+            vec![LastDelivered::default(); 3],
+            Config::default(),
+            |_, replica, _| replica as u8 + 1,
+        );
+        let failure = (0..10)
+            .filter_map(|seed| sim.check(seed).err())
+            .next()
+            .expect("a last-delivered register diverges");
+
+        assert_eq!(failure.reason, Reason::Diverged);
+        assert!(failure.schedule.len() <= 3);
+        assert!(sim.run(&failure.schedule).is_err());
+    }
+}
@@ -0,0 +1,603 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::oplog::OpLog;
+use crate::replica::Replica;
+use crate::traits::{CmRDT, CvRDT};
+use crate::vclock::{Actor, Dot, VClock};
+
+/// A replica along with the log of the Op's it was built from, logged
+/// under the dot of the actor that made each Op.
+///
+/// Op's every tracked peer has acknowledged are trimmed from the log. A
+/// peer is tracked from the first time it syncs with us, use `add_peer` to
+/// keep Op's around for a peer that has not synced yet. A peer that is
+/// missing trimmed Op's catches up by merging our state instead, see
+/// `merge`.
+#[derive(Debug)]
+pub struct Peer<T: CmRDT, A: Actor> {
+    replica: Replica<T, A>,
+    log: OpLog<T, A>,
+    // the clock each tracked peer last sent us
+    acks: BTreeMap<A, VClock<A>>,
+}
+
+impl<T, A> Peer<T, A>
+where
+    T: CmRDT + CvRDT + Default + Serialize + DeserializeOwned,
+    T::Op: Clone + Serialize + DeserializeOwned,
+    A: Actor + Serialize + DeserializeOwned,
+{
+    /// Open the peer owned by `actor` whose log is stored in `dir`, its state
+    /// is replayed from the log.
+    pub fn open(actor: A, dir: impl AsRef<Path>) -> Result<Self> {
+        let log = OpLog::open(dir)?;
+        let state = log.replay()?;
+        Ok(Peer {
+            replica: Replica::new(actor, state),
+            log,
+            acks: BTreeMap::new(),
+        })
+    }
+
+    /// The actor that owns this peer
+    pub fn actor(&self) -> &A {
+        self.replica.actor()
+    }
+
+    /// The current state of the peer
+    pub fn state(&self) -> &T {
+        self.replica.state()
+    }
+
+    /// The dots of every Op this peer has applied, trimmed Op's included
+    pub fn clock(&self) -> &VClock<A> {
+        self.log.clock()
+    }
+
+    /// Keep the Op's `actor` has not acknowledged, even if it never synced
+    /// with us yet.
+    pub fn add_peer(&mut self, actor: A) {
+        self.acks.entry(actor).or_default();
+    }
+
+    /// Make an edit: the Op is built by `f` from the current state, then
+    /// logged under the next dot of this peer's actor and applied.
+    pub fn edit<F>(&mut self, f: F) -> Result<T::Op>
+    where
+        F: FnOnce(&T, &A) -> T::Op,
+    {
+        let op = f(self.replica.state(), self.replica.actor());
+        self.log
+            .append(self.log.next_dot(self.replica.actor().clone()), &op)?;
+        self.replica.apply(op.clone());
+        Ok(op)
+    }
+
+    /// Apply an Op made by another peer under its dot. An Op that was
+    /// already applied is skipped.
+    pub fn apply(&mut self, dot: Dot<A>, op: T::Op) -> Result<()> {
+        self.log.clock().validate_dot(&dot)?;
+        if self.log.clock().get(&dot.actor) >= dot.counter {
+            return Ok(());
+        }
+        self.replica.state().validate_op(&op)?;
+        self.log.append(dot, &op)?;
+        self.replica.apply(op);
+        Ok(())
+    }
+
+    /// Merge in the state of a peer that has seen `clock`, catching up on
+    /// Op's that were trimmed from its log. The merged state is persisted
+    /// as our snapshot, nothing is done if we've seen `clock` already.
+    pub fn merge(&mut self, clock: &VClock<A>, state: T) -> Result<()> {
+        if clock <= self.clock() {
+            return Ok(());
+        }
+        self.replica.merge(state);
+        self.log.absorb(self.replica.state(), clock)
+    }
+
+    /// Record that `actor` has seen `clock`, trimming the Op's every tracked
+    /// peer has now seen from the log.
+    pub fn acknowledge(&mut self, actor: A, clock: &VClock<A>) -> Result<()> {
+        if &actor == self.actor() {
+            return Ok(());
+        }
+        self.acks.entry(actor).or_default().merge(clock.clone());
+
+        let mut acks = self.acks.values();
+        let mut acked = match acks.next() {
+            Some(clock) => clock.clone(),
+            None => return Ok(()),
+        };
+        for clock in acks {
+            acked.glb(clock);
+        }
+        self.log.trim(self.replica.state(), &acked)
+    }
+
+    /// The logged Op's made by `actor` after `counter`, in the order they
+    /// were made. Trimmed Op's are no longer available.
+    pub fn ops_after(&self, actor: &A, counter: u64) -> Result<Vec<(Dot<A>, T::Op)>> {
+        self.log.ops_after(actor, counter)
+    }
+
+    /// The Op's a peer that has seen `clock` is missing, in causal order.
+    /// Fails with `OpsTrimmed` if some of them were trimmed from the log.
+    pub fn missing(&self, clock: &VClock<A>) -> Result<VecDeque<(Dot<A>, T::Op)>> {
+        let missing: VecDeque<_> = self
+            .log
+            .ops()?
+            .into_iter()
+            .filter(|(dot, _)| clock.get(&dot.actor) < dot.counter)
+            .collect();
+        let unseen: u64 = self
+            .clock()
+            .iter()
+            .map(|dot| dot.counter.saturating_sub(clock.get(dot.actor)))
+            .sum();
+        if unseen != missing.len() as u64 {
+            return Err(Error::OpsTrimmed);
+        }
+        Ok(missing)
+    }
+}
+
+/// The messages exchanged by two sync sessions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Message<A: Actor, Op> {
+    /// The actor and clock of the sender, always the first message of a
+    /// session. The clock acknowledges the Op's the sender has seen.
+    Clock(A, VClock<A>),
+
+    /// A batch of Op's the receiver is missing, in causal order
+    Ops(Vec<(Dot<A>, Op)>),
+
+    /// The clock and bincode encoded state of the sender, sent instead of
+    /// Op's when some of the Op's the receiver is missing were trimmed
+    State(VClock<A>, Vec<u8>),
+
+    /// The sender has sent every Op the receiver was missing
+    Done,
+}
+
+/// One side of a sync between two peers.
+///
+/// Each side sends its clock, then the Op's the other side is missing in
+/// batches of at most `max_batch` Op's, then `Done`. If some of those Op's
+/// were trimmed from its log, a side sends its whole state instead. The session doesn't
+/// care how messages travel, it only needs them delivered in order: pass
+/// what `poll` returns to the other side's `handle` until both are done.
+///
+/// ``` rust
+/// use crdts::sync::{Peer, Session};
+/// use crdts::Orswot;
+///
+/// let dir = std::env::temp_dir().join("crdts-sync-doc");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// type Basket = Peer<Orswot<String, String>, String>;
+///
+/// let mut a = Basket::open("A".to_string(), dir.join("a")).unwrap();
+/// let mut b = Basket::open("B".to_string(), dir.join("b")).unwrap();
+/// a.edit(|set, actor| set.add("apple".to_string(), set.read().derive_add_ctx(actor.clone())))
+///     .unwrap();
+/// b.edit(|set, actor| set.add("banana".to_string(), set.read().derive_add_ctx(actor.clone())))
+///     .unwrap();
+///
+/// let mut a_session = Session::new(10);
+/// let mut b_session = Session::new(10);
+/// a_session.start(&a);
+/// while !a_session.is_done() || !b_session.is_done() {
+///     while let Some(msg) = a_session.poll() {
+///         b_session.handle(&mut b, msg).unwrap();
+///     }
+///     while let Some(msg) = b_session.poll() {
+///         a_session.handle(&mut a, msg).unwrap();
+///     }
+/// }
+///
+/// assert_eq!(a.state(), b.state());
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Session<A: Actor, Op> {
+    max_batch: usize,
+    phase: Phase,
+    outbox: VecDeque<Message<A, Op>>,
+    // the Op's the remote peer is missing, once we've received its clock
+    pending: Option<VecDeque<(Dot<A>, Op)>>,
+    received_done: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    // we have not sent our clock
+    Idle,
+    // we sent our clock, the Op's the remote is missing may not be known yet
+    Sending,
+    // we sent every Op the remote is missing
+    Sent,
+}
+
+impl<A, Op> Session<A, Op>
+where
+    A: Actor + Serialize + DeserializeOwned,
+    Op: Clone + Serialize + DeserializeOwned,
+{
+    /// Create a session that sends at most `max_batch` Op's per message
+    pub fn new(max_batch: usize) -> Self {
+        assert!(max_batch > 0, "max_batch must be at least 1");
+        Session {
+            max_batch,
+            phase: Phase::Idle,
+            outbox: VecDeque::new(),
+            pending: None,
+            received_done: false,
+        }
+    }
+
+    /// Queue our clock if it was not sent yet. Receiving the remote's clock
+    /// starts the session as well, so only one side needs to call this.
+    pub fn start<T>(&mut self, peer: &Peer<T, A>)
+    where
+        T: CmRDT<Op = Op> + CvRDT + Default + Serialize + DeserializeOwned,
+    {
+        if self.phase == Phase::Idle {
+            self.outbox
+                .push_back(Message::Clock(peer.actor().clone(), peer.clock().clone()));
+            self.phase = Phase::Sending;
+        }
+    }
+
+    /// The next message to send to the remote, None if there is nothing to
+    /// send until we hear from the remote again.
+    pub fn poll(&mut self) -> Option<Message<A, Op>> {
+        if let Some(msg) = self.outbox.pop_front() {
+            return Some(msg);
+        }
+        if self.phase != Phase::Sending {
+            return None;
+        }
+
+        let pending = self.pending.as_mut()?;
+        if pending.is_empty() {
+            self.phase = Phase::Sent;
+            return Some(Message::Done);
+        }
+        let n = pending.len().min(self.max_batch);
+        Some(Message::Ops(pending.drain(..n).collect()))
+    }
+
+    /// Handle a message from the remote
+    pub fn handle<T>(&mut self, peer: &mut Peer<T, A>, msg: Message<A, Op>) -> Result<()>
+    where
+        T: CmRDT<Op = Op> + CvRDT + Default + Serialize + DeserializeOwned,
+    {
+        match msg {
+            Message::Clock(actor, clock) => {
+                if self.pending.is_some() {
+                    return Err(Error::UnexpectedMessage);
+                }
+                self.start(peer);
+                self.pending = match peer.missing(&clock) {
+                    Ok(missing) => Some(missing),
+                    Err(Error::OpsTrimmed) => {
+                        let state = bincode::serialize(peer.state())?;
+                        self.outbox
+                            .push_back(Message::State(peer.clock().clone(), state));
+                        Some(VecDeque::new())
+                    }
+                    Err(err) => return Err(err),
+                };
+                peer.acknowledge(actor, &clock)?;
+            }
+            Message::Ops(ops) => {
+                if self.pending.is_none() || self.received_done {
+                    return Err(Error::UnexpectedMessage);
+                }
+                for (dot, op) in ops {
+                    peer.apply(dot, op)?;
+                }
+            }
+            Message::State(clock, state) => {
+                if self.pending.is_none() || self.received_done {
+                    return Err(Error::UnexpectedMessage);
+                }
+                peer.merge(&clock, bincode::deserialize(&state)?)?;
+            }
+            Message::Done => {
+                if self.pending.is_none() || self.received_done {
+                    return Err(Error::UnexpectedMessage);
+                }
+                self.received_done = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// True once we've sent and received every missing Op
+    pub fn is_done(&self) -> bool {
+        self.phase == Phase::Sent && self.received_done && self.outbox.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::mpsc;
+    use std::thread;
+
+    use crate::{MVReg, Map, Orswot};
+
+    type TPeer = Peer<Orswot<u8, u8>, u8>;
+    type TSession = Session<u8, <Orswot<u8, u8> as CmRDT>::Op>;
+
+    // the logs of a test's peers, removed once the test is done
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("crdts-sync-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            TempDir(dir)
+        }
+
+        fn peer<T>(&self, actor: u8) -> Peer<T, u8>
+        where
+            T: CmRDT + CvRDT + Default + Serialize + DeserializeOwned,
+            T::Op: Clone + Serialize + DeserializeOwned,
+        {
+            Peer::open(actor, self.0.join(actor.to_string())).unwrap()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn add(peer: &mut TPeer, member: u8) {
+        peer.edit(|set, actor| set.add(member, set.read().derive_add_ctx(*actor)))
+            .unwrap();
+    }
+
+    fn rm(peer: &mut TPeer, member: u8) {
+        peer.edit(|set, _| set.rm(member, set.contains(&member).derive_rm_ctx()))
+            .unwrap();
+    }
+
+    /// Run both sessions to completion, returning the number of messages sent
+    fn sync<T>(a: &mut Peer<T, u8>, b: &mut Peer<T, u8>, max_batch: usize) -> usize
+    where
+        T: CmRDT + CvRDT + Default + Serialize + DeserializeOwned,
+        T::Op: Clone + Serialize + DeserializeOwned,
+    {
+        let mut a_session = Session::new(max_batch);
+        let mut b_session = Session::new(max_batch);
+        let mut sent = 0;
+        a_session.start(a);
+        while !a_session.is_done() || !b_session.is_done() {
+            while let Some(msg) = a_session.poll() {
+                b_session.handle(b, msg).unwrap();
+                sent += 1;
+            }
+            while let Some(msg) = b_session.poll() {
+                a_session.handle(a, msg).unwrap();
+                sent += 1;
+            }
+        }
+        sent
+    }
+
+    #[test]
+    fn test_sync_exchanges_missing_ops() {
+        let dir = TempDir::new("exchange");
+        let mut a = dir.peer(1);
+        let mut b = dir.peer(2);
+        add(&mut a, 1);
+        add(&mut a, 2);
+        add(&mut b, 3);
+        sync(&mut a, &mut b, 10);
+        assert_eq!(a.state(), b.state());
+        assert_eq!(a.clock(), b.clock());
+
+        rm(&mut b, 1);
+        add(&mut a, 4);
+        sync(&mut b, &mut a, 10);
+        assert_eq!(a.state(), b.state());
+        assert_eq!(a.state().read().val, vec![2, 3, 4].into_iter().collect());
+    }
+
+    #[test]
+    fn test_ops_are_sent_in_bounded_batches() {
+        let dir = TempDir::new("batches");
+        let mut a = dir.peer(1);
+        let mut b = dir.peer(2);
+        for member in 0..10 {
+            add(&mut a, member);
+        }
+
+        // a's clock, b's clock, b's done, 4 batches of ops from a and a's done
+        assert_eq!(sync(&mut a, &mut b, 3), 8);
+        assert_eq!(a.state(), b.state());
+
+        // nothing is missing, only clocks and dones are exchanged
+        assert_eq!(sync(&mut a, &mut b, 3), 4);
+    }
+
+    #[test]
+    fn test_ops_relayed_through_a_third_peer() {
+        let dir = TempDir::new("relay");
+        let mut a = dir.peer::<Map<u8, MVReg<u8, u8>, u8>>(1);
+        let mut b = dir.peer(2);
+        let mut c = dir.peer(3);
+        a.edit(|map, actor| {
+            map.update(1, map.get(&1).derive_add_ctx(*actor), |reg, ctx| {
+                reg.write(1, ctx)
+            })
+        })
+        .unwrap();
+        sync(&mut a, &mut b, 1);
+        b.edit(|map, actor| {
+            map.update(1, map.get(&1).derive_add_ctx(*actor), |reg, ctx| {
+                reg.write(2, ctx)
+            })
+        })
+        .unwrap();
+        sync(&mut b, &mut c, 1);
+
+        assert_eq!(c.ops_after(&1, 0).unwrap().len(), 1);
+        assert_eq!(c.state().get(&1).val.unwrap().read().val, vec![2]);
+        sync(&mut c, &mut a, 1);
+        assert_eq!(a.state(), c.state());
+    }
+
+    #[test]
+    fn test_ops_every_peer_acknowledged_are_trimmed() {
+        let dir = TempDir::new("trim");
+        let mut a: TPeer = dir.peer(1);
+        let mut b = dir.peer(2);
+        let mut c = dir.peer(3);
+        a.add_peer(3);
+        for member in 0..5 {
+            add(&mut a, member);
+        }
+
+        // b's clock is acknowledged before it receives the Op's
+        sync(&mut a, &mut b, 10);
+        assert_eq!(a.ops_after(&1, 0).unwrap().len(), 5);
+
+        // b has seen every Op, c has not
+        sync(&mut a, &mut b, 10);
+        assert_eq!(a.ops_after(&1, 0).unwrap().len(), 5);
+
+        sync(&mut a, &mut c, 10);
+        sync(&mut a, &mut c, 10);
+        assert!(a.ops_after(&1, 0).unwrap().is_empty());
+        assert_eq!(a.clock().get(&1), 5);
+
+        // Op's made after the trim are still sent
+        add(&mut a, 5);
+        sync(&mut a, &mut b, 10);
+        assert_eq!(a.state(), b.state());
+
+        // a peer missing trimmed Op's can't catch up from the log
+        let d: TPeer = dir.peer(4);
+        assert_eq!(a.missing(d.clock()), Err(Error::OpsTrimmed));
+
+        // the state survives a restart, the trimmed Op's are in the snapshot
+        let state = a.state().clone();
+        drop(a);
+        let a: TPeer = dir.peer(1);
+        assert_eq!(a.state(), &state);
+    }
+
+    #[test]
+    fn test_peer_joining_after_a_trim_catches_up_from_state() {
+        let dir = TempDir::new("join-after-trim");
+        let mut a: TPeer = dir.peer(1);
+        let mut b = dir.peer(2);
+        for member in 0..5 {
+            add(&mut a, member);
+        }
+        rm(&mut a, 0);
+        sync(&mut a, &mut b, 10);
+        sync(&mut a, &mut b, 10);
+        assert!(a.ops_after(&1, 0).unwrap().is_empty());
+
+        // d joins with an edit of its own, a sends its state instead of Op's
+        let mut d = dir.peer(4);
+        add(&mut d, 9);
+        sync(&mut d, &mut a, 2);
+        assert_eq!(a.state(), d.state());
+        assert_eq!(a.clock(), d.clock());
+        assert_eq!(
+            d.state().read().val,
+            vec![1, 2, 3, 4, 9].into_iter().collect()
+        );
+
+        // d has the merged state but not its Op's, it passes the state on
+        let mut e = dir.peer(5);
+        sync(&mut e, &mut d, 2);
+        assert_eq!(e.state(), d.state());
+
+        // later Op's still flow as Op's, and the merge survives a restart
+        rm(&mut b, 1);
+        sync(&mut b, &mut a, 10);
+        sync(&mut a, &mut d, 10);
+        assert_eq!(d.ops_after(&2, 0).unwrap().len(), 1);
+        assert_eq!(a.state(), d.state());
+        let state = d.state().clone();
+        drop(d);
+        let d: TPeer = dir.peer(4);
+        assert_eq!(d.state(), &state);
+        assert_eq!(d.clock(), a.clock());
+    }
+
+    #[test]
+    fn test_ops_before_clock_are_rejected() {
+        let dir = TempDir::new("before-clock");
+        let mut peer: TPeer = dir.peer(1);
+        let mut session: TSession = Session::new(1);
+        assert_eq!(
+            session.handle(&mut peer, Message::Ops(vec![])),
+            Err(Error::UnexpectedMessage)
+        );
+        assert_eq!(
+            session.handle(&mut peer, Message::Done),
+            Err(Error::UnexpectedMessage)
+        );
+    }
+
+    #[test]
+    fn test_sync_over_channels() {
+        let dir = TempDir::new("channels");
+        let mut a = dir.peer(1);
+        let mut b = dir.peer(2);
+        for member in 0..20 {
+            add(&mut a, member);
+            add(&mut b, member + 20);
+        }
+        rm(&mut b, 25);
+
+        let (a_tx, b_rx) = mpsc::channel();
+        let (b_tx, a_rx) = mpsc::channel();
+
+        let run = |mut peer: TPeer, tx: mpsc::Sender<_>, rx: mpsc::Receiver<_>, start| {
+            thread::spawn(move || {
+                let mut session: TSession = Session::new(4);
+                if start {
+                    session.start(&peer);
+                }
+                while !session.is_done() {
+                    while let Some(msg) = session.poll() {
+                        tx.send(msg).unwrap();
+                    }
+                    if session.is_done() {
+                        break;
+                    }
+                    let msg = rx.recv().unwrap();
+                    session.handle(&mut peer, msg).unwrap();
+                }
+                peer
+            })
+        };
+
+        let a = run(a, a_tx, a_rx, true);
+        let b = run(b, b_tx, b_rx, false);
+        let (a, b) = (a.join().unwrap(), b.join().unwrap());
+        assert_eq!(a.state(), b.state());
+        assert!(!a.state().contains(&25).val);
+        assert_eq!(a.state().read().val.len(), 39);
+    }
+}
@@ -1,6 +1,14 @@
 use std::fmt::Debug;
 
-use crate::vclock::{Actor, VClock};
+use sha2::{Digest, Sha256};
+
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::Gen;
+
+#[cfg(any(test, feature = "quickcheck"))]
+use crate::ctx::AddCtx;
+use crate::error::Error;
+use crate::vclock::{Actor, Dot, VClock};
 
 /// State based CRDT's replicate by transmitting the entire CRDT state.
 pub trait CvRDT {
@@ -35,12 +43,133 @@ pub trait CmRDT {
 
     /// Apply an Op to the CRDT
     fn apply(&mut self, op: Self::Op);
+
+    /// Check that an Op is well formed and safe to apply to the current state.
+    ///
+    /// The default implementation accepts every Op.
+    fn validate_op(&self, _op: &Self::Op) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Validate an Op before applying it, a rejected Op leaves the CRDT untouched.
+    fn try_apply(&mut self, op: Self::Op) -> Result<(), Error> {
+        self.validate_op(&op)?;
+        self.apply(op);
+        Ok(())
+    }
 }
 
 /// CRDT's are causal if they are built on top of vector clocks.
 pub trait Causal<A: Actor> {
     /// Forget data that is strictly smaller than this clock
     fn forget(&mut self, clock: &VClock<A>);
+
+    /// Forget the dots strictly smaller than this clock of the Map the CRDT
+    /// is nested in.
+    ///
+    /// The same as `forget` for CRDT's built from the dots of the Op's that
+    /// made them. A counter only counts its own increments, the Map's dots
+    /// tell nothing about them, so a counter has nothing to forget.
+    fn forget_dots(&mut self, clock: &VClock<A>) {
+        self.forget(clock)
+    }
+}
+
+/// CRDT's whose Op's can be validated against the clock of the CRDT they're
+/// nested in.
+///
+/// A CRDT nested in a Map only sees the dots of the Op's applied to it, while
+/// the Op's made for it carry dots of the whole Map. To the nested CRDT every
+/// dot the Map spent on another entry looks like a gap, so the Map validates
+/// nested Op's against its own clock.
+pub trait ValidateUnder<A: Actor>: CmRDT {
+    /// `validate_op`, checking the Op's dots against `clock`, the dots the
+    /// parent has seen, instead of against our own.
+    fn validate_op_under(&self, op: &Self::Op, clock: &VClock<A>) -> Result<(), Error>;
+}
+
+/// CRDT's that can attach a new dot to all of the data they currently hold.
+///
+/// Paired with `Causal::forget`, this lets us re-witness data under a fresh
+/// dot, e.g. when a Map entry is moved to another key.
+pub trait Witness<A: Actor> {
+    /// Witness all data in this CRDT with the given dot
+    fn witness(&mut self, dot: &Dot<A>);
+}
+
+/// CRDT's whose states are ordered by what they know, `merge` moves a state
+/// up to the least upper bound of the two.
+pub trait Lattice: CvRDT + Sized {
+    /// The least upper bound of two states, the same as `merge`
+    fn join(mut self, other: Self) -> Self {
+        self.merge(other);
+        self
+    }
+
+    /// Whether `other` knows everything this state knows, merging this state
+    /// into `other` would leave it unchanged.
+    fn leq(&self, other: &Self) -> bool;
+
+    /// Whether this is the empty state every replica starts from
+    fn is_bottom(&self) -> bool;
+}
+
+/// Lattices with a greatest lower bound
+pub trait Meet: Lattice {
+    /// Keep only what both states know
+    fn meet(&mut self, other: &Self);
+}
+
+/// CRDT's that can report what changed between two of their states.
+///
+/// `apply_with_changes` and `merge_with_changes` work the changes out as
+/// they go, touching only what the Op or the merge touches.
+pub trait TrackChanges: CmRDT + CvRDT {
+    /// A description of the changes between two states of this CRDT
+    type Changes: Debug;
+
+    /// Describe the changes that lead from `before` to the current state
+    fn changes_since(&self, before: &Self) -> Self::Changes;
+
+    /// Apply an Op to the CRDT and report what it changed
+    fn apply_with_changes(&mut self, op: Self::Op) -> Self::Changes;
+
+    /// Merge the given CRDT into the current CRDT and report what it changed
+    fn merge_with_changes(&mut self, other: Self) -> Self::Changes;
+}
+
+/// CRDT's that can check that their state is well formed, e.g. after it was
+/// deserialized from disk or received from a peer.
+pub trait Validate {
+    /// Check the structural invariants of this CRDT
+    fn validate(&self) -> Result<(), Error>;
+}
+
+/// CRDT's with a canonical encoding, equal states always encode to the same
+/// bytes no matter the order their data was inserted in.
+///
+/// The canonical encoding is a bincode encoding with every hash based
+/// collection written out in sorted order, so it decodes with bincode like
+/// any other encoding of the CRDT.
+pub trait Canonical {
+    /// Encode the state canonically
+    fn canonical_bytes(&self) -> Vec<u8>;
+
+    /// A sha256 digest of the canonical encoding, for content addressing
+    fn digest(&self) -> [u8; 32] {
+        Sha256::digest(self.canonical_bytes()).into()
+    }
+}
+
+/// CRDT's that can generate an arbitrary Op against their current state.
+///
+/// Unlike `Arbitrary` for an Op, the generated Op is built from the given
+/// context, the way a real replica would build it, so applying it keeps the
+/// CRDT (and any Map it is nested in) well formed.
+#[cfg(any(test, feature = "quickcheck"))]
+pub trait ArbitraryOp<A: Actor>: CmRDT {
+    /// Generate an Op from the given add context
+    fn arbitrary_op<G: Gen>(&self, ctx: AddCtx<A>, g: &mut G) -> Self::Op;
 }
 
 /// Funky variant of the `CvRDT` trait.
@@ -0,0 +1,467 @@
+//! A vector clock that remembers when each actor last incremented its
+//! counter, so the clock can be pruned.
+//!
+//! A `VClock` gains an actor for every replica that ever wrote through it and
+//! never drops one. Where exact causality can be traded for a bounded size,
+//! e.g. the clocks of application values versioned as in `examples/vclock.rs`,
+//! a `TVClock` can drop its oldest actors the way riak_core prunes its
+//! vclocks. A pruned clock compares as older than it is, so an update that
+//! descends a pruned entry will be seen as concurrent with it, never the
+//! other way round.
+//!
+//! Timestamps are supplied by the caller, in whatever unit the `PrunePolicy`
+//! is written in. They only decide which actors are pruned, never how clocks
+//! compare.
+//!
+//! # Examples
+//!
+//! ```
+//! use crdts::tvclock::{PrunePolicy, TVClock};
+//! use crdts::CmRDT;
+//!
+//! let mut clock = TVClock::new();
+//! for (i, actor) in ["A", "B", "C", "D"].iter().enumerate() {
+//!     clock.apply(clock.inc(*actor, i as u64 * 100));
+//! }
+//!
+//! let policy = PrunePolicy {
+//!     small: 2,
+//!     big: 3,
+//!     young: 50,
+//!     old: 1000,
+//! };
+//! let pruned = clock.prune(400, &policy);
+//! assert_eq!(pruned.get(&"A"), 1);
+//! assert_eq!(clock.len(), 3);
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+use crate::canonical;
+use crate::error::{Error, Result};
+use crate::traits::{Canonical, Causal, CmRDT, CvRDT, Lattice, Validate, ValidateUnder};
+use crate::vclock::{Actor, Dot, VClock};
+
+/// When to prune a `TVClock`, riak_core's `small_vclock`, `big_vclock`,
+/// `young_vclock` and `old_vclock` bucket properties.
+///
+/// Actors are pruned oldest first. Clocks of at most `small` actors and
+/// actors younger than `young` are never pruned. Past those, an actor is
+/// pruned while the clock has more than `big` actors or the actor is older
+/// than `old`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PrunePolicy {
+    /// Never prune a clock of at most this many actors
+    pub small: usize,
+    /// Prune a clock of more than this many actors
+    pub big: usize,
+    /// Never prune an actor younger than this
+    pub young: u64,
+    /// Prune an actor older than this
+    pub old: u64,
+}
+
+impl Default for PrunePolicy {
+    /// riak_core's defaults, with timestamps in seconds
+    fn default() -> Self {
+        Self {
+            small: 50,
+            big: 50,
+            young: 20,
+            old: 86_400,
+        }
+    }
+}
+
+/// An increment of a `TVClock`, a dot and when it was made
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Op<A> {
+    /// The actor's new version
+    pub dot: Dot<A>,
+    /// When the actor made this version
+    pub timestamp: u64,
+}
+
+/// A `VClock` with the time each actor last incremented its counter.
+///
+/// Clocks are compared through `clock`, the timestamps don't take part.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TVClock<A: Actor> {
+    clock: VClock<A>,
+    timestamps: BTreeMap<A, u64>,
+}
+
+impl<A: Actor> Default for TVClock<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Actor + Display> Display for TVClock<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<")?;
+        for (i, (actor, counter)) in self.clock.dots.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            let timestamp = self.timestamp(actor).unwrap_or(0);
+            write!(f, "{}:{}@{}", actor, counter, timestamp)?;
+        }
+        write!(f, ">")
+    }
+}
+
+impl<A: Actor> CmRDT for TVClock<A> {
+    type Op = Op<A>;
+
+    /// Advance the actor's counter, taking the Op's timestamp if the counter
+    /// moved. Of two Op's with the same dot the later timestamp wins.
+    fn apply(&mut self, Op { dot, timestamp }: Self::Op) {
+        self.witness(dot.actor, dot.counter, timestamp);
+    }
+
+    fn validate_op(&self, op: &Self::Op) -> Result<()> {
+        self.clock.validate_op(&op.dot)
+    }
+}
+
+impl<A: Actor> ValidateUnder<A> for TVClock<A> {
+    fn validate_op_under(&self, op: &Self::Op, clock: &VClock<A>) -> Result<()> {
+        clock.validate_dot(&op.dot)
+    }
+}
+
+impl<A: Actor> CvRDT for TVClock<A> {
+    /// Take each actor's larger counter and its timestamp, or the later
+    /// timestamp when the counters are equal.
+    fn merge(&mut self, other: Self) {
+        for dot in other.clock {
+            let timestamp = other.timestamps.get(&dot.actor).cloned().unwrap_or(0);
+            self.witness(dot.actor, dot.counter, timestamp);
+        }
+    }
+}
+
+impl<A: Actor> Lattice for TVClock<A> {
+    /// Each of our actors is behind, or at the same counter and no later, in
+    /// `other`.
+    fn leq(&self, other: &Self) -> bool {
+        self.clock.dots.iter().all(|(actor, counter)| {
+            let ours = (*counter, self.timestamp(actor).unwrap_or(0));
+            let theirs = (other.get(actor), other.timestamp(actor).unwrap_or(0));
+            ours <= theirs
+        })
+    }
+
+    fn is_bottom(&self) -> bool {
+        self.is_empty()
+    }
+}
+
+impl<A: Actor> Causal<A> for TVClock<A> {
+    /// Forget the actors the given clock has seen all of
+    fn forget(&mut self, clock: &VClock<A>) {
+        self.clock.forget(clock);
+        let remaining = &self.clock;
+        self.timestamps
+            .retain(|actor, _| remaining.dots.contains_key(actor));
+    }
+}
+
+impl<A: Actor + Serialize> Canonical for TVClock<A> {
+    fn canonical_bytes(&self) -> Vec<u8> {
+        canonical::encode(self)
+    }
+}
+
+impl<A: Actor> Validate for TVClock<A> {
+    fn validate(&self) -> Result<()> {
+        self.clock.validate()?;
+        if self.clock.dots.keys().eq(self.timestamps.keys()) {
+            Ok(())
+        } else {
+            Err(Error::InvalidState(
+                "TVClock must have a timestamp for each actor of its clock",
+            ))
+        }
+    }
+}
+
+impl<A: Actor> From<TVClock<A>> for VClock<A> {
+    fn from(clock: TVClock<A>) -> Self {
+        clock.clock
+    }
+}
+
+impl<A: Actor> TVClock<A> {
+    /// Returns a new `TVClock` instance.
+    pub fn new() -> Self {
+        Self {
+            clock: VClock::new(),
+            timestamps: BTreeMap::new(),
+        }
+    }
+
+    /// Timestamp every actor of a `VClock` with `now`
+    pub fn from_clock(clock: VClock<A>, now: u64) -> Self {
+        let timestamps = clock
+            .dots
+            .keys()
+            .map(|actor| (actor.clone(), now))
+            .collect();
+        Self { clock, timestamps }
+    }
+
+    /// The clock, without timestamps
+    pub fn clock(&self) -> &VClock<A> {
+        &self.clock
+    }
+
+    /// The counter of an actor, 0 if the clock hasn't seen it
+    pub fn get(&self, actor: &A) -> u64 {
+        self.clock.get(actor)
+    }
+
+    /// When the actor last incremented its counter
+    pub fn timestamp(&self, actor: &A) -> Option<u64> {
+        self.timestamps.get(actor).cloned()
+    }
+
+    /// The number of actors
+    pub fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    /// Returns `true` if this clock has no actors.
+    pub fn is_empty(&self) -> bool {
+        self.timestamps.is_empty()
+    }
+
+    /// Generate the Op that increments the actor's counter at time `now`
+    pub fn inc(&self, actor: A, now: u64) -> Op<A> {
+        Op {
+            dot: self.clock.inc(actor),
+            timestamp: now,
+        }
+    }
+
+    /// Prune the oldest actors as the policy allows, returning the dots that
+    /// were pruned.
+    pub fn prune(&mut self, now: u64, policy: &PrunePolicy) -> VClock<A> {
+        let mut pruned = VClock::new();
+        if self.len() <= policy.small {
+            return pruned;
+        }
+
+        let mut by_age: Vec<(u64, A)> = self
+            .timestamps
+            .iter()
+            .map(|(actor, timestamp)| (*timestamp, actor.clone()))
+            .collect();
+        by_age.sort();
+
+        for (timestamp, actor) in by_age {
+            let age = now.saturating_sub(timestamp);
+            let prunable = self.len() > policy.small && age >= policy.young;
+            if !prunable || !(self.len() > policy.big || age > policy.old) {
+                break;
+            }
+            self.timestamps.remove(&actor);
+            if let Some(counter) = self.clock.dots.remove(&actor) {
+                pruned.apply(Dot::new(actor, counter));
+            }
+        }
+        pruned
+    }
+
+    fn witness(&mut self, actor: A, counter: u64, timestamp: u64) {
+        let current = self.clock.get(&actor);
+        if counter > current {
+            self.timestamps.insert(actor.clone(), timestamp);
+            self.clock.apply(Dot::new(actor, counter));
+        } else if counter == current && counter > 0 {
+            if let Some(t) = self.timestamps.get_mut(&actor) {
+                *t = (*t).max(timestamp);
+            }
+        }
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Actor + Arbitrary> Arbitrary for Op<A> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Op {
+            dot: Dot::arbitrary(g),
+            timestamp: u64::arbitrary(g),
+        }
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let timestamp = self.timestamp;
+        Box::new(self.dot.shrink().map(move |dot| Op { dot, timestamp }))
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Actor + Arbitrary> Arbitrary for TVClock<A> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        let mut clock = TVClock::new();
+        for op in Vec::<Op<A>>::arbitrary(g) {
+            clock.apply(op);
+        }
+        clock
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let ops: Vec<Op<A>> = self
+            .clock
+            .iter()
+            .map(|dot| Op {
+                dot: Dot::new(dot.actor.clone(), dot.counter),
+                timestamp: self.timestamp(dot.actor).unwrap_or(0),
+            })
+            .collect();
+        Box::new(ops.shrink().map(|ops| {
+            let mut clock = TVClock::new();
+            for op in ops {
+                clock.apply(op);
+            }
+            clock
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use quickcheck::quickcheck;
+
+    use crate::laws;
+
+    fn build(entries: &[(&'static str, u64, u64)]) -> TVClock<&'static str> {
+        let mut clock = TVClock::new();
+        for (actor, counter, timestamp) in entries {
+            clock.apply(Op {
+                dot: Dot::new(*actor, *counter),
+                timestamp: *timestamp,
+            });
+        }
+        clock
+    }
+
+    fn actors(clock: &TVClock<&'static str>) -> Vec<&'static str> {
+        clock.clock().dots.keys().cloned().collect()
+    }
+
+    // riak_core's vclock prune tests
+    #[test]
+    fn test_prune_small() {
+        let policy = PrunePolicy {
+            small: 4,
+            ..PrunePolicy::default()
+        };
+        let mut clock = build(&[("1", 1, 1), ("2", 2, 2), ("3", 3, 3)]);
+        assert!(clock.prune(1_000_000, &policy).is_empty());
+        assert_eq!(clock.len(), 3);
+    }
+
+    #[test]
+    fn test_prune_young() {
+        let policy = PrunePolicy {
+            small: 1,
+            young: 1000,
+            ..PrunePolicy::default()
+        };
+        let mut clock = build(&[("1", 1, 1), ("2", 2, 2), ("3", 3, 3)]);
+        assert!(clock.prune(500, &policy).is_empty());
+        assert_eq!(clock.len(), 3);
+    }
+
+    #[test]
+    fn test_prune_big() {
+        let policy = PrunePolicy {
+            small: 1,
+            big: 2,
+            young: 1,
+            old: 100_000,
+        };
+        let mut clock = build(&[("1", 1, 1), ("2", 2, 2), ("3", 3, 3)]);
+        let pruned = clock.prune(10, &policy);
+        assert_eq!(pruned, Dot::new("1", 1).into());
+        assert_eq!(actors(&clock), vec!["2", "3"]);
+    }
+
+    #[test]
+    fn test_prune_old() {
+        let policy = PrunePolicy {
+            small: 1,
+            big: 3,
+            young: 1,
+            old: 3,
+        };
+        let mut clock = build(&[("1", 1, 1), ("2", 2, 2), ("3", 3, 10)]);
+        clock.prune(8, &policy);
+        assert_eq!(actors(&clock), vec!["3"]);
+    }
+
+    #[test]
+    fn test_prune_order() {
+        // oldest first, whatever the actors or counters
+        let policy = PrunePolicy {
+            small: 1,
+            big: 2,
+            young: 1,
+            old: 100_000,
+        };
+        let mut clock = build(&[("1", 5, 3), ("2", 1, 1), ("3", 9, 2)]);
+        clock.prune(10, &policy);
+        assert_eq!(actors(&clock), vec!["1", "3"]);
+    }
+
+    #[test]
+    fn test_merge_keeps_timestamp_of_larger_counter() {
+        let mut a = build(&[("A", 2, 10), ("B", 1, 50)]);
+        let b = build(&[("A", 1, 90), ("B", 1, 60), ("C", 1, 5)]);
+        a.merge(b);
+        assert_eq!(a, build(&[("A", 2, 10), ("B", 1, 60), ("C", 1, 5)]));
+        assert_eq!(a.validate(), Ok(()));
+        assert_eq!(format!("{}", a), "<A:2@10, B:1@60, C:1@5>");
+    }
+
+    quickcheck! {
+        fn prop_merge_laws(a: TVClock<u8>, b: TVClock<u8>, c: TVClock<u8>) -> bool {
+            laws::merge_is_associative(a.clone(), b.clone(), c)
+                && laws::merge_is_commutative(a.clone(), b)
+                && laws::merge_is_idempotent(a)
+        }
+
+        fn prop_ops_commute(clock: TVClock<u8>, op1: Op<u8>, op2: Op<u8>) -> bool {
+            laws::ops_commute(clock, op1, op2)
+        }
+
+        fn prop_pruned_clock_is_not_ahead(clock: TVClock<u8>, now: u64, small: u8, big: u8, young: u8, old: u8) -> bool {
+            let policy = PrunePolicy {
+                small: small as usize,
+                big: big as usize,
+                young: young as u64,
+                old: old as u64,
+            };
+            let mut pruned = clock.clone();
+            let removed = pruned.prune(now, &policy);
+            let mut restored = pruned.clock().clone();
+            restored.merge(removed);
+
+            pruned.validate().is_ok()
+                && pruned.clock() <= clock.clock()
+                && (pruned.len() == clock.len() || pruned.len() >= policy.small)
+                && &restored == clock.clock()
+        }
+    }
+}
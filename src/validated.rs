@@ -0,0 +1,38 @@
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::traits::Validate;
+
+/// Wraps a CRDT so that deserializing it also checks its invariants.
+///
+/// State that fails validation is rejected with a deserialization error.
+///
+/// # Examples
+///
+/// ```
+/// use crdts::{validated::Validated, CmRDT, Orswot};
+///
+/// let mut set: Orswot<u8, String> = Orswot::new();
+/// set.apply(set.add(1, set.read().derive_add_ctx("A".to_string())));
+///
+/// let json = serde_json::to_string(&Validated(set.clone())).unwrap();
+/// let validated: Validated<Orswot<u8, String>> = serde_json::from_str(&json).unwrap();
+/// assert_eq!(validated.into_inner(), set);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct Validated<T>(pub T);
+
+impl<T> Validated<T> {
+    /// Unwrap the validated CRDT
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Validate> Deserialize<'de> for Validated<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let crdt = T::deserialize(deserializer)?;
+        crdt.validate().map_err(de::Error::custom)?;
+        Ok(Validated(crdt))
+    }
+}
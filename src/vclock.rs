@@ -13,15 +13,29 @@
 //! ```
 
 // TODO: we have a mixture of language here with witness and actor. Clean this up
+use std::borrow::Borrow;
 use std::cmp::{self, Ordering};
-use std::collections::{btree_map, BTreeMap};
 use std::fmt::{self, Debug, Display};
 use std::hash::Hash;
+use std::iter::{FromIterator, Peekable};
+use std::marker::PhantomData;
 use std::mem;
+use std::ops::Index;
+use std::slice;
 
+use serde::de::{Deserializer, MapAccess, Visitor};
+use serde::ser::Serializer;
 use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
 
-use crate::traits::{Causal, CmRDT, CvRDT};
+#[cfg(any(test, feature = "quickcheck"))]
+use quickcheck::{Arbitrary, Gen};
+
+use crate::canonical;
+use crate::error::{Error, Result};
+use crate::traits::{
+    Canonical, Causal, CmRDT, CvRDT, Lattice, Meet, Validate, ValidateUnder, Witness,
+};
 
 /// Common Actor type. Actors are unique identifier for every `thing` mutating a VClock.
 /// VClock based CRDT's will need to expose this Actor type to the user.
@@ -56,7 +70,7 @@ impl<A: Actor> Dot<A> {
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct VClock<A: Actor> {
     /// dots is the mapping from actors to their associated counters
-    pub dots: BTreeMap<A, u64>,
+    pub dots: Dots<A>,
 }
 
 impl<A: Actor> Default for VClock<A> {
@@ -67,14 +81,19 @@ impl<A: Actor> Default for VClock<A> {
 
 impl<A: Actor> PartialOrd for VClock<A> {
     fn partial_cmp(&self, other: &VClock<A>) -> Option<Ordering> {
-        if self == other {
-            Some(Ordering::Equal)
-        } else if other.dots.iter().all(|(w, c)| self.get(w) >= *c) {
-            Some(Ordering::Greater)
-        } else if self.dots.iter().all(|(w, c)| other.get(w) >= *c) {
-            Some(Ordering::Less)
-        } else {
-            None
+        let (mut ahead, mut behind) = (false, false);
+        for (_, ours, theirs) in self.dots.zip(&other.dots) {
+            ahead |= ours > theirs;
+            behind |= ours < theirs;
+            if ahead && behind {
+                return None;
+            }
+        }
+        match (ahead, behind) {
+            (false, false) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Greater),
+            (false, true) => Some(Ordering::Less),
+            (true, true) => None,
         }
     }
 }
@@ -96,10 +115,36 @@ impl<A: Actor> Causal<A> for VClock<A> {
     /// Forget any actors that have smaller counts than the
     /// count in the given vclock
     fn forget(&mut self, other: &Self) {
-        for Dot { actor, counter } in other.iter() {
-            if counter >= self.get(&actor) {
-                self.dots.remove(&actor);
+        self.dots.retain_against(&other.dots, |_, counter, theirs| {
+            if counter > theirs {
+                Some(counter)
+            } else {
+                None
             }
+        });
+    }
+}
+
+impl<A: Actor> Witness<A> for VClock<A> {
+    fn witness(&mut self, dot: &Dot<A>) {
+        self.apply_dot(dot.clone());
+    }
+}
+
+impl<A: Actor + Serialize> Canonical for VClock<A> {
+    fn canonical_bytes(&self) -> Vec<u8> {
+        canonical::encode(self)
+    }
+}
+
+impl<A: Actor> Validate for VClock<A> {
+    fn validate(&self) -> Result<()> {
+        if self.dots.values().any(|counter| *counter == 0) {
+            Err(Error::InvalidState(
+                "VClock counters must be greater than zero",
+            ))
+        } else {
+            Ok(())
         }
     }
 }
@@ -125,22 +170,46 @@ impl<A: Actor> CmRDT for VClock<A> {
     fn apply(&mut self, dot: Self::Op) {
         self.apply_dot(dot);
     }
+
+    fn validate_op(&self, dot: &Self::Op) -> Result<()> {
+        self.validate_dot(dot)
+    }
+}
+
+impl<A: Actor> ValidateUnder<A> for VClock<A> {
+    fn validate_op_under(&self, dot: &Self::Op, clock: &VClock<A>) -> Result<()> {
+        clock.validate_dot(dot)
+    }
 }
 
 impl<A: Actor> CvRDT for VClock<A> {
     fn merge(&mut self, other: Self) {
-        for (actor, counter) in other.dots {
-            self.apply_dot(Dot::new(actor, counter));
-        }
+        self.dots.merge(other.dots);
+    }
+}
+
+impl<A: Actor> Lattice for VClock<A> {
+    fn leq(&self, other: &Self) -> bool {
+        self.dots
+            .iter()
+            .all(|(actor, counter)| *counter <= other.get(actor))
+    }
+
+    fn is_bottom(&self) -> bool {
+        self.is_empty()
+    }
+}
+
+impl<A: Actor> Meet for VClock<A> {
+    fn meet(&mut self, other: &Self) {
+        self.glb(other);
     }
 }
 
 impl<A: Actor> VClock<A> {
     /// Returns a new `VClock` instance.
     pub fn new() -> Self {
-        Self {
-            dots: BTreeMap::new(),
-        }
+        Self { dots: Dots::new() }
     }
 
     /// Returns a clone of self but with information that is older than given clock is
@@ -151,10 +220,56 @@ impl<A: Actor> VClock<A> {
         cloned
     }
 
+    /// Returns a clone of self holding only the actors missing from the given clock
+    pub(crate) fn clone_without_actors(&self, other: &Self) -> Self {
+        let mut cloned = self.clone();
+        cloned
+            .dots
+            .retain_against(&other.dots, |_, ours, theirs| match theirs {
+                0 => Some(ours),
+                _ => None,
+            });
+        cloned
+    }
+
+    /// Drop the actors missing from the given clock
+    pub(crate) fn retain_actors(&mut self, other: &Self) {
+        self.dots
+            .retain_against(&other.dots, |_, ours, theirs| match theirs {
+                0 => None,
+                _ => Some(ours),
+            });
+    }
+
     /// Apply a Dot to this vclock.
     fn apply_dot(&mut self, dot: Dot<A>) {
-        if self.get(&dot.actor) < dot.counter {
-            self.dots.insert(dot.actor, dot.counter);
+        match self.dots.search(&dot.actor) {
+            Ok(i) => {
+                let counter = &mut self.dots.entries[i].1;
+                *counter = cmp::max(*counter, dot.counter);
+            }
+            Err(i) if dot.counter > 0 => self.dots.entries.insert(i, (dot.actor, dot.counter)),
+            Err(_) => (),
+        }
+    }
+
+    /// A dot may only be the actor's next counter, or one we've seen already.
+    pub(crate) fn validate_dot(&self, dot: &Dot<A>) -> Result<()> {
+        if dot.counter > self.get(&dot.actor) + 1 {
+            Err(Error::CausalGap)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// A remove clock must be non-empty and may only witness what we've seen.
+    pub(crate) fn validate_rm_clock(&self, clock: &Self) -> Result<()> {
+        if clock.is_empty() {
+            return Err(Error::EmptyClock);
+        }
+        match clock.partial_cmp(self) {
+            None | Some(Ordering::Greater) => Err(Error::RmClockAhead),
+            _ => Ok(()),
         }
     }
 
@@ -216,14 +331,18 @@ impl<A: Actor> VClock<A> {
     /// Returns the common elements (same actor and counter)
     /// for two `VClock` instances.
     pub fn intersection(left: &VClock<A>, right: &Self) -> Self {
-        let mut dots = BTreeMap::new();
-        for (left_actor, left_counter) in left.dots.iter() {
-            let right_counter = right.get(left_actor);
-            if right_counter == *left_counter {
-                dots.insert(left_actor.clone(), *left_counter);
-            }
-        }
-        Self { dots }
+        let mut clock = left.clone();
+        clock.dots.retain_against(
+            &right.dots,
+            |_, ours, theirs| {
+                if ours == theirs {
+                    Some(ours)
+                } else {
+                    None
+                }
+            },
+        );
+        clock
     }
 
     /// Reduces this VClock to the greatest-lower-bound of the given
@@ -244,18 +363,14 @@ impl<A: Actor> VClock<A> {
     /// assert_eq!(c.get(&43), 0);
     /// ```
     pub fn glb(&mut self, other: &Self) {
-        self.dots = mem::replace(&mut self.dots, BTreeMap::new())
-            .into_iter()
-            .filter_map(|(actor, count)| {
-                // Since an actor missing from the dots map has an implied
-                // counter of 0 we can save some memory, and remove the actor.
-                let min_count = cmp::min(count, other.get(&actor));
-                match min_count {
-                    0 => None,
-                    _ => Some((actor, min_count)),
-                }
-            })
-            .collect();
+        self.dots.retain_against(&other.dots, |_, ours, theirs| {
+            // Since an actor missing from the dots map has an implied
+            // counter of 0 we can save some memory, and remove the actor.
+            match cmp::min(ours, theirs) {
+                0 => None,
+                min_count => Some(min_count),
+            }
+        });
     }
 
     /// Returns an iterator over the dots in this vclock
@@ -269,14 +384,14 @@ impl<A: Actor> VClock<A> {
 
 /// Generated from calls to VClock::into_iter()
 pub struct IntoIter<A: Actor> {
-    btree_iter: btree_map::IntoIter<A, u64>,
+    dots_iter: DotsIntoIter<A>,
 }
 
 impl<A: Actor> std::iter::Iterator for IntoIter<A> {
     type Item = Dot<A>;
 
     fn next(&mut self) -> Option<Dot<A>> {
-        self.btree_iter
+        self.dots_iter
             .next()
             .map(|(actor, counter)| Dot::new(actor, counter))
     }
@@ -289,7 +404,7 @@ impl<A: Actor> std::iter::IntoIterator for VClock<A> {
     /// Consumes the vclock and returns an iterator over dots in the clock
     fn into_iter(self) -> Self::IntoIter {
         IntoIter {
-            btree_iter: self.dots.into_iter(),
+            dots_iter: self.dots.into_iter(),
         }
     }
 }
@@ -313,3 +428,395 @@ impl<A: Actor> From<Dot<A>> for VClock<A> {
         clock
     }
 }
+
+/// The actors of a `VClock` and their counters, sorted by actor.
+///
+/// Most clocks, e.g. the member clocks of an `Orswot`, hold one or two
+/// actors, these are stored inline and only larger clocks allocate. Lookups
+/// are binary searches and the operations combining two clocks walk both
+/// sorted lists once. The API follows the parts of `BTreeMap` clocks are used
+/// with, and `Dots` serializes as a map, like the `BTreeMap` it replaced.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Dots<A> {
+    entries: SmallVec<[(A, u64); 2]>,
+}
+
+impl<A> Default for Dots<A> {
+    fn default() -> Self {
+        Dots {
+            entries: SmallVec::new(),
+        }
+    }
+}
+
+impl<A: Debug> Debug for Dots<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(self.entries.iter().map(|(actor, counter)| (actor, counter)))
+            .finish()
+    }
+}
+
+impl<A: Ord> Dots<A> {
+    /// No dots
+    pub fn new() -> Self {
+        Dots::default()
+    }
+
+    /// The number of actors
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether there are no actors
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn search<Q: Ord + ?Sized>(&self, actor: &Q) -> std::result::Result<usize, usize>
+    where
+        A: Borrow<Q>,
+    {
+        self.entries
+            .binary_search_by(|(a, _)| a.borrow().cmp(actor))
+    }
+
+    /// The counter of an actor
+    pub fn get<Q: Ord + ?Sized>(&self, actor: &Q) -> Option<&u64>
+    where
+        A: Borrow<Q>,
+    {
+        self.search(actor).ok().map(|i| &self.entries[i].1)
+    }
+
+    /// A mutable reference to the counter of an actor
+    pub fn get_mut<Q: Ord + ?Sized>(&mut self, actor: &Q) -> Option<&mut u64>
+    where
+        A: Borrow<Q>,
+    {
+        match self.search(actor) {
+            Ok(i) => Some(&mut self.entries[i].1),
+            Err(_) => None,
+        }
+    }
+
+    /// Whether the actor has a counter
+    pub fn contains_key<Q: Ord + ?Sized>(&self, actor: &Q) -> bool
+    where
+        A: Borrow<Q>,
+    {
+        self.search(actor).is_ok()
+    }
+
+    /// Set an actor's counter, returning its previous counter
+    pub fn insert(&mut self, actor: A, counter: u64) -> Option<u64> {
+        // dots are mostly inserted in order, e.g. when deserializing
+        match self.entries.last() {
+            Some((last, _)) if *last >= actor => (),
+            _ => {
+                self.entries.push((actor, counter));
+                return None;
+            }
+        }
+        match self.search(&actor) {
+            Ok(i) => Some(mem::replace(&mut self.entries[i].1, counter)),
+            Err(i) => {
+                self.entries.insert(i, (actor, counter));
+                None
+            }
+        }
+    }
+
+    /// Remove an actor, returning its counter
+    pub fn remove<Q: Ord + ?Sized>(&mut self, actor: &Q) -> Option<u64>
+    where
+        A: Borrow<Q>,
+    {
+        match self.search(actor) {
+            Ok(i) => Some(self.entries.remove(i).1),
+            Err(_) => None,
+        }
+    }
+
+    /// Keep only the actors for which `f` returns true
+    pub fn retain<F: FnMut(&A, &mut u64) -> bool>(&mut self, mut f: F) {
+        self.entries.retain(|(actor, counter)| f(actor, counter));
+    }
+
+    /// Remove all actors
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The actors and their counters, sorted by actor
+    pub fn iter(&self) -> DotsIter<'_, A> {
+        DotsIter {
+            entries: self.entries.iter(),
+        }
+    }
+
+    /// The actors in order
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = &A> + ExactSizeIterator {
+        self.entries.iter().map(|(actor, _)| actor)
+    }
+
+    /// The counters, in the order of their actors
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &u64> + ExactSizeIterator {
+        self.entries.iter().map(|(_, counter)| counter)
+    }
+
+    /// Every actor of either, with our counter and theirs, 0 when missing
+    fn zip<'a>(&'a self, other: &'a Self) -> Zip<'a, A> {
+        Zip {
+            ours: self.entries.iter().peekable(),
+            theirs: other.entries.iter().peekable(),
+        }
+    }
+
+    /// Replace each of our counters with `f(actor, ours, theirs)`, removing
+    /// the actor when it returns `None`.
+    fn retain_against<F>(&mut self, other: &Self, mut f: F)
+    where
+        F: FnMut(&A, u64, u64) -> Option<u64>,
+    {
+        let mut theirs = other.entries.iter().peekable();
+        self.entries.retain(|(actor, counter)| {
+            while matches!(theirs.peek(), Some((a, _)) if a < actor) {
+                theirs.next();
+            }
+            let their_counter = match theirs.peek() {
+                Some((a, c)) if a == actor => *c,
+                _ => 0,
+            };
+            match f(actor, *counter, their_counter) {
+                Some(new_counter) => {
+                    *counter = new_counter;
+                    true
+                }
+                None => false,
+            }
+        });
+    }
+
+    /// Take the max of each actor's counters, moving their actors in
+    fn merge(&mut self, other: Self) {
+        if other.is_empty() {
+            return;
+        }
+        let mut merged = SmallVec::with_capacity(cmp::max(self.len(), other.len()));
+        let mut ours = mem::take(&mut self.entries).into_iter().peekable();
+        let mut theirs = other.entries.into_iter().peekable();
+        loop {
+            let ord = match (ours.peek(), theirs.peek()) {
+                (Some((a, _)), Some((b, _))) => a.cmp(b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+            match ord {
+                Ordering::Less => merged.extend(ours.next()),
+                Ordering::Greater => {
+                    merged.extend(theirs.next().filter(|(_, counter)| *counter > 0))
+                }
+                Ordering::Equal => {
+                    let (actor, our_counter) = ours.next().expect("peeked");
+                    let (_, their_counter) = theirs.next().expect("peeked");
+                    merged.push((actor, cmp::max(our_counter, their_counter)));
+                }
+            }
+        }
+        self.entries = merged;
+    }
+}
+
+impl<A: Ord> Index<&A> for Dots<A> {
+    type Output = u64;
+
+    /// The counter of an actor, panics if the actor has none
+    fn index(&self, actor: &A) -> &u64 {
+        self.get(actor).expect("no counter for actor")
+    }
+}
+
+impl<A: Ord> FromIterator<(A, u64)> for Dots<A> {
+    fn from_iter<I: IntoIterator<Item = (A, u64)>>(iter: I) -> Self {
+        let mut dots = Dots::new();
+        dots.extend(iter);
+        dots
+    }
+}
+
+impl<A: Ord> Extend<(A, u64)> for Dots<A> {
+    fn extend<I: IntoIterator<Item = (A, u64)>>(&mut self, iter: I) {
+        for (actor, counter) in iter {
+            self.insert(actor, counter);
+        }
+    }
+}
+
+impl<A> IntoIterator for Dots<A> {
+    type Item = (A, u64);
+    type IntoIter = DotsIntoIter<A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        DotsIntoIter {
+            entries: self.entries.into_iter(),
+        }
+    }
+}
+
+impl<'a, A> IntoIterator for &'a Dots<A> {
+    type Item = (&'a A, &'a u64);
+    type IntoIter = DotsIter<'a, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        DotsIter {
+            entries: self.entries.iter(),
+        }
+    }
+}
+
+impl<A: Serialize> Serialize for Dots<A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_map(self.entries.iter().map(|(actor, counter)| (actor, counter)))
+    }
+}
+
+impl<'de, A: Ord + Deserialize<'de>> Deserialize<'de> for Dots<A> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct DotsVisitor<A>(PhantomData<A>);
+
+        impl<'de, A: Ord + Deserialize<'de>> Visitor<'de> for DotsVisitor<A> {
+            type Value = Dots<A>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a map of actors to counters")
+            }
+
+            fn visit_map<M: MapAccess<'de>>(
+                self,
+                mut map: M,
+            ) -> std::result::Result<Self::Value, M::Error> {
+                let mut dots = Dots::new();
+                while let Some((actor, counter)) = map.next_entry()? {
+                    dots.insert(actor, counter);
+                }
+                Ok(dots)
+            }
+        }
+
+        deserializer.deserialize_map(DotsVisitor(PhantomData))
+    }
+}
+
+/// An iterator over the actors of a `Dots` and their counters
+pub struct DotsIter<'a, A> {
+    entries: slice::Iter<'a, (A, u64)>,
+}
+
+impl<'a, A> Iterator for DotsIter<'a, A> {
+    type Item = (&'a A, &'a u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|(actor, counter)| (actor, counter))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl<'a, A> DoubleEndedIterator for DotsIter<'a, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.entries
+            .next_back()
+            .map(|(actor, counter)| (actor, counter))
+    }
+}
+
+impl<'a, A> ExactSizeIterator for DotsIter<'a, A> {}
+
+/// An owning iterator over the actors of a `Dots` and their counters
+pub struct DotsIntoIter<A> {
+    entries: smallvec::IntoIter<[(A, u64); 2]>,
+}
+
+impl<A> Iterator for DotsIntoIter<A> {
+    type Item = (A, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl<A> DoubleEndedIterator for DotsIntoIter<A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.entries.next_back()
+    }
+}
+
+impl<A> ExactSizeIterator for DotsIntoIter<A> {}
+
+struct Zip<'a, A> {
+    ours: Peekable<slice::Iter<'a, (A, u64)>>,
+    theirs: Peekable<slice::Iter<'a, (A, u64)>>,
+}
+
+impl<'a, A: Ord> Iterator for Zip<'a, A> {
+    type Item = (&'a A, u64, u64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ord = match (self.ours.peek(), self.theirs.peek()) {
+            (Some((a, _)), Some((b, _))) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => return None,
+        };
+        match ord {
+            Ordering::Less => self.ours.next().map(|(a, c)| (a, *c, 0)),
+            Ordering::Greater => self.theirs.next().map(|(a, c)| (a, 0, *c)),
+            Ordering::Equal => {
+                let (actor, ours) = self.ours.next().expect("peeked");
+                let (_, theirs) = self.theirs.next().expect("peeked");
+                Some((actor, *ours, *theirs))
+            }
+        }
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Actor + Arbitrary> Arbitrary for Dot<A> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        // keep counters small so that dots of the same actor meet often
+        Dot::new(A::arbitrary(g), u64::from(u8::arbitrary(g) % 50) + 1)
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let actor = self.actor.clone();
+        Box::new(
+            self.counter
+                .shrink()
+                .filter(|counter| *counter > 0)
+                .map(move |counter| Dot::new(actor.clone(), counter)),
+        )
+    }
+}
+
+#[cfg(any(test, feature = "quickcheck"))]
+impl<A: Actor + Arbitrary> Arbitrary for VClock<A> {
+    fn arbitrary<G: Gen>(g: &mut G) -> Self {
+        Vec::<Dot<A>>::arbitrary(g).into_iter().collect()
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+        let dots: Vec<Dot<A>> = self
+            .iter()
+            .map(|dot| Dot::new(dot.actor.clone(), dot.counter))
+            .collect();
+        Box::new(dots.shrink().map(|dots| dots.into_iter().collect()))
+    }
+}
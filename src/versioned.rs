@@ -0,0 +1,706 @@
+//! Versioned envelopes for persisted CRDT states and Op's.
+//!
+//! Every type names the layout it serializes with in a schema, e.g.
+//! `map/2<orswot/1>` for a `Map` of `Orswot`'s. `to_bytes` wraps the bincode
+//! encoding in an envelope carrying its schema, and `Migrations::load`
+//! upgrades the payload step by step with the registered migrations until
+//! it matches the schema of the type being loaded.
+//!
+//! Migrations compose through nesting: a `Map` migrates its own layout and
+//! hands each of its values to the migrations of the nested schema, so a
+//! migration registered for `orswot/1` also upgrades the `Orswot`'s of a
+//! `map/1<orswot/1>`.
+//!
+//! Data written by crdts 3.0 has no envelope, it's loaded as a payload in the
+//! type's `legacy_schema`. Telling the two apart is best-effort, see `MAGIC`.
+//!
+//! ``` rust
+//! use crdts::versioned::{self, Migrations};
+//! use crdts::{CmRDT, Orswot};
+//!
+//! let mut set: Orswot<String, u8> = Orswot::new();
+//! set.apply(set.add("apple".to_string(), set.read().derive_add_ctx(1)));
+//!
+//! let migrations = Migrations::new();
+//! let bytes = versioned::to_bytes(&set);
+//! assert_eq!(migrations.load::<Orswot<String, u8>>(&bytes).unwrap(), set);
+//!
+//! // state persisted by crdts 3.0
+//! let legacy = bincode::serialize(&set).unwrap();
+//! assert_eq!(migrations.load::<Orswot<String, u8>>(&legacy).unwrap(), set);
+//! ```
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::lwwreg::{self, LWWReg};
+use crate::map::{self, Key, Map};
+use crate::mvreg::{self, MVReg};
+use crate::orswot::{self, Member, Orswot};
+use crate::pncounter::{self, PNCounter};
+use crate::vclock::{Actor, Dot, VClock};
+use crate::{GCounter, GSet};
+
+/// Marks the start of an envelope.
+///
+/// Unversioned 3.0 data may start with these bytes too, e.g. an `LWWReg`
+/// holding a `u32`, so detecting envelopes is best-effort: bytes starting
+/// with `MAGIC` that don't load as an envelope are loaded as 3.0 data, and
+/// 3.0 data that also happens to load as a complete envelope is misread.
+pub const MAGIC: [u8; 4] = *b"CRDT";
+
+/// Types whose serialized layout is named by a schema
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// The schema of the layout this version of the type serializes with,
+    /// bump it whenever the layout changes.
+    fn schema() -> String;
+
+    /// The schema of the layout crdts 3.0 serialized this type with
+    fn legacy_schema() -> String;
+
+    /// Read one value in the `schema` layout off the front of `payload`,
+    /// returning it re-encoded in `Self::schema()`.
+    ///
+    /// The default steps through the registered migrations, types that nest
+    /// other versioned values migrate those through their own schemas.
+    fn migrate(migrations: &Migrations, schema: &str, payload: &mut &[u8]) -> Result<Vec<u8>> {
+        migrations.migrate_steps::<Self>(schema, payload)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    schema: String,
+    payload: Vec<u8>,
+}
+
+/// Serialize a value in an envelope naming its schema
+pub fn to_bytes<T: Versioned>(val: &T) -> Vec<u8> {
+    let envelope = Envelope {
+        schema: T::schema(),
+        payload: bincode::serialize(val).expect("CRDT state always serializes"),
+    };
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(bincode::serialize(&envelope).expect("envelopes always serialize"));
+    bytes
+}
+
+// reads one value off the front of the payload and returns it re-encoded
+type Migration = Box<dyn Fn(&mut &[u8]) -> Result<Vec<u8>>>;
+
+fn read<T: DeserializeOwned>(payload: &mut &[u8]) -> Result<T> {
+    Ok(bincode::deserialize_from(payload)?)
+}
+
+// Split a schema like `map-op/2<orswot/1, orswot-op/1>` into its name and
+// the schemas nested in it.
+fn parse_schema(schema: &str) -> Option<(&str, Vec<&str>)> {
+    let open = match schema.find('<') {
+        Some(open) => open,
+        None => return Some((schema, Vec::new())),
+    };
+    if !schema.ends_with('>') {
+        return None;
+    }
+
+    let mut nested = Vec::new();
+    let mut depth = 0;
+    let mut start = open + 1;
+    let inner_end = schema.len() - 1;
+    for (i, c) in schema[..inner_end]
+        .char_indices()
+        .skip_while(|(i, _)| *i <= open)
+    {
+        match c {
+            '<' => depth += 1,
+            '>' if depth == 0 => return None,
+            '>' => depth -= 1,
+            ',' if depth == 0 => {
+                nested.push(schema[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if depth != 0 {
+        return None;
+    }
+    nested.push(schema[start..inner_end].trim());
+    Some((&schema[..open], nested))
+}
+
+/// A registry of migrations between schemas
+#[derive(Default)]
+pub struct Migrations {
+    // the schema each migration upgrades to, by the schema it upgrades from
+    steps: HashMap<String, (String, Migration)>,
+}
+
+impl fmt::Debug for Migrations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let steps: HashMap<&String, &String> = self
+            .steps
+            .iter()
+            .map(|(from, (to, _))| (from, to))
+            .collect();
+        f.debug_struct("Migrations").field("steps", &steps).finish()
+    }
+}
+
+impl Migrations {
+    /// A registry without any migrations
+    pub fn new() -> Self {
+        Migrations::default()
+    }
+
+    /// Register a migration rewriting a value in the `from` schema, decoded
+    /// as an `Old`, to a `New` in the `to` schema, replacing any migration
+    /// already registered from `from`. Decoding an `Old` tells us where the
+    /// value ends, so the migration also applies to values nested in a `Map`.
+    pub fn register<Old, New, F>(
+        &mut self,
+        from: impl Into<String>,
+        to: impl Into<String>,
+        migration: F,
+    ) where
+        Old: DeserializeOwned,
+        New: Serialize,
+        F: Fn(Old) -> Result<New> + 'static,
+    {
+        let migration = move |payload: &mut &[u8]| {
+            let new = migration(read(payload)?)?;
+            Ok(bincode::serialize(&new)?)
+        };
+        self.steps
+            .insert(from.into(), (to.into(), Box::new(migration)));
+    }
+
+    /// Load a value written by `to_bytes` or by crdts 3.0, migrating its
+    /// payload to the current schema of `T`.
+    pub fn load<T: Versioned>(&self, bytes: &[u8]) -> Result<T> {
+        if bytes.starts_with(&MAGIC) {
+            self.load_envelope(&bytes[MAGIC.len()..]).or_else(|err| {
+                self.load_payload(&T::legacy_schema(), bytes)
+                    .map_err(|_| err)
+            })
+        } else {
+            self.load_payload(&T::legacy_schema(), bytes)
+        }
+    }
+
+    /// Read one value in the `schema` layout off the front of `payload` and
+    /// step it through the registered migrations to `T::schema()`, see
+    /// `Versioned::migrate`.
+    pub fn migrate_steps<T: Versioned>(
+        &self,
+        schema: &str,
+        payload: &mut &[u8],
+    ) -> Result<Vec<u8>> {
+        let target = T::schema();
+        let mut schema = schema.to_string();
+        let mut seen = vec![schema.clone()];
+        let mut migrated: Option<Vec<u8>> = None;
+        while schema != target {
+            let (to, migration) = self.steps.get(&schema).ok_or_else(|| Error::NoMigration {
+                from: schema.clone(),
+                to: target.clone(),
+            })?;
+            migrated = Some(match migrated {
+                None => migration(payload)?,
+                Some(bytes) => migration(&mut bytes.as_slice())?,
+            });
+            schema = to.clone();
+            if seen.contains(&schema) {
+                return Err(Error::MigrationCycle(schema));
+            }
+            seen.push(schema.clone());
+        }
+
+        match migrated {
+            Some(bytes) => Ok(bytes),
+            // already in the current layout, decode it to find where it ends
+            None => Ok(bincode::serialize(&read::<T>(payload)?)?),
+        }
+    }
+
+    fn load_envelope<T: Versioned>(&self, mut bytes: &[u8]) -> Result<T> {
+        let envelope: Envelope = read(&mut bytes)?;
+        if !bytes.is_empty() {
+            return Err(Error::TrailingBytes);
+        }
+        self.load_payload(&envelope.schema, &envelope.payload)
+    }
+
+    fn load_payload<T: Versioned>(&self, schema: &str, mut payload: &[u8]) -> Result<T> {
+        let migrated = T::migrate(self, schema, &mut payload)?;
+        if !payload.is_empty() {
+            return Err(Error::TrailingBytes);
+        }
+        bincode::deserialize(&migrated).map_err(Error::from)
+    }
+}
+
+// The layouts of the CRDT's in this crate. Map entries gained the dots
+// removed from them and map Op's gained `Mv` since 3.0, the rest are
+// unchanged.
+
+impl<A: Actor + Serialize + DeserializeOwned> Versioned for VClock<A> {
+    fn schema() -> String {
+        "vclock/1".to_string()
+    }
+
+    fn legacy_schema() -> String {
+        "vclock/1".to_string()
+    }
+}
+
+impl<A: Actor + Serialize + DeserializeOwned> Versioned for Dot<A> {
+    fn schema() -> String {
+        "dot/1".to_string()
+    }
+
+    fn legacy_schema() -> String {
+        "dot/1".to_string()
+    }
+}
+
+impl<A: Actor + Serialize + DeserializeOwned> Versioned for GCounter<A> {
+    fn schema() -> String {
+        "gcounter/1".to_string()
+    }
+
+    fn legacy_schema() -> String {
+        "gcounter/1".to_string()
+    }
+}
+
+impl<A: Actor + Serialize + DeserializeOwned> Versioned for PNCounter<A> {
+    fn schema() -> String {
+        "pncounter/1".to_string()
+    }
+
+    fn legacy_schema() -> String {
+        "pncounter/1".to_string()
+    }
+}
+
+impl<A: Actor + Serialize + DeserializeOwned> Versioned for pncounter::Op<A> {
+    fn schema() -> String {
+        "pncounter-op/1".to_string()
+    }
+
+    fn legacy_schema() -> String {
+        "pncounter-op/1".to_string()
+    }
+}
+
+impl<T: Ord + Serialize + DeserializeOwned> Versioned for GSet<T> {
+    fn schema() -> String {
+        "gset/1".to_string()
+    }
+
+    fn legacy_schema() -> String {
+        "gset/1".to_string()
+    }
+}
+
+impl<V, M> Versioned for LWWReg<V, M>
+where
+    V: lwwreg::Val + Serialize + DeserializeOwned,
+    M: lwwreg::Marker + Serialize + DeserializeOwned,
+{
+    fn schema() -> String {
+        "lwwreg/1".to_string()
+    }
+
+    fn legacy_schema() -> String {
+        "lwwreg/1".to_string()
+    }
+}
+
+impl<V, A> Versioned for MVReg<V, A>
+where
+    V: mvreg::Val + Serialize + DeserializeOwned,
+    A: Actor + Serialize + DeserializeOwned,
+{
+    fn schema() -> String {
+        "mvreg/1".to_string()
+    }
+
+    fn legacy_schema() -> String {
+        "mvreg/1".to_string()
+    }
+}
+
+impl<V, A> Versioned for mvreg::Op<V, A>
+where
+    V: mvreg::Val + Serialize + DeserializeOwned,
+    A: Actor + Serialize + DeserializeOwned,
+{
+    fn schema() -> String {
+        "mvreg-op/1".to_string()
+    }
+
+    fn legacy_schema() -> String {
+        "mvreg-op/1".to_string()
+    }
+}
+
+impl<M, A> Versioned for Orswot<M, A>
+where
+    M: Member + Serialize + DeserializeOwned,
+    A: Actor + Serialize + DeserializeOwned,
+{
+    fn schema() -> String {
+        "orswot/1".to_string()
+    }
+
+    fn legacy_schema() -> String {
+        "orswot/1".to_string()
+    }
+}
+
+impl<M, A> Versioned for orswot::Op<M, A>
+where
+    M: Member + Serialize + DeserializeOwned,
+    A: Actor + Serialize + DeserializeOwned,
+{
+    fn schema() -> String {
+        "orswot-op/1".to_string()
+    }
+
+    fn legacy_schema() -> String {
+        "orswot-op/1".to_string()
+    }
+}
+
+impl<K, V, A> Versioned for Map<K, V, A>
+where
+    K: Key + Serialize + DeserializeOwned,
+    V: map::Val<A> + Versioned,
+    A: Actor + Serialize + DeserializeOwned,
+{
+    fn schema() -> String {
+        format!("map/2<{}>", V::schema())
+    }
+
+    fn legacy_schema() -> String {
+        format!("map/1<{}>", V::legacy_schema())
+    }
+
+    fn migrate(migrations: &Migrations, schema: &str, payload: &mut &[u8]) -> Result<Vec<u8>> {
+        let (has_removed, val_schema) = match parse_schema(schema) {
+            Some(("map/1", ref nested)) if nested.len() == 1 => (false, nested[0]),
+            Some(("map/2", ref nested)) if nested.len() == 1 => (true, nested[0]),
+            _ => return migrations.migrate_steps::<Self>(schema, payload),
+        };
+
+        // bincode lays structs out field by field and maps as a length
+        // followed by their entries, so we can rewrite the entries in place.
+        let clock: VClock<A> = read(payload)?;
+        let len: u64 = read(payload)?;
+        let mut bytes = bincode::serialize(&(clock, len))?;
+        for _ in 0..len {
+            let key: K = read(payload)?;
+            let entry_clock: VClock<A> = read(payload)?;
+            // map/1 entries don't remember the dots removed from them
+            let removed: VClock<A> = if has_removed {
+                read(payload)?
+            } else {
+                VClock::new()
+            };
+            bytes.extend(bincode::serialize(&(key, entry_clock, removed))?);
+            bytes.extend(V::migrate(migrations, val_schema, payload)?);
+        }
+        let deferred: HashMap<VClock<A>, BTreeSet<K>> = read(payload)?;
+        bytes.extend(bincode::serialize(&deferred)?);
+        Ok(bytes)
+    }
+}
+
+impl<K, V, A> Versioned for map::Op<K, V, A>
+where
+    K: Key + Serialize + DeserializeOwned,
+    V: map::Val<A> + Versioned,
+    V::Op: Versioned,
+    A: Actor + Serialize + DeserializeOwned,
+{
+    fn schema() -> String {
+        format!("map-op/2<{}, {}>", V::schema(), V::Op::schema())
+    }
+
+    fn legacy_schema() -> String {
+        format!(
+            "map-op/1<{}, {}>",
+            V::legacy_schema(),
+            V::Op::legacy_schema()
+        )
+    }
+
+    fn migrate(migrations: &Migrations, schema: &str, payload: &mut &[u8]) -> Result<Vec<u8>> {
+        let (has_mv, val_schema, op_schema) = match parse_schema(schema) {
+            Some(("map-op/1", ref nested)) if nested.len() == 2 => (false, nested[0], nested[1]),
+            Some(("map-op/2", ref nested)) if nested.len() == 2 => (true, nested[0], nested[1]),
+            _ => return migrations.migrate_steps::<Self>(schema, payload),
+        };
+
+        // bincode prefixes enum variants with their index
+        let variant: u32 = read(payload)?;
+        let mut bytes = bincode::serialize(&variant)?;
+        match variant {
+            0 => {
+                let rm: (VClock<A>, BTreeSet<K>) = read(payload)?;
+                bytes.extend(bincode::serialize(&rm)?);
+            }
+            1 => {
+                let up: (Dot<A>, K) = read(payload)?;
+                bytes.extend(bincode::serialize(&up)?);
+                bytes.extend(V::Op::migrate(migrations, op_schema, payload)?);
+            }
+            2 if has_mv => {
+                let mv: (Dot<A>, K, VClock<A>, K, VClock<A>) = read(payload)?;
+                bytes.extend(bincode::serialize(&mv)?);
+                bytes.extend(V::migrate(migrations, val_schema, payload)?);
+            }
+            _ => {
+                return Err(Error::Encoding(Box::new(bincode::ErrorKind::Custom(
+                    format!("{} has no variant {}", schema, variant),
+                ))))
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::{CmRDT, Map};
+
+    type TSet = Orswot<u8, u8>;
+    type TMap = Map<String, TSet, u8>;
+
+    /// A later layout of a set: an `Orswot` with a label
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Labeled {
+        set: TSet,
+        label: String,
+    }
+
+    impl Versioned for Labeled {
+        fn schema() -> String {
+            "labeled-orswot/2".to_string()
+        }
+
+        fn legacy_schema() -> String {
+            "orswot/1".to_string()
+        }
+    }
+
+    fn label_orswots(set: TSet) -> Result<Labeled> {
+        let label = "unlabeled".to_string();
+        Ok(Labeled { set, label })
+    }
+
+    fn sample_set() -> TSet {
+        let mut set = TSet::new();
+        set.apply(set.add(3, set.read().derive_add_ctx(1)));
+        set
+    }
+
+    // `sample_map` in the 3.0 layout, its entries have no removed dots, with
+    // the given value under "fruit"
+    fn legacy_map_bytes<V: Serialize>(val: V) -> Vec<u8> {
+        let clock: VClock<u8> = Dot::new(1, 1).into();
+        let mut entries = BTreeMap::new();
+        entries.insert("fruit".to_string(), (clock.clone(), val));
+        let deferred: HashMap<VClock<u8>, BTreeSet<String>> = HashMap::new();
+        bincode::serialize(&(clock, entries, deferred)).unwrap()
+    }
+
+    fn envelope_bytes(schema: &str, payload: Vec<u8>) -> Vec<u8> {
+        let envelope = Envelope {
+            schema: schema.to_string(),
+            payload,
+        };
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(bincode::serialize(&envelope).unwrap());
+        bytes
+    }
+
+    fn sample_map() -> TMap {
+        let mut map = TMap::new();
+        let op = map.update("fruit", map.len().derive_add_ctx(1), |set, ctx| {
+            set.add(3, ctx)
+        });
+        map.apply(op);
+        map
+    }
+
+    #[test]
+    fn test_envelope_names_the_nested_schemas() {
+        assert_eq!(TMap::schema(), "map/2<orswot/1>");
+        assert_eq!(
+            map::Op::<String, TSet, u8>::schema(),
+            "map-op/2<orswot/1, orswot-op/1>"
+        );
+
+        let map = sample_map();
+        let bytes = to_bytes(&map);
+        assert!(bytes.starts_with(&MAGIC));
+        assert_eq!(Migrations::new().load::<TMap>(&bytes).unwrap(), map);
+    }
+
+    #[test]
+    fn test_loads_unversioned_3_0_state() {
+        let legacy = legacy_map_bytes(sample_set());
+        assert_eq!(
+            Migrations::new().load::<TMap>(&legacy).unwrap(),
+            sample_map()
+        );
+
+        // a map nested in a map, both in the 3.0 layout
+        let mut nested: Map<String, TMap, u8> = Map::new();
+        let op = nested.update("basket", nested.len().derive_add_ctx(1), |map, ctx| {
+            map.update("fruit", ctx, |set, ctx| set.add(3, ctx))
+        });
+        nested.apply(op);
+        let clock: VClock<u8> = Dot::new(1, 1).into();
+        let mut legacy = bincode::serialize(&(&clock, 1u64, "basket", &clock)).unwrap();
+        legacy.extend(legacy_map_bytes(sample_set()));
+        legacy.extend(bincode::serialize(&0u64).unwrap());
+        assert_eq!(
+            Migrations::new()
+                .load::<Map<String, TMap, u8>>(&legacy)
+                .unwrap(),
+            nested
+        );
+
+        let map = sample_map();
+        let op = map.update("fruit", map.len().derive_add_ctx(2), |set, ctx| {
+            set.add(4, ctx)
+        });
+        let legacy = bincode::serialize(&op).unwrap();
+        assert_eq!(
+            Migrations::new()
+                .load::<map::Op<String, TSet, u8>>(&legacy)
+                .unwrap(),
+            op
+        );
+    }
+
+    #[test]
+    fn test_nested_migrations_compose() {
+        // an older layout of a set that only listed its members, added by 1
+        let mut migrations = Migrations::new();
+        migrations.register("orswot/0", "orswot/1", |members: Vec<u8>| {
+            let mut set = TSet::new();
+            for member in members {
+                set.apply(set.add(member, set.read().derive_add_ctx(1)));
+            }
+            Ok(set)
+        });
+
+        let bytes = envelope_bytes("map/1<orswot/0>", legacy_map_bytes(vec![3u8]));
+        assert_eq!(migrations.load::<TMap>(&bytes).unwrap(), sample_map());
+
+        let op: map::Op<String, TSet, u8> = map::Op::Up {
+            dot: Dot::new(1, 1),
+            key: "fruit".to_string(),
+            op: orswot::Op::Add {
+                dot: Dot::new(1, 1),
+                members: vec![3].into_iter().collect(),
+            },
+        };
+        // and of its adds, which were always made by 1
+        migrations.register("orswot-op/0", "orswot-op/1", |members: Vec<u8>| {
+            Ok(orswot::Op::<u8, u8>::Add {
+                dot: Dot::new(1, 1),
+                members: members.into_iter().collect(),
+            })
+        });
+        let mut payload = bincode::serialize(&(1u32, Dot::new(1u8, 1), "fruit")).unwrap();
+        payload.extend(bincode::serialize(&vec![3u8]).unwrap());
+        let bytes = envelope_bytes("map-op/1<orswot/0, orswot-op/0>", payload);
+        assert_eq!(
+            migrations
+                .load::<map::Op<String, TSet, u8>>(&bytes)
+                .unwrap(),
+            op
+        );
+    }
+
+    #[test]
+    fn test_loads_3_0_data_that_starts_like_an_envelope() {
+        let reg = LWWReg {
+            val: u32::from_le_bytes(MAGIC),
+            marker: 7u64,
+        };
+        let legacy = bincode::serialize(&reg).unwrap();
+        assert!(legacy.starts_with(&MAGIC));
+        assert_eq!(
+            Migrations::new().load::<LWWReg<u32, u64>>(&legacy).unwrap(),
+            reg
+        );
+    }
+
+    #[test]
+    fn test_migrates_older_layouts() {
+        let mut set = TSet::new();
+        set.apply(set.add(7, set.read().derive_add_ctx(2)));
+
+        let mut migrations = Migrations::new();
+        migrations.register("orswot/1", "labeled-orswot/2", label_orswots);
+
+        let expected = Labeled {
+            set: set.clone(),
+            label: "unlabeled".to_string(),
+        };
+        let enveloped = to_bytes(&set);
+        let legacy = bincode::serialize(&set).unwrap();
+        assert_eq!(migrations.load::<Labeled>(&enveloped).unwrap(), expected);
+        assert_eq!(migrations.load::<Labeled>(&legacy).unwrap(), expected);
+
+        // data already in the current layout is loaded as is
+        let current = to_bytes(&expected);
+        assert_eq!(migrations.load::<Labeled>(&current).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_missing_or_cyclic_migrations() {
+        let bytes = to_bytes(&TSet::new());
+
+        match Migrations::new().load::<Labeled>(&bytes) {
+            Err(Error::NoMigration { from, to }) => {
+                assert_eq!(from, "orswot/1");
+                assert_eq!(to, "labeled-orswot/2");
+            }
+            other => panic!("expected a missing migration, got {:?}", other),
+        }
+
+        let mut migrations = Migrations::new();
+        migrations.register("orswot/1", "orswot/0", |set: TSet| Ok(set));
+        migrations.register("orswot/0", "orswot/1", |set: TSet| Ok(set));
+        match migrations.load::<Labeled>(&bytes) {
+            Err(Error::MigrationCycle(schema)) => assert_eq!(schema, "orswot/1"),
+            other => panic!("expected a migration cycle, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rejects_a_corrupt_envelope() {
+        let mut bytes = to_bytes(&sample_map());
+        bytes.truncate(bytes.len() - 1);
+        assert!(matches!(
+            Migrations::new().load::<TMap>(&bytes),
+            Err(Error::Encoding(_))
+        ));
+    }
+}
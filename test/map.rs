@@ -1,4 +1,7 @@
-use crdts::{map, mvreg, Causal, CmRDT, CvRDT, Dot, MVReg, Map, VClock};
+use crdts::{
+    map, mvreg, Causal, CmRDT, CvRDT, Dot, Error, GCounter, MVReg, Map, PNCounter, TrackChanges,
+    Validate, VClock,
+};
 use quickcheck::TestResult;
 
 use super::vclock;
@@ -8,6 +11,7 @@ type TKey = u8;
 type TVal = MVReg<u8, TActor>;
 type TOp = map::Op<TKey, Map<TKey, TVal, TActor>, TActor>;
 type TMap = Map<TKey, Map<TKey, TVal, TActor>, TActor>;
+type TOpsPrim = (u8, Vec<(u8, u8, u8, u8, u8)>);
 
 fn build_ops(prims: (u8, Vec<(u8, u8, u8, u8, u8)>)) -> (TActor, Vec<TOp>) {
     let (actor, ops_data) = prims;
@@ -144,6 +148,486 @@ fn test_remove() {
     assert_eq!(m.len().val, 0);
 }
 
+#[test]
+fn test_clear() {
+    let mut m: TMap = Map::new();
+    m.apply(m.update(101, m.get(&101).derive_add_ctx(1), |map, ctx| {
+        map.update(110, ctx, |reg, ctx| reg.write(0, ctx))
+    }));
+    m.apply(m.update(102, m.get(&102).derive_add_ctx(1), |map, ctx| {
+        map.update(110, ctx, |reg, ctx| reg.write(1, ctx))
+    }));
+
+    let mut m2 = m.clone();
+    let clear_op = m.clear(m.read_ctx().derive_rm_ctx());
+
+    // an update concurrent to the clear should survive it
+    m2.apply(m2.update(102, m2.get(&102).derive_add_ctx(2), |map, ctx| {
+        map.update(220, ctx, |reg, ctx| reg.write(2, ctx))
+    }));
+
+    m.apply(clear_op.clone());
+    assert!(m.is_empty().val);
+
+    m.merge(m2.clone());
+    m2.apply(clear_op);
+    assert_eq!(m, m2);
+
+    assert_eq!(m.len().val, 1);
+    let inner_map = m.get(&102).val.unwrap();
+    assert_eq!(inner_map.get(&110).val, None);
+    assert_eq!(inner_map.get(&220).val.map(|r| r.read().val), Some(vec![2]));
+}
+
+#[test]
+fn test_changes() {
+    let mut m: TMap = Map::new();
+    m.apply(m.update(101, m.get(&101).derive_add_ctx(1), |map, ctx| {
+        map.update(110, ctx, |reg, ctx| reg.write(0, ctx))
+    }));
+    m.apply(m.update(102, m.get(&102).derive_add_ctx(1), |map, ctx| {
+        map.update(120, ctx, |reg, ctx| reg.write(1, ctx))
+    }));
+
+    let mut m2 = m.clone();
+    m2.apply(m2.update(101, m2.get(&101).derive_add_ctx(2), |map, ctx| {
+        map.update(110, ctx, |reg, ctx| reg.write(2, ctx))
+    }));
+    m2.apply(m2.rm(102, m2.get(&102).derive_rm_ctx()));
+    m2.apply(m2.update(103, m2.get(&103).derive_add_ctx(2), |map, ctx| {
+        map.update(130, ctx, |reg, ctx| reg.write(3, ctx))
+    }));
+
+    let changes = m.merge_with_changes(m2);
+    assert_eq!(changes.added, vec![103].into_iter().collect());
+    assert_eq!(changes.removed, vec![102].into_iter().collect());
+    assert_eq!(changes.updated.len(), 1);
+
+    let nested = &changes.updated[&101];
+    assert!(nested.added.is_empty());
+    assert!(nested.removed.is_empty());
+    assert_eq!(nested.updated.len(), 1);
+    assert_eq!(nested.updated[&110], Some(vec![2]));
+
+    // an op on one key only reports that key
+    let op = m.update(103, m.get(&103).derive_add_ctx(1), |map, ctx| {
+        map.update(131, ctx, |reg, ctx| reg.write(4, ctx))
+    });
+    let changes = m.apply_with_changes(op);
+    assert!(changes.added.is_empty());
+    assert!(changes.removed.is_empty());
+    assert_eq!(changes.updated.len(), 1);
+    assert_eq!(changes.updated[&103].added, vec![131].into_iter().collect());
+}
+
+#[test]
+fn test_undo() {
+    let mut m: TMap = Map::new();
+    m.apply(m.update(101, m.get(&101).derive_add_ctx(1), |map, ctx| {
+        map.update(110, ctx, |reg, ctx| reg.write(0, ctx))
+    }));
+    let mut m2 = m.clone();
+
+    // undo an edit
+    let op = m.update(101, m.get(&101).derive_add_ctx(1), |map, ctx| {
+        map.update(110, ctx, |reg, ctx| reg.write(1, ctx))
+    });
+    let undo = m.apply_with_undo(op);
+
+    // concurrently, actor 2 edits the same entry
+    m2.apply(m2.update(101, m2.get(&101).derive_add_ctx(2), |map, ctx| {
+        map.update(111, ctx, |reg, ctx| reg.write(2, ctx))
+    }));
+    m.merge(m2);
+
+    for undo_op in m.undo(&undo, m.read_ctx().derive_add_ctx(1)) {
+        m.apply(undo_op);
+    }
+    let inner = m.get(&101).val.unwrap();
+    assert_eq!(inner.get(&110).val.map(|r| r.read().val), Some(vec![0]));
+    assert_eq!(inner.get(&111).val.map(|r| r.read().val), Some(vec![2]));
+
+    // undo a remove
+    let undo = m.apply_with_undo(m.rm(101, m.get(&101).derive_rm_ctx()));
+    assert_eq!(m.get(&101).val, None);
+
+    for undo_op in m.undo(&undo, m.read_ctx().derive_add_ctx(1)) {
+        m.apply(undo_op);
+    }
+    let inner = m.get(&101).val.unwrap();
+    assert_eq!(inner.get(&110).val.map(|r| r.read().val), Some(vec![0]));
+    assert_eq!(inner.get(&111).val.map(|r| r.read().val), Some(vec![2]));
+
+    // undo an edit that created the entry
+    let op = m.update(102, m.get(&102).derive_add_ctx(1), |map, ctx| {
+        map.update(120, ctx, |reg, ctx| reg.write(3, ctx))
+    });
+    let undo = m.apply_with_undo(op);
+    for undo_op in m.undo(&undo, m.read_ctx().derive_add_ctx(1)) {
+        m.apply(undo_op);
+    }
+    assert_eq!(m.get(&102).val, None);
+    assert_eq!(m.len().val, 1);
+
+    // undo a move
+    let undo = m.apply_with_undo(m.mv(101, 102, m.read_ctx().derive_add_ctx(1)).unwrap());
+    for undo_op in m.undo(&undo, m.read_ctx().derive_add_ctx(1)) {
+        m.apply(undo_op);
+    }
+    assert_eq!(m.get(&102).val, None);
+    let inner = m.get(&101).val.unwrap();
+    assert_eq!(inner.get(&110).val.map(|r| r.read().val), Some(vec![0]));
+    assert_eq!(inner.get(&111).val.map(|r| r.read().val), Some(vec![2]));
+
+    // an Op we've already seen changes nothing, there is nothing to undo
+    let op = m.update(101, m.get(&101).derive_add_ctx(1), |map, ctx| {
+        map.update(110, ctx, |reg, ctx| reg.write(4, ctx))
+    });
+    m.apply(op.clone());
+    let undo = m.apply_with_undo(op);
+    assert_eq!(m.undo(&undo, m.read_ctx().derive_add_ctx(1)), vec![]);
+}
+
+#[test]
+fn test_undo_restores_a_counter() {
+    let mut m: Map<TKey, GCounter<TActor>, TActor> = Map::new();
+    // the Map's dots run ahead of the counts
+    for (key, actor) in vec![(102, 1), (102, 1), (102, 1), (101, 1), (101, 1), (101, 2)] {
+        m.apply(m.update(key, m.get(&key).derive_add_ctx(actor), |c, ctx| {
+            c.inc(ctx.dot.actor)
+        }));
+    }
+    let snapshot = m.clone();
+
+    let undo = m.apply_with_undo(m.rm(101, m.get(&101).derive_rm_ctx()));
+    assert_eq!(m.get(&101).val, None);
+
+    let undo_ops = m.undo(&undo, m.read_ctx().derive_add_ctx(1));
+    let mut merged = snapshot;
+    for undo_op in undo_ops {
+        m.apply(undo_op);
+    }
+    merged.merge(m.clone());
+    assert_eq!(m, merged);
+    assert_eq!(m.get(&101).val.map(|c| c.read()), Some(3u8.into()));
+}
+
+#[test]
+fn test_try_apply() {
+    let mut m: TMap = Map::new();
+    let op = m.update(101, m.get(&101).derive_add_ctx(1), |map, ctx| {
+        map.update(110, ctx, |reg, ctx| reg.write(0, ctx))
+    });
+    assert_eq!(m.try_apply(op.clone()), Ok(()));
+    assert_eq!(m.try_apply(op), Ok(()));
+    let snapshot = m.clone();
+
+    // the dot was already used to edit key 101
+    let conflicting_op = m.update(102, m.get(&102).derive_add_ctx(1), |map, ctx| {
+        map.update(120, ctx, |reg, ctx| reg.write(1, ctx))
+    });
+    let conflicting_op = match conflicting_op {
+        map::Op::Up { key, op, .. } => map::Op::Up {
+            dot: Dot::new(1, 1),
+            key,
+            op,
+        },
+        _ => unreachable!(),
+    };
+    assert_eq!(m.try_apply(conflicting_op), Err(Error::ConflictingMarker));
+
+    let gap_op = map::Op::Up {
+        dot: Dot::new(1, 3),
+        key: 102,
+        op: map::Op::Rm {
+            clock: Dot::new(1, 1).into(),
+            keyset: vec![120].into_iter().collect(),
+        },
+    };
+    assert_eq!(m.try_apply(gap_op), Err(Error::CausalGap));
+
+    // nested ops are validated too
+    let nested_empty_op = map::Op::Up {
+        dot: Dot::new(1, 2),
+        key: 101,
+        op: map::Op::Up {
+            dot: Dot::new(1, 2),
+            key: 110,
+            op: mvreg::Op::Put {
+                clock: VClock::new(),
+                val: 2,
+            },
+        },
+    };
+    assert_eq!(m.try_apply(nested_empty_op), Err(Error::EmptyClock));
+
+    let ahead_rm_op = map::Op::Rm {
+        clock: Dot::new(2, 1).into(),
+        keyset: vec![101].into_iter().collect(),
+    };
+    assert_eq!(m.try_apply(ahead_rm_op), Err(Error::RmClockAhead));
+
+    // a nested Put that witnesses a write we haven't seen
+    let nested_gap_op = map::Op::Up {
+        dot: Dot::new(1, 2),
+        key: 101,
+        op: map::Op::Up {
+            dot: Dot::new(1, 2),
+            key: 110,
+            op: mvreg::Op::Put {
+                clock: vec![Dot::new(1, 2), Dot::new(2, 1)].into_iter().collect(),
+                val: 2,
+            },
+        },
+    };
+    assert_eq!(m.try_apply(nested_gap_op), Err(Error::CausalGap));
+
+    // a nested remove under a clock ahead of the Map's
+    let nested_ahead_rm_op = map::Op::Up {
+        dot: Dot::new(1, 2),
+        key: 101,
+        op: map::Op::Rm {
+            clock: Dot::new(2, 1).into(),
+            keyset: vec![110].into_iter().collect(),
+        },
+    };
+    assert_eq!(m.try_apply(nested_ahead_rm_op), Err(Error::RmClockAhead));
+
+    assert_eq!(m, snapshot);
+}
+
+#[test]
+fn test_validate() {
+    let mut m: TMap = Map::new();
+    m.apply(m.update(101, m.get(&101).derive_add_ctx(1), |map, ctx| {
+        map.update(110, ctx, |reg, ctx| reg.write(0, ctx))
+    }));
+    assert_eq!(m.validate(), Ok(()));
+
+    // the nested register holds two values where one overwrote the other
+    let json = r#"{
+        "clock": {"dots": {"1": 2}},
+        "entries": {"101": {
+            "clock": {"dots": {"1": 2}},
+            "removed": {"dots": {}},
+            "val": {
+                "clock": {"dots": {"1": 2}},
+                "entries": {"110": {
+                    "clock": {"dots": {"1": 2}},
+                    "removed": {"dots": {}},
+                    "val": {"vals": [[{"dots": {"1": 1}}, 0], [{"dots": {"1": 2}}, 1]]}
+                }},
+                "deferred": {}
+            }
+        }},
+        "deferred": {}
+    }"#;
+    let m: TMap = serde_json::from_str(json).unwrap();
+    assert_eq!(
+        m.validate(),
+        Err(Error::InvalidState(
+            "MVReg values must have pairwise concurrent clocks"
+        ))
+    );
+}
+
+#[test]
+fn test_loads_entries_without_removed_dots() {
+    let mut m: TMap = Map::new();
+    m.apply(m.update(101, m.get(&101).derive_add_ctx(1), |map, ctx| {
+        map.update(110, ctx, |reg, ctx| reg.write(0, ctx))
+    }));
+
+    // serialized by 3.0, before entries remembered their removed dots
+    let json = r#"{
+        "clock": {"dots": {"1": 1}},
+        "entries": {"101": {
+            "clock": {"dots": {"1": 1}},
+            "val": {
+                "clock": {"dots": {"1": 1}},
+                "entries": {"110": {
+                    "clock": {"dots": {"1": 1}},
+                    "val": {"vals": [[{"dots": {"1": 1}}, 0]]}
+                }},
+                "deferred": {}
+            }
+        }},
+        "deferred": {}
+    }"#;
+    let loaded: TMap = serde_json::from_str(json).unwrap();
+    assert_eq!(loaded, m);
+}
+
+#[test]
+fn test_diff_leaves_out_seen_values() {
+    let mut m: TMap = Map::new();
+    for key in 0..50 {
+        m.apply(m.update(key, m.get(&key).derive_add_ctx(1), |map, ctx| {
+            map.update(key, ctx, |reg, ctx| reg.write(key, ctx))
+        }));
+    }
+    let mut older = m.clone();
+    m.apply(m.update(7, m.get(&7).derive_add_ctx(2), |map, ctx| {
+        map.update(70, ctx, |reg, ctx| reg.write(70, ctx))
+    }));
+    m.apply(m.rm(8, m.get(&8).derive_rm_ctx()));
+
+    let diff = m.diff(&older);
+    let diff_len = bincode::serialize(&diff).unwrap().len();
+    let full_len = bincode::serialize(&m).unwrap().len();
+    assert!(diff_len < full_len);
+
+    older.merge(diff);
+    assert_eq!(older, m);
+}
+
+#[test]
+fn test_diff_leaves_out_keys_older_has() {
+    let mut m: TMap = Map::new();
+    for key in 0..50 {
+        m.apply(m.update(key, m.get(&key).derive_add_ctx(key % 5), |map, ctx| {
+            map.update(key, ctx, |reg, ctx| reg.write(key, ctx))
+        }));
+    }
+    let mut older = m.clone();
+    m.apply(m.update(100, m.get(&100).derive_add_ctx(9), |map, ctx| {
+        map.update(100, ctx, |reg, ctx| reg.write(100, ctx))
+    }));
+    m.apply(m.rm(3, m.get(&3).derive_rm_ctx()));
+
+    // the new key, and the keys edited by the actor whose edit was removed
+    let diff = m.diff(&older);
+    assert_eq!(diff.len().val, 10);
+    assert!(bincode::serialize(&diff).unwrap().len() < bincode::serialize(&m).unwrap().len());
+
+    older.merge(diff);
+    assert_eq!(older, m);
+}
+
+#[test]
+fn test_mv() {
+    let mut m: TMap = Map::new();
+    m.apply(m.update(101, m.get(&101).derive_add_ctx(1), |map, ctx| {
+        map.update(110, ctx, |reg, ctx| reg.write(0, ctx))
+    }));
+
+    let snapshot = m.clone();
+    let mv_op = m.mv(101, 102, m.read_ctx().derive_add_ctx(1)).unwrap();
+    m.apply(mv_op.clone());
+
+    assert_eq!(m.get(&101).val, None);
+    assert_eq!(
+        m.get(&102)
+            .val
+            .and_then(|m2| m2.get(&110).val)
+            .map(|r| r.read().val),
+        Some(vec![0])
+    );
+
+    // state and op based replication of a move agree
+    let mut m2 = snapshot.clone();
+    m2.apply(mv_op);
+    let mut m3 = snapshot;
+    m3.merge(m.clone());
+    assert_eq!(m, m2);
+    assert_eq!(m, m3);
+
+    // there is nothing to move from an absent key
+    assert_eq!(m.mv(101, 103, m.read_ctx().derive_add_ctx(1)), None);
+}
+
+#[test]
+fn test_mv_with_concurrent_updates() {
+    let mut m1: TMap = Map::new();
+    m1.apply(m1.update(101, m1.get(&101).derive_add_ctx(1), |map, ctx| {
+        map.update(110, ctx, |reg, ctx| reg.write(0, ctx))
+    }));
+    m1.apply(m1.update(102, m1.get(&102).derive_add_ctx(1), |map, ctx| {
+        map.update(120, ctx, |reg, ctx| reg.write(1, ctx))
+    }));
+
+    let mut m2 = m1.clone();
+    let mut m3 = m1.clone();
+
+    let mv_op = m1.mv(101, 102, m1.read_ctx().derive_add_ctx(1)).unwrap();
+    m1.apply(mv_op.clone());
+
+    // concurrently edit the key we move from ...
+    let from_op = m2.update(101, m2.get(&101).derive_add_ctx(2), |map, ctx| {
+        map.update(111, ctx, |reg, ctx| reg.write(2, ctx))
+    });
+    m2.apply(from_op.clone());
+
+    // ... and the key we move to
+    let to_op = m3.update(102, m3.get(&102).derive_add_ctx(3), |map, ctx| {
+        map.update(121, ctx, |reg, ctx| reg.write(3, ctx))
+    });
+    m3.apply(to_op.clone());
+
+    let mut merged = m1.clone();
+    merged.merge(m2.clone());
+    merged.merge(m3.clone());
+
+    m1.apply(from_op.clone());
+    m1.apply(to_op.clone());
+    m2.apply(to_op);
+    m2.apply(mv_op.clone());
+    m3.apply(mv_op);
+    m3.apply(from_op);
+
+    for m in [m1, m2, m3, merged] {
+        // the concurrent edit to `from` was not seen by the move, so it stays behind
+        let from = m.get(&101).val.unwrap();
+        assert_eq!(from.len().val, 1);
+        assert_eq!(from.get(&111).val.map(|r| r.read().val), Some(vec![2]));
+
+        // the moved value replaces what the move saw under `to`, while the
+        // concurrent edit to `to` is merged with the moved value
+        let to = m.get(&102).val.unwrap();
+        assert_eq!(to.len().val, 2);
+        assert_eq!(to.get(&110).val.map(|r| r.read().val), Some(vec![0]));
+        assert_eq!(to.get(&120).val, None);
+        assert_eq!(to.get(&121).val.map(|r| r.read().val), Some(vec![3]));
+    }
+}
+
+#[test]
+fn test_mv_keeps_a_counter() {
+    type TCounters = Map<TKey, PNCounter<TActor>, TActor>;
+    let count = |m: &TCounters, key: TKey, actor: TActor, up: bool| {
+        m.update(key, m.get(&key).derive_add_ctx(actor), |c, ctx| {
+            if up {
+                c.inc(ctx.dot.actor)
+            } else {
+                c.dec(ctx.dot.actor)
+            }
+        })
+    };
+
+    let mut m1 = TCounters::new();
+    m1.apply(count(&m1, 9, 1, true));
+    m1.apply(count(&m1, 1, 1, true));
+    m1.apply(count(&m1, 1, 1, true));
+    m1.apply(count(&m1, 1, 2, true));
+    m1.apply(count(&m1, 1, 2, false));
+    m1.apply(count(&m1, 1, 2, true));
+    let mut m2 = m1.clone();
+    let mut merged = m1.clone();
+
+    let mv_op = m1.mv(1, 2, m1.read_ctx().derive_add_ctx(1)).unwrap();
+    m1.apply(mv_op.clone());
+    m2.apply(mv_op);
+    merged.merge(m1.clone());
+    assert_eq!(m1, m2);
+    assert_eq!(m1, merged);
+    assert_eq!(m1.get(&1).val, None);
+    assert_eq!(m1.get(&2).val.map(|c| c.read()), Some(3.into()));
+
+    // the moved counter keeps counting from its total
+    m1.apply(count(&m1, 2, 2, true));
+    assert_eq!(m1.get(&2).val.map(|c| c.read()), Some(4.into()));
+}
+
 #[test]
 fn test_reset_remove_semantics() {
     let mut m1 = TMap::new();
@@ -172,6 +656,46 @@ fn test_reset_remove_semantics() {
     assert_eq!(inner_map.len().val, 1);
 }
 
+#[test]
+fn test_counter_values_keep_their_counts() {
+    let mut m1: Map<TKey, GCounter<TActor>, TActor> = Map::new();
+    let inc = |m: &Map<TKey, GCounter<TActor>, TActor>, key: TKey, actor: TActor| {
+        m.update(key, m.get(&key).derive_add_ctx(actor), |c, ctx| {
+            c.inc(ctx.dot.actor)
+        })
+    };
+
+    // the Map's dots run ahead of the counts of the fresh counter
+    m1.apply(inc(&m1, 9, 1));
+    m1.apply(inc(&m1, 1, 1));
+    m1.apply(inc(&m1, 1, 1));
+    assert_eq!(m1.get(&1).val.map(|c| c.read()), Some(2u8.into()));
+
+    // a remove resets the counts it has seen, a concurrent increment survives
+    let mut m2 = m1.clone();
+    let rm_op = m1.rm(1, m1.get(&1).derive_rm_ctx());
+    m1.apply(rm_op.clone());
+    let inc_op = inc(&m2, 1, 2);
+    m2.apply(inc_op.clone());
+
+    let mut merged = m1.clone();
+    merged.merge(m2.clone());
+    m1.apply(inc_op);
+    m2.apply(rm_op);
+    assert_eq!(m1, m2);
+    assert_eq!(m1, merged);
+    assert_eq!(m1.get(&1).val.map(|c| c.read()), Some(1u8.into()));
+
+    // counting again after the remove
+    let op = inc(&m1, 1, 1);
+    m1.apply(op.clone());
+    m2.apply(op);
+    merged.merge(m1.clone());
+    assert_eq!(m1, m2);
+    assert_eq!(m1, merged);
+    assert_eq!(m1.get(&1).val.map(|c| c.read()), Some(2u8.into()));
+}
+
 #[test]
 fn test_updating_with_current_clock_should_be_a_nop() {
     let mut m1: TMap = Map::new();
@@ -522,6 +1046,25 @@ fn apply_ops(map: &mut TMap, ops: &[TOp]) {
 }
 
 quickcheck! {
+    fn prop_tracked_changes_match_changes_since(
+        ops1_prim: TOpsPrim,
+        ops2_prim: TOpsPrim
+    ) -> bool {
+        let mut m1: TMap = Map::new();
+        let mut m2: TMap = Map::new();
+        apply_ops(&mut m1, &build_ops(ops1_prim).1);
+        let ops2 = build_ops(ops2_prim).1;
+        apply_ops(&mut m2, &ops2[..ops2.len() / 2]);
+
+        let mut merged = m1.clone();
+        let mut tracked = merged.merge_with_changes(m2.clone()) == merged.changes_since(&m1);
+        for op in ops2 {
+            let before = merged.clone();
+            tracked &= merged.apply_with_changes(op) == merged.changes_since(&before);
+        }
+        tracked
+    }
+
     // TODO: add test to show equivalence of merge and Op exchange
     fn prop_op_exchange_same_as_merge(
         ops1_prim: (u8, Vec<(u8, u8, u8, u8, u8)>),
@@ -747,6 +1290,50 @@ quickcheck! {
         TestResult::from_bool(m1 == m2)
     }
 
+    fn prop_merge_diff_same_as_merge(
+        shared_prim: TOpsPrim,
+        ops1_prim: TOpsPrim,
+        ops2_prim: TOpsPrim
+    ) -> TestResult {
+        let shared = build_ops(shared_prim);
+        let ops1 = build_ops(ops1_prim);
+        let ops2 = build_ops(ops2_prim);
+
+        if shared.0 == ops1.0 || shared.0 == ops2.0 || ops1.0 == ops2.0 {
+            return TestResult::discard();
+        }
+
+        let mut m1: TMap = Map::new();
+        apply_ops(&mut m1, &shared.1);
+        let mut older = m1.clone();
+
+        apply_ops(&mut m1, &ops1.1);
+        apply_ops(&mut older, &ops2.1);
+
+        let diff = m1.diff(&older);
+
+        let mut merged_with_diff = older.clone();
+        merged_with_diff.merge(diff);
+
+        older.merge(m1);
+
+        TestResult::from_bool(merged_with_diff == older)
+    }
+
+    fn prop_ops_and_merges_keep_state_valid(
+        ops1_prim: TOpsPrim,
+        ops2_prim: TOpsPrim
+    ) -> bool {
+        let mut m1: TMap = Map::new();
+        let mut m2: TMap = Map::new();
+        apply_ops(&mut m1, &build_ops(ops1_prim).1);
+        apply_ops(&mut m2, &build_ops(ops2_prim).1);
+        let m1_valid = m1.validate().is_ok();
+
+        m1.merge(m2);
+        m1_valid && m1.validate().is_ok()
+    }
+
     fn prop_merge_idempotent(
         ops_prim: (u8, Vec<(u8, u8, u8, u8, u8)>)
     ) -> bool {
@@ -33,6 +33,121 @@ fn test_write_should_not_mutate_reg() {
     assert_eq!(reg.read().add_clock, VClock::from(Dot::new("A", 1)));
 }
 
+#[test]
+fn test_changes() {
+    let mut r1 = MVReg::new();
+    r1.apply(r1.write(1, r1.read().derive_add_ctx("A")));
+
+    let mut r2 = r1.clone();
+    r1.apply(r1.write(2, r1.read().derive_add_ctx("A")));
+    r2.apply(r2.write(3, r2.read().derive_add_ctx("B")));
+
+    // the concurrent write produces a new sibling set
+    assert_eq!(r1.merge_with_changes(r2.clone()), Some(vec![2, 3]));
+
+    // merging what we've already seen changes nothing
+    assert_eq!(r1.merge_with_changes(r2), None);
+
+    let op = r1.write(4, r1.read().derive_add_ctx("A"));
+    assert_eq!(r1.apply_with_changes(op), Some(vec![4]));
+}
+
+#[test]
+fn test_undo() {
+    let mut r1 = MVReg::new();
+    r1.apply(r1.write(1, r1.read().derive_add_ctx("A")));
+    let mut r2 = r1.clone();
+
+    let before = r1.read();
+    let op = r1.write(2, r1.read().derive_add_ctx("A"));
+    r1.apply(op.clone());
+
+    // B writes concurrently to our write
+    r2.apply(r2.write(3, r2.read().derive_add_ctx("B")));
+    r1.merge(r2);
+    assert_eq!(r1.read().val, vec![2, 3]);
+
+    // undoing our write keeps B's concurrent value
+    let undo_op = r1.undo(&op, before, r1.read().derive_add_ctx("A"));
+    r1.apply(undo_op.unwrap());
+    assert_eq!(r1.read().val, vec![3, 1]);
+
+    // the write has been overwritten, there is nothing left to undo
+    let before = MVReg::new().read();
+    assert_eq!(r1.undo(&op, before, r1.read().derive_add_ctx("A")), None);
+}
+
+#[test]
+fn test_try_apply() {
+    let mut reg = MVReg::new();
+    let op = reg.write(1, reg.read().derive_add_ctx("A"));
+    assert_eq!(reg.try_apply(op.clone()), Ok(()));
+
+    let empty_op = Op::Put {
+        clock: VClock::new(),
+        val: 2,
+    };
+    assert_eq!(reg.try_apply(empty_op), Err(Error::EmptyClock));
+
+    let gap_op = Op::Put {
+        clock: Dot::new("A", 3).into(),
+        val: 2,
+    };
+    assert_eq!(reg.try_apply(gap_op), Err(Error::CausalGap));
+
+    let unseen_deps_op = Op::Put {
+        clock: vec![Dot::new("A", 2), Dot::new("B", 1)]
+            .into_iter()
+            .collect(),
+        val: 2,
+    };
+    assert_eq!(reg.try_apply(unseen_deps_op), Err(Error::CausalGap));
+
+    // replaying the op with a different value doesn't replace our value
+    let Op::Put { clock, .. } = op.clone();
+    assert_eq!(reg.try_apply(Op::Put { clock, val: 2 }), Ok(()));
+    assert_eq!(reg.read().val, vec![1]);
+
+    // B writes concurrently to A's write
+    let other: MVReg<u8, &str> = MVReg::new();
+    let concurrent_op = other.write(3, other.read().derive_add_ctx("B"));
+    assert_eq!(reg.try_apply(concurrent_op), Ok(()));
+    assert_eq!(reg.read().val, vec![1, 3]);
+
+    // no write could have witnessed both A's and B's dots as its new dot
+    let conflicting_op = Op::Put {
+        clock: vec![Dot::new("A", 1), Dot::new("B", 1)]
+            .into_iter()
+            .collect(),
+        val: 4,
+    };
+    assert_eq!(reg.try_apply(conflicting_op), Err(Error::ConflictingMarker));
+
+    // once overwritten, replaying the first op is fine
+    let op2 = reg.write(5, reg.read().derive_add_ctx("B"));
+    assert_eq!(reg.try_apply(op2), Ok(()));
+    assert_eq!(reg.try_apply(op), Ok(()));
+    assert_eq!(reg.read().val, vec![5]);
+}
+
+#[test]
+fn test_validate() {
+    let json = r#"{"vals": [[{"dots": {"1": 1}}, 1], [{"dots": {"2": 1}}, 2]]}"#;
+    let reg: MVReg<u8, u8> = serde_json::from_str(json).unwrap();
+    assert_eq!(reg.validate(), Ok(()));
+
+    // the second value should have overwritten the first
+    let json = r#"{"vals": [[{"dots": {"1": 1}}, 1], [{"dots": {"1": 2}}, 2]]}"#;
+    let reg: MVReg<u8, u8> = serde_json::from_str(json).unwrap();
+    assert_eq!(
+        reg.validate(),
+        Err(Error::InvalidState(
+            "MVReg values must have pairwise concurrent clocks"
+        ))
+    );
+    assert!(serde_json::from_str::<validated::Validated<MVReg<u8, u8>>>(json).is_err());
+}
+
 #[test]
 fn test_concurrent_update_with_same_value_dont_collapse_on_merge() {
     // this is important to prevent because it breaks commutativity
@@ -82,6 +197,29 @@ fn test_multi_val() {
     assert!(r1.read().val == vec![32, 82] || r1.read().val == vec![82, 32]);
 }
 
+#[test]
+fn test_canonical_bytes_ignore_write_order() {
+    let mut a = MVReg::new();
+    let mut b = MVReg::new();
+    let op_a = a.write(1, a.read().derive_add_ctx(1));
+    let op_b = b.write(2, b.read().derive_add_ctx(2));
+
+    a.apply(op_a.clone());
+    a.apply(op_b.clone());
+    b.apply(op_b);
+    b.apply(op_a);
+
+    // equal, but the values are held in a different order
+    assert_eq!(a, b);
+    assert_ne!(
+        bincode::serialize(&a).unwrap(),
+        bincode::serialize(&b).unwrap()
+    );
+    assert_eq!(a.digest(), b.digest());
+    let decoded: MVReg<u8, u8> = bincode::deserialize(&a.canonical_bytes()).unwrap();
+    assert_eq!(decoded, b);
+}
+
 #[test]
 fn test_op_commute_quickcheck1() {
     let mut reg1 = MVReg::new();
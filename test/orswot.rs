@@ -1,5 +1,4 @@
 extern crate crdts;
-extern crate rand;
 
 use crdts::{orswot::Op, *};
 use std::collections::HashSet;
@@ -31,6 +30,29 @@ fn build_opvec(op_prims: Vec<(u8, HashSet<u8>, u8, u64)>) -> OpVec {
 }
 
 quickcheck! {
+    fn prop_tracked_changes_match_changes_since(
+        op_prims1: Vec<(u8, HashSet<u8>, u8, u64)>,
+        op_prims2: Vec<(u8, HashSet<u8>, u8, u64)>
+    ) -> bool {
+        let mut a: Orswot<u8, u8> = Orswot::new();
+        let mut b: Orswot<u8, u8> = Orswot::new();
+        let ops2 = build_opvec(op_prims2).ops;
+        for (_, op) in build_opvec(op_prims1).ops {
+            a.apply(op);
+        }
+        for (_, op) in ops2[..ops2.len() / 2].iter().cloned() {
+            b.apply(op);
+        }
+
+        let mut merged = a.clone();
+        let mut tracked = merged.merge_with_changes(b) == merged.changes_since(&a);
+        for (_, op) in ops2 {
+            let before = merged.clone();
+            tracked &= merged.apply_with_changes(op) == merged.changes_since(&before);
+        }
+        tracked
+    }
+
     fn prop_merge_converges(op_prims: Vec<(u8, HashSet<u8>, u8, u64)>) -> bool {
         let ops = build_opvec(op_prims);
         // Different interleavings of ops applied to different
@@ -65,6 +87,48 @@ quickcheck! {
         }
         true
     }
+
+    fn prop_ops_and_merges_keep_state_valid(
+        op_prims1: Vec<(u8, HashSet<u8>, u8, u64)>,
+        op_prims2: Vec<(u8, HashSet<u8>, u8, u64)>
+    ) -> bool {
+        let mut a: Orswot<u8, u8> = Orswot::new();
+        let mut b: Orswot<u8, u8> = Orswot::new();
+        for (_, op) in build_opvec(op_prims1).ops {
+            a.apply(op);
+        }
+        for (_, op) in build_opvec(op_prims2).ops {
+            b.apply(op);
+        }
+        let a_valid = a.validate().is_ok();
+
+        a.merge(b);
+        a_valid && a.validate().is_ok()
+    }
+
+    fn prop_merge_diff_same_as_merge(
+        shared_prims: Vec<(u8, HashSet<u8>, u8, u64)>,
+        op_prims1: Vec<(u8, HashSet<u8>, u8, u64)>,
+        op_prims2: Vec<(u8, HashSet<u8>, u8, u64)>
+    ) -> bool {
+        let mut a: Orswot<u8, u8> = Orswot::new();
+        for (_, op) in build_opvec(shared_prims).ops {
+            a.apply(op);
+        }
+        let mut older = a.clone();
+        for (_, op) in build_opvec(op_prims1).ops {
+            a.apply(op);
+        }
+        for (_, op) in build_opvec(op_prims2).ops {
+            older.apply(op);
+        }
+
+        let mut merged_with_diff = older.clone();
+        merged_with_diff.merge(a.diff(&older));
+
+        older.merge(a);
+        merged_with_diff == older
+    }
 }
 
 /// When two orswots have identical clocks, but different elements,
@@ -235,6 +299,160 @@ fn test_dead_node_update() {
     assert_eq!(a.read().val, HashSet::new());
 }
 
+#[test]
+fn test_clear() {
+    let mut a = Orswot::new();
+    a.apply(a.add_all(vec![1, 2, 3], a.read().derive_add_ctx("A")));
+
+    let mut b = a.clone();
+    let clear_op = a.clear(a.read().derive_rm_ctx());
+    a.apply(clear_op.clone());
+    assert!(a.read().val.is_empty());
+
+    // an add concurrent with the clear is not removed by it
+    b.apply(b.add(4, b.read().derive_add_ctx("B")));
+    b.apply(clear_op);
+    assert_eq!(b.read().val, vec![4].into_iter().collect());
+
+    a.merge(b.clone());
+    assert_eq!(a, b);
+}
+
+#[test]
+fn test_diff_leaves_out_entries_older_has() {
+    let mut a: Orswot<u8, u8> = Orswot::new();
+    for actor in 0..5 {
+        for i in 0..10 {
+            a.apply(a.add(actor * 10 + i, a.read().derive_add_ctx(actor)));
+        }
+    }
+    let mut older = a.clone();
+    a.apply(a.add(100, a.read().derive_add_ctx(5)));
+    a.apply(a.rm(10, a.contains(&10).derive_rm_ctx()));
+
+    // only the entries holding dots of the actors that edited since
+    let diff = a.diff(&older);
+    assert_eq!(diff.read().val, (11..20).chain(once(100)).collect());
+    assert!(bincode::serialize(&diff).unwrap().len() < bincode::serialize(&a).unwrap().len());
+
+    older.merge(diff);
+    assert_eq!(older, a);
+}
+
+#[test]
+fn test_changes() {
+    let mut a: Orswot<u8, &str> = Orswot::new();
+    a.apply(a.add(0, a.read().derive_add_ctx("A")));
+    a.apply(a.add(1, a.read().derive_add_ctx("A")));
+
+    let mut b = a.clone();
+    b.apply(b.add(2, b.read().derive_add_ctx("B")));
+    b.apply(b.rm(0, b.read().derive_rm_ctx()));
+
+    let changes = a.merge_with_changes(b);
+    assert_eq!(changes.added, once(2).collect());
+    assert_eq!(changes.removed, once(0).collect());
+
+    // re-adding an existing member is not reported
+    let changes = a.apply_with_changes(a.add(1, a.read().derive_add_ctx("A")));
+    assert_eq!(changes.added, HashSet::new());
+    assert_eq!(changes.removed, HashSet::new());
+}
+
+#[test]
+fn test_undo() {
+    let mut a: Orswot<u8, &str> = Orswot::new();
+    a.apply(a.add(0, a.read().derive_add_ctx("A")));
+    let mut b = a.clone();
+
+    let before = a.read();
+    let add_op = a.add_all(vec![0, 1], a.read().derive_add_ctx("A"));
+    a.apply(add_op.clone());
+
+    // b concurrently adds 1 as well
+    b.apply(b.add(1, b.read().derive_add_ctx("B")));
+
+    a.apply(a.undo(&add_op, before, a.read().derive_add_ctx("A")));
+    assert_eq!(a.read().val, once(0).collect());
+
+    // the undo only removes what the add did, b's add survives
+    a.merge(b);
+    assert_eq!(a.read().val, vec![0, 1].into_iter().collect());
+
+    let before = a.read();
+    let rm_op = a.rm_all(vec![0, 1], a.read().derive_rm_ctx());
+    a.apply(rm_op.clone());
+    assert!(a.read().val.is_empty());
+
+    a.apply(a.undo(&rm_op, before, a.read().derive_add_ctx("A")));
+    assert_eq!(a.read().val, vec![0, 1].into_iter().collect());
+}
+
+#[test]
+fn test_try_apply() {
+    let mut a: Orswot<u8, &str> = Orswot::new();
+    let op = a.add(0, a.read().derive_add_ctx("A"));
+    assert_eq!(a.try_apply(op.clone()), Ok(()));
+
+    // replaying an op is fine
+    assert_eq!(a.try_apply(op), Ok(()));
+
+    // reusing a dot for different members is not
+    let conflicting_op = Op::Add {
+        dot: Dot::new("A", 1),
+        members: once(1).collect(),
+    };
+    assert_eq!(a.try_apply(conflicting_op), Err(Error::ConflictingMarker));
+
+    let gap_op = Op::Add {
+        dot: Dot::new("A", 3),
+        members: once(1).collect(),
+    };
+    assert_eq!(a.try_apply(gap_op), Err(Error::CausalGap));
+
+    let empty_rm_op = Op::Rm {
+        clock: VClock::new(),
+        members: once(0).collect(),
+    };
+    assert_eq!(a.try_apply(empty_rm_op), Err(Error::EmptyClock));
+
+    let ahead_rm_op = Op::Rm {
+        clock: Dot::new("B", 1).into(),
+        members: once(0).collect(),
+    };
+    assert_eq!(a.try_apply(ahead_rm_op), Err(Error::RmClockAhead));
+
+    // none of the rejected ops touched the set
+    assert_eq!(a.read().val, once(0).collect());
+    assert_eq!(a.read().add_clock, Dot::new("A", 1).into());
+}
+
+#[test]
+fn test_validate() {
+    let mut a: Orswot<u8, u8> = Orswot::new();
+    a.apply(a.add(0, a.read().derive_add_ctx(1)));
+    a.apply(Op::Rm {
+        clock: Dot::new(2, 1).into(),
+        members: once(0).collect(),
+    });
+    assert_eq!(a.validate(), Ok(()));
+
+    // the member clock has seen more than the set itself
+    let json = r#"{
+        "clock": {"dots": {"1": 1}},
+        "entries": {"0": {"dots": {"1": 2}}},
+        "deferred": {}
+    }"#;
+    let a: Orswot<u8, u8> = serde_json::from_str(json).unwrap();
+    assert_eq!(
+        a.validate(),
+        Err(Error::InvalidState(
+            "Orswot member clocks must be dominated by the Orswot clock"
+        ))
+    );
+    assert!(serde_json::from_str::<validated::Validated<Orswot<u8, u8>>>(json).is_err());
+}
+
 #[test]
 fn test_reset_remove_semantics() {
     let mut m1: Map<u8, Orswot<u8, &str>, &str> = Map::new();
@@ -0,0 +1,184 @@
+use std::collections::{BTreeSet, HashSet};
+use std::iter::FromIterator;
+
+use crdts::riak;
+use crdts::*;
+
+type TSet = Orswot<String, String>;
+type TMap = Map<String, TSet, String>;
+
+const VCLOCK: &[u8] = include_bytes!("fixtures/riak_dt/vclock.bin");
+const GCOUNTER: &[u8] = include_bytes!("fixtures/riak_dt/gcounter.bin");
+const PNCOUNTER: &[u8] = include_bytes!("fixtures/riak_dt/pncounter.bin");
+const LWWREG: &[u8] = include_bytes!("fixtures/riak_dt/lwwreg.bin");
+const ORSWOT_V1: &[u8] = include_bytes!("fixtures/riak_dt/orswot_v1.bin");
+const ORSWOT_V1_COMPRESSED: &[u8] = include_bytes!("fixtures/riak_dt/orswot_v1_compressed.bin");
+const ORSWOT_V2: &[u8] = include_bytes!("fixtures/riak_dt/orswot_v2.bin");
+const MAP_V1: &[u8] = include_bytes!("fixtures/riak_dt/map_v1.bin");
+const MAP_V2: &[u8] = include_bytes!("fixtures/riak_dt/map_v2.bin");
+
+fn actor(name: &str) -> String {
+    name.to_string()
+}
+
+fn clock(dots: &[(&str, u64)]) -> VClock<String> {
+    dots.iter()
+        .map(|(actor, counter)| Dot::new(actor.to_string(), *counter))
+        .collect()
+}
+
+fn members(names: &[&str]) -> HashSet<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+fn add(set: &mut TSet, member: &str, actor: &str) {
+    let op = set.add(
+        member.to_string(),
+        set.read().derive_add_ctx(actor.to_string()),
+    );
+    set.apply(op);
+}
+
+// {[{<<"a">>,2},{<<"b">>,1}],
+//  [{<<"apple">>,[{<<"a">>,1}]},{<<"pear">>,[{<<"a">>,2},{<<"b">>,1}]}],
+//  [{[{<<"b">>,3}],[<<"kiwi">>]}]}
+fn fixture_orswot() -> TSet {
+    let mut a = TSet::new();
+    add(&mut a, "apple", "a");
+    add(&mut a, "pear", "a");
+    let mut b = TSet::new();
+    add(&mut b, "pear", "b");
+    a.merge(b);
+    a.apply(orswot::Op::Rm {
+        clock: clock(&[("b", 3)]),
+        members: members(&["kiwi"]),
+    });
+    a
+}
+
+// the field {<<"fruit">>, riak_dt_orswot} updated concurrently by a and b,
+// with a deferred remove of {<<"veg">>, riak_dt_orswot}
+fn fixture_map() -> TMap {
+    let mut a = TMap::new();
+    for member in &["apple", "pear"] {
+        let op = a.update("fruit", a.len().derive_add_ctx(actor("a")), |set, ctx| {
+            set.add(member.to_string(), ctx)
+        });
+        a.apply(op);
+    }
+    let mut b = TMap::new();
+    let op = b.update("fruit", b.len().derive_add_ctx(actor("b")), |set, ctx| {
+        set.add("plum".to_string(), ctx)
+    });
+    b.apply(op);
+    a.merge(b);
+    a.apply(map::Op::Rm {
+        clock: clock(&[("b", 2)]),
+        keyset: BTreeSet::from_iter(vec!["veg".to_string()]),
+    });
+    a
+}
+
+#[test]
+fn test_vclock_fixture() {
+    let expected = clock(&[("a", 2), ("b", 1)]);
+    let term = riak::binary_to_term(VCLOCK).unwrap();
+    assert_eq!(
+        <VClock<String> as riak::Etf>::from_term(term).unwrap(),
+        expected
+    );
+    assert_eq!(riak::term_to_binary(&riak::Etf::to_term(&expected)), VCLOCK);
+}
+
+#[test]
+fn test_gcounter_fixture() {
+    let mut expected = GCounter::new();
+    for _ in 0..5 {
+        expected.apply(expected.inc(actor("a")));
+    }
+    for _ in 0..3 {
+        expected.apply(expected.inc(actor("b")));
+    }
+    assert_eq!(
+        riak::from_binary::<GCounter<String>>(GCOUNTER).unwrap(),
+        expected
+    );
+    assert_eq!(riak::to_binary(&expected), GCOUNTER);
+}
+
+#[test]
+fn test_pncounter_fixture() {
+    let mut expected = PNCounter::new();
+    for _ in 0..5 {
+        expected.apply(expected.inc(actor("a")));
+    }
+    for _ in 0..2 {
+        expected.apply(expected.dec(actor("a")));
+    }
+    for _ in 0..3 {
+        expected.apply(expected.dec(actor("b")));
+    }
+    let counter = riak::from_binary::<PNCounter<String>>(PNCOUNTER).unwrap();
+    assert_eq!(counter.read(), 0.into());
+    assert_eq!(counter, expected);
+    assert_eq!(riak::to_binary(&expected), PNCOUNTER);
+}
+
+#[test]
+fn test_lwwreg_fixture() {
+    let expected = LWWReg {
+        val: "hello".to_string(),
+        marker: 1_500_000_000_000_000u64,
+    };
+    assert_eq!(
+        riak::from_binary::<LWWReg<String, u64>>(LWWREG).unwrap(),
+        expected
+    );
+    assert_eq!(riak::to_binary(&expected), LWWREG);
+}
+
+#[test]
+fn test_orswot_fixtures() {
+    let expected = fixture_orswot();
+    assert_eq!(expected.read().val, members(&["apple", "pear"]));
+
+    for fixture in &[ORSWOT_V1, ORSWOT_V1_COMPRESSED, ORSWOT_V2] {
+        assert_eq!(riak::from_binary::<TSet>(fixture).unwrap(), expected);
+    }
+    // we write the orddict layout, uncompressed
+    assert_eq!(riak::to_binary(&expected), ORSWOT_V1);
+}
+
+#[test]
+fn test_map_fixtures() {
+    let expected = fixture_map();
+    let fruit = expected.get(&"fruit".to_string()).val.unwrap();
+    assert_eq!(fruit.read().val, members(&["apple", "pear", "plum"]));
+
+    assert_eq!(riak::from_binary::<TMap>(MAP_V1).unwrap(), expected);
+    assert_eq!(riak::from_binary::<TMap>(MAP_V2).unwrap(), expected);
+    assert_eq!(riak::to_binary(&expected), MAP_V1);
+}
+
+#[test]
+fn test_rejects_other_types_and_versions() {
+    match riak::from_binary::<TSet>(MAP_V1) {
+        Err(Error::WrongTag { expected, found }) => assert_eq!((expected, found), (75, 77)),
+        other => panic!("expected a wrong tag, got {:?}", other),
+    }
+
+    let mut future = ORSWOT_V1.to_vec();
+    future[1] = 3;
+    assert!(matches!(
+        riak::from_binary::<TSet>(&future),
+        Err(Error::UnsupportedVersion(3))
+    ));
+
+    // a map field of another type
+    let nested = riak::from_binary::<Map<String, TMap, String>>(MAP_V1);
+    assert!(matches!(nested, Err(Error::UnexpectedTerm(_))));
+
+    for len in 0..ORSWOT_V1.len() {
+        assert!(riak::from_binary::<TSet>(&ORSWOT_V1[..len]).is_err());
+    }
+}
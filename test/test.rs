@@ -6,4 +6,6 @@ extern crate crdts;
 mod map;
 mod mvreg;
 mod orswot;
+mod reference;
+mod riak;
 mod vclock;
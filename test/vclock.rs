@@ -1,6 +1,7 @@
 use crdts::*;
 
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 
 pub fn build_vclock(prims: Vec<u8>) -> VClock<u8> {
     let mut v = VClock::new();
@@ -99,6 +100,69 @@ quickcheck! {
             }
         }
     }
+
+    fn prop_serialized_like_a_btreemap(dots: Vec<(u8, u64)>) -> bool {
+        let clock: VClock<u8> = dots.iter().map(|(a, c)| Dot::new(*a, *c)).collect();
+        let tree: BTreeMap<u8, u64> = clock.iter().map(|dot| (*dot.actor, dot.counter)).collect();
+
+        bincode::serialize(&clock.dots).unwrap() == bincode::serialize(&tree).unwrap()
+            && serde_json::to_string(&clock.dots).unwrap() == serde_json::to_string(&tree).unwrap()
+            && bincode::deserialize::<VClock<u8>>(&bincode::serialize(&clock).unwrap()).unwrap() == clock
+    }
+
+    fn prop_dots_agree_with_a_btreemap(prims_a: Vec<u8>, prims_b: Vec<u8>) -> bool {
+        let a = build_vclock(prims_a);
+        let b = build_vclock(prims_b);
+        let tree = |clock: &VClock<u8>| -> BTreeMap<u8, u64> {
+            clock.iter().map(|dot| (*dot.actor, dot.counter)).collect()
+        };
+        let (ta, tb) = (tree(&a), tree(&b));
+
+        let mut merged = a.clone();
+        merged.merge(b.clone());
+        let mut tree_merged = ta.clone();
+        for (actor, counter) in tb.iter() {
+            let entry = tree_merged.entry(*actor).or_insert(0);
+            *entry = (*entry).max(*counter);
+        }
+
+        let intersection = VClock::intersection(&a, &b);
+        let tree_intersection: BTreeMap<u8, u64> = ta.iter()
+            .filter(|(actor, counter)| tb.get(actor) == Some(counter))
+            .map(|(actor, counter)| (*actor, *counter))
+            .collect();
+
+        tree(&merged) == tree_merged
+            && tree(&intersection) == tree_intersection
+            && a.dots.keys().eq(ta.keys())
+            && ta.iter().all(|(actor, counter)| a.dots[actor] == *counter)
+    }
+}
+
+#[test]
+fn test_try_apply() {
+    let mut v = VClock::new();
+    assert_eq!(v.try_apply(Dot::new("A", 2)), Err(Error::CausalGap));
+    assert_eq!(v, VClock::new());
+
+    assert_eq!(v.try_apply(Dot::new("A", 1)), Ok(()));
+    assert_eq!(v.try_apply(Dot::new("A", 1)), Ok(()));
+    assert_eq!(v.try_apply(Dot::new("A", 2)), Ok(()));
+    assert_eq!(v.get(&"A"), 2);
+}
+
+#[test]
+fn test_validate() {
+    let v: VClock<u8> = serde_json::from_str(r#"{"dots": {"1": 2}}"#).unwrap();
+    assert_eq!(v.validate(), Ok(()));
+
+    let v: VClock<u8> = serde_json::from_str(r#"{"dots": {"1": 0}}"#).unwrap();
+    assert_eq!(
+        v.validate(),
+        Err(Error::InvalidState(
+            "VClock counters must be greater than zero"
+        ))
+    );
 }
 
 #[test]